@@ -0,0 +1 @@
+// The registry's PocketIC tests live in tests/; this crate has no library code.
@@ -0,0 +1,386 @@
+// Shared PocketIC harness and client-side copies of the registry's Candid types
+#![allow(dead_code)]
+
+use candid::utils::{ArgumentDecoder, ArgumentEncoder};
+use candid::{decode_args, encode_args, CandidType, Deserialize, Principal};
+use pocket_ic::{PocketIc, WasmResult};
+use sha2::{Digest, Sha256};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::OnceLock;
+use std::time::Duration;
+
+const INITIAL_CYCLES: u128 = 2_000_000_000_000;
+pub const ONE_YEAR: Duration = Duration::from_secs(365 * 24 * 60 * 60);
+
+#[derive(CandidType, Deserialize, Default)]
+pub struct RegistryConfig {
+    pub mcp_endpoint_template: Option<String>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct CreateSeasonRequest {
+    pub min_letters: u64,
+    pub max_letters: Option<u64>,
+    pub total_allowed: u64,
+    pub price_icp: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RegistrationRequest {
+    pub domain_name: String,
+    pub administrator: Principal,
+    pub operator: Principal,
+    pub payment_block: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct RevealRequest {
+    pub domain_name: String,
+    pub salt: Vec<u8>,
+    pub administrator: Principal,
+    pub operator: Principal,
+    pub payment_block: u64,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AdminGiftRequest {
+    pub domain_name: String,
+    pub recipient: Principal,
+    pub administrator: Principal,
+    pub operator: Principal,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct AdminCreateDomainRequest {
+    pub domain_name: String,
+    pub recipient: Principal,
+    pub administrator: Principal,
+    pub operator: Principal,
+    pub recipient_address: String,
+}
+
+// Only the fields the tests look at; Candid skips the rest when decoding
+#[derive(CandidType, Deserialize, Debug)]
+pub struct RegistrationReceipt {
+    pub domain: String,
+    pub owner: Principal,
+    pub season_id: Option<u64>,
+    pub fee_e8s: u64,
+    pub sequence: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum DomainStatus {
+    Active,
+    Expired,
+    Reserved,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct DomainInfo {
+    pub name: String,
+    pub owner: Principal,
+    pub status: DomainStatus,
+    pub was_gifted: bool,
+    pub expiration_time: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct PendingTransfer {
+    pub domain_name: String,
+    pub from: Principal,
+    pub to: Principal,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum SeasonStatus {
+    Active,
+    Completed,
+    Deactivated,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub struct SeasonStats {
+    pub season_number: u64,
+    pub names_available: u64,
+    pub names_taken: u64,
+    pub price_icp: u64,
+    pub status: SeasonStatus,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum RegistryError {
+    NotAdmin,
+    Unauthorized,
+    InvalidName,
+    Reserved,
+    ShortNameRequiresApproval,
+    Unavailable,
+    WalletAlreadyOwns { domain: String },
+    DomainNotFound,
+    NoSeason,
+    SeasonFull,
+    SeasonNotFound,
+    SeasonNotActive,
+    ActiveSeasonExists,
+    AddressNotAuthorized { address: String },
+    PaymentInvalid { reason: String },
+    CanisterCreationFailed { reason: String },
+    InvalidInput { reason: String },
+    ContentTooLarge { max_bytes: u64 },
+    NotCanisterName,
+    InvalidEndpoint { reason: String },
+    EndpointHostDenied { host: String },
+    EndpointHostNotAllowed { host: String },
+    NoPendingChange,
+    InvalidManifest { reason: String },
+    LastAdmin,
+    RegistrationInProgress { domain: String },
+    ReservationExpired,
+    CommitmentNotFound,
+    CommitmentTooNew { reveal_after: u64 },
+    CommitmentExpired,
+    AuctionNotFound,
+    AuctionClosed,
+    BidTooLow { minimum_e8s: u64 },
+    ListingNotFound,
+    ListingPriceChanged { price_e8s: u64 },
+    SaleInProgress,
+    OfferNotFound,
+    TransferNotFound,
+    TransferExpired,
+    WalletLimitReached { limit: u64 },
+    SubdomainNotFound,
+    SubdomainFeeChanged { fee_e8s: u64 },
+    SubdomainNotRevocable,
+    PermissionDenied { permission: Permission },
+    ProposalRequired { threshold: u64 },
+    ProposalNotFound,
+    ProposalClosed,
+    AlreadyVoted,
+    MissingRole { role: AdminRole },
+    LedgerUnavailable,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum AdminRole {
+    SuperAdmin,
+    SeasonManager,
+    Moderator,
+    Treasurer,
+    Support,
+}
+
+#[derive(CandidType, Deserialize, Debug, PartialEq)]
+pub enum Permission {
+    Transfer,
+    Renew,
+    SetEndpoint,
+    EditMetadata,
+    UploadFiles,
+    ManageSubdomains,
+}
+
+pub fn user(n: u8) -> Principal {
+    Principal::self_authenticating([n])
+}
+
+fn repository_root() -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("..")
+}
+
+// The first release, which kept no state across upgrades
+pub const BASELINE_REVISION: &str = "fdeaef9";
+
+/// The registry wasm, taken from `REGISTRY_WASM` or built once per test binary.
+pub fn registry_wasm() -> Vec<u8> {
+    static WASM: OnceLock<Vec<u8>> = OnceLock::new();
+    WASM.get_or_init(|| {
+        if let Ok(path) = std::env::var("REGISTRY_WASM") {
+            return std::fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path, e));
+        }
+        let root = repository_root();
+        // A target dir of its own, so the nested build never waits on the outer cargo lock
+        build_wasm(&root, &root.join("target/registry-wasm"))
+    })
+    .clone()
+}
+
+/// The wasm of `BASELINE_REVISION`, taken from `BASELINE_WASM` or built from a checkout of it.
+pub fn baseline_wasm() -> Vec<u8> {
+    if let Ok(path) = std::env::var("BASELINE_WASM") {
+        return std::fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path, e));
+    }
+
+    // Outside the repository, where cargo would take the checkout for a workspace member
+    let source = std::env::temp_dir().join(format!("registry-{}", BASELINE_REVISION));
+    let _ = std::fs::remove_dir_all(&source);
+    std::fs::create_dir_all(&source).expect("cannot create the baseline checkout");
+    let archive = Command::new("git")
+        .arg("-C")
+        .arg(repository_root())
+        .args(["archive", "--format=tar", BASELINE_REVISION])
+        .output()
+        .expect("failed to run git");
+    assert!(archive.status.success(), "cannot export {}", BASELINE_REVISION);
+    let mut tar = Command::new("tar")
+        .arg("-x")
+        .arg("-C")
+        .arg(&source)
+        .stdin(Stdio::piped())
+        .spawn()
+        .expect("failed to run tar");
+    tar.stdin.take().unwrap().write_all(&archive.stdout).unwrap();
+    assert!(tar.wait().unwrap().success(), "cannot unpack {}", BASELINE_REVISION);
+
+    build_wasm(&source, &source.join("target"))
+}
+
+fn build_wasm(source: &Path, target_dir: &Path) -> Vec<u8> {
+    let status = Command::new(env!("CARGO"))
+        .args(["build", "--release", "--target", "wasm32-unknown-unknown", "--manifest-path"])
+        .arg(source.join("Cargo.toml"))
+        .env("CARGO_TARGET_DIR", target_dir)
+        .status()
+        .expect("failed to run cargo");
+    assert!(status.success(), "building the registry wasm in {} failed", source.display());
+
+    let path = target_dir.join("wasm32-unknown-unknown/release/registry.wasm");
+    std::fs::read(&path).unwrap_or_else(|e| panic!("cannot read {}: {}", path.display(), e))
+}
+
+pub struct Registry {
+    pub pic: PocketIc,
+    pub canister: Principal,
+    pub admin: Principal,
+}
+
+impl Registry {
+    pub fn install() -> Self {
+        let admin = user(1);
+        Self::install_wasm(registry_wasm(), encode_args((admin, None::<RegistryConfig>)).unwrap(), admin)
+    }
+
+    pub fn install_wasm(wasm: Vec<u8>, arg: Vec<u8>, admin: Principal) -> Self {
+        let pic = PocketIc::new();
+        let canister = pic.create_canister();
+        pic.add_cycles(canister, INITIAL_CYCLES);
+        pic.install_canister(canister, wasm, arg, None);
+
+        Registry { pic, canister, admin }
+    }
+
+    pub fn update<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(
+        &self,
+        sender: Principal,
+        method: &str,
+        args: A,
+    ) -> R {
+        let result = self.pic.update_call(self.canister, sender, method, encode_args(args).unwrap());
+        decode_reply(method, result)
+    }
+
+    pub fn query<A: ArgumentEncoder, R: for<'a> ArgumentDecoder<'a>>(&self, method: &str, args: A) -> R {
+        let result = self.pic.query_call(self.canister, Principal::anonymous(), method, encode_args(args).unwrap());
+        decode_reply(method, result)
+    }
+
+    pub fn upgrade(&self) {
+        self.upgrade_with(encode_args(()).unwrap());
+    }
+
+    pub fn upgrade_with(&self, arg: Vec<u8>) {
+        self.pic
+            .upgrade_canister(self.canister, registry_wasm(), arg, None)
+            .expect("upgrade failed");
+    }
+
+    pub fn advance_time(&self, duration: Duration) {
+        self.pic.advance_time(duration);
+        self.pic.tick();
+    }
+
+    pub fn create_season(&self, min_letters: u64, max_letters: Option<u64>, total_allowed: u64, price_icp: u64) -> u64 {
+        let request = CreateSeasonRequest { min_letters, max_letters, total_allowed, price_icp };
+        let (result,): (Result<u64, RegistryError>,) =
+            self.update(self.admin, "create_registration_season", (request,));
+        result.expect("season creation failed")
+    }
+
+    // Commits to the name, waits out the commitment delay and reveals it
+    pub fn register(&self, sender: Principal, domain_name: &str) -> Result<RegistrationReceipt, RegistryError> {
+        let salt = b"pepper".to_vec();
+        let mut hasher = Sha256::new();
+        hasher.update(domain_name.as_bytes());
+        hasher.update([0]);
+        hasher.update(sender.as_slice());
+        hasher.update(&salt);
+        let commitment = hasher.finalize().to_vec();
+        let (committed,): (Result<u64, RegistryError>,) = self.update(sender, "commit_registration", (commitment,));
+        committed.expect("commitment failed");
+        self.advance_time(Duration::from_secs(60));
+
+        let request = RevealRequest {
+            domain_name: domain_name.to_string(),
+            salt,
+            administrator: sender,
+            operator: sender,
+            payment_block: 1,
+        };
+        let (result,) = self.update(sender, "reveal_registration", (request,));
+        result
+    }
+
+    pub fn register_directly(&self, sender: Principal, domain_name: &str) -> Result<RegistrationReceipt, RegistryError> {
+        let request = RegistrationRequest {
+            domain_name: domain_name.to_string(),
+            administrator: sender,
+            operator: sender,
+            payment_block: 1,
+        };
+        let (result,) = self.update(sender, "register_domain", (request,));
+        result
+    }
+
+    pub fn gift(&self, recipient: Principal, domain_name: &str) -> Result<RegistrationReceipt, RegistryError> {
+        let request = AdminGiftRequest {
+            domain_name: domain_name.to_string(),
+            recipient,
+            administrator: recipient,
+            operator: recipient,
+        };
+        let (result,) = self.update(self.admin, "admin_gift_domain", (request,));
+        result
+    }
+
+    pub fn domain_info(&self, domain_name: &str) -> Option<DomainInfo> {
+        let (info,) = self.query("get_domain_info", (domain_name.to_string(),));
+        info
+    }
+
+    pub fn wallet_domain(&self, wallet: Principal) -> Option<String> {
+        let (domain,) = self.query("get_wallet_domain", (wallet,));
+        domain
+    }
+
+    pub fn season_stats(&self, season_id: u64) -> SeasonStats {
+        let (stats,): (Option<SeasonStats>,) = self.query("get_season_stats", (season_id,));
+        stats.expect("season not found")
+    }
+}
+
+fn decode_reply<R: for<'a> ArgumentDecoder<'a>>(
+    method: &str,
+    result: Result<WasmResult, pocket_ic::UserError>,
+) -> R {
+    match result {
+        Ok(WasmResult::Reply(bytes)) => {
+            decode_args(&bytes).unwrap_or_else(|e| panic!("cannot decode the reply of {}: {}", method, e))
+        }
+        Ok(WasmResult::Reject(message)) => panic!("{} was rejected: {}", method, message),
+        Err(error) => panic!("{} failed: {}", method, error),
+    }
+}
@@ -0,0 +1,87 @@
+// Expiry with time advancement and state persistence across upgrades, including from the baseline
+mod common;
+
+use candid::encode_args;
+use common::*;
+use std::time::Duration;
+
+#[test]
+fn expired_domains_can_be_registered_again() {
+    let registry = Registry::install();
+    registry.create_season(4, None, 10, 10);
+    registry.register(user(2), "shortlived").unwrap();
+    assert_eq!(registry.domain_info("shortlived").unwrap().status, DomainStatus::Active);
+
+    assert_eq!(registry.register(user(3), "shortlived").unwrap_err(), RegistryError::Unavailable);
+
+    registry.advance_time(ONE_YEAR + Duration::from_secs(60));
+    assert_eq!(registry.domain_info("shortlived").unwrap().status, DomainStatus::Expired);
+
+    let receipt = registry.register(user(3), "shortlived").unwrap();
+    assert_eq!(receipt.owner, user(3));
+    assert_eq!(registry.domain_info("shortlived").unwrap().status, DomainStatus::Active);
+}
+
+#[test]
+fn renewal_extends_expiration() {
+    let registry = Registry::install();
+    registry.create_season(4, None, 10, 10);
+    registry.register(user(2), "longlived").unwrap();
+    let expiration = registry.domain_info("longlived").unwrap().expiration_time;
+
+    let (result,): (Result<String, RegistryError>,) =
+        registry.update(user(2), "renew_domain", ("longlived".to_string(), 2u64));
+    result.unwrap();
+
+    registry.advance_time(ONE_YEAR + Duration::from_secs(60));
+    let info = registry.domain_info("longlived").unwrap();
+    assert_eq!(info.expiration_time, expiration + ONE_YEAR.as_nanos() as u64);
+    assert_eq!(info.status, DomainStatus::Active);
+}
+
+#[test]
+fn state_survives_upgrades() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, None, 10, 10);
+    let first = registry.register(user(2), "persisted").unwrap();
+    registry.gift(user(3), "giftedname").unwrap();
+
+    registry.upgrade();
+
+    assert_eq!(registry.domain_info("persisted").unwrap().owner, user(2));
+    assert!(registry.domain_info("giftedname").unwrap().was_gifted);
+    assert_eq!(registry.wallet_domain(user(2)), Some("persisted".to_string()));
+    assert_eq!(registry.season_stats(season).names_taken, 2);
+
+    let (is_admin,): (bool,) = registry.query("is_user_admin", (registry.admin,));
+    assert!(is_admin);
+
+    // Counters continue instead of restarting
+    let next = registry.register(user(4), "afterupgrade").unwrap();
+    assert_eq!(next.sequence, first.sequence + 2);
+    assert_eq!(
+        registry.register(user(2), "secondtry").unwrap_err(),
+        RegistryError::WalletAlreadyOwns { domain: "persisted".to_string() }
+    );
+}
+
+#[test]
+fn upgrades_from_the_baseline_start_from_the_upgrade_arguments() {
+    let admin = user(1);
+    let registry = Registry::install_wasm(baseline_wasm(), encode_args((admin,)).unwrap(), admin);
+
+    // The baseline saved nothing, so the upgrade sets the registry up as `init` would
+    registry.upgrade_with(encode_args((admin, None::<RegistryConfig>)).unwrap());
+
+    let (is_admin,): (bool,) = registry.query("is_user_admin", (admin,));
+    assert!(is_admin);
+    registry.create_season(4, None, 10, 10);
+    assert_eq!(registry.register(user(2), "firstafter").unwrap().owner, user(2));
+
+    // Later upgrades find the saved state and ignore their arguments
+    registry.upgrade_with(encode_args((user(9), None::<RegistryConfig>)).unwrap());
+    let (is_admin,): (bool,) = registry.query("is_user_admin", (user(9),));
+    assert!(!is_admin);
+    assert_eq!(registry.domain_info("firstafter").unwrap().owner, user(2));
+}
+
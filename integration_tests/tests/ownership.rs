@@ -0,0 +1,72 @@
+// One-domain-per-wallet rule and ownership transfers
+mod common;
+
+use common::*;
+
+#[test]
+fn wallet_can_register_only_one_domain() {
+    let registry = Registry::install();
+    registry.create_season(4, None, 10, 10);
+
+    registry.register(user(2), "firstname").unwrap();
+    assert_eq!(
+        registry.register(user(2), "secondname").unwrap_err(),
+        RegistryError::WalletAlreadyOwns { domain: "firstname".to_string() }
+    );
+    assert_eq!(registry.wallet_domain(user(2)), Some("firstname".to_string()));
+
+    // Gifts follow the same rule
+    assert_eq!(
+        registry.gift(user(2), "giftname").unwrap_err(),
+        RegistryError::WalletAlreadyOwns { domain: "firstname".to_string() }
+    );
+}
+
+#[test]
+fn transfer_moves_the_wallet_mapping() {
+    let registry = Registry::install();
+    registry.create_season(4, None, 10, 10);
+    registry.register(user(2), "movingname").unwrap();
+
+    let (result,): (Result<PendingTransfer, RegistryError>,) =
+        registry.update(user(2), "propose_domain_transfer", ("movingname".to_string(), user(3), None::<u64>));
+    result.unwrap();
+    // Nothing moves until the recipient accepts
+    assert_eq!(registry.domain_info("movingname").unwrap().owner, user(2));
+
+    let (result,): (Result<(), RegistryError>,) =
+        registry.update(user(3), "accept_domain_transfer", ("movingname".to_string(),));
+    result.unwrap();
+
+    assert_eq!(registry.domain_info("movingname").unwrap().owner, user(3));
+    assert_eq!(registry.wallet_domain(user(2)), None);
+    assert_eq!(registry.wallet_domain(user(3)), Some("movingname".to_string()));
+
+    // The previous owner may register again
+    registry.register(user(2), "freshname").unwrap();
+}
+
+#[test]
+fn transfer_requires_owner_and_a_free_recipient() {
+    let registry = Registry::install();
+    registry.create_season(4, None, 10, 10);
+    registry.register(user(2), "ownedname").unwrap();
+    registry.register(user(3), "othername").unwrap();
+
+    let (result,): (Result<PendingTransfer, RegistryError>,) =
+        registry.update(user(4), "propose_domain_transfer", ("ownedname".to_string(), user(4), None::<u64>));
+    assert_eq!(result.unwrap_err(), RegistryError::Unauthorized);
+
+    let (result,): (Result<PendingTransfer, RegistryError>,) =
+        registry.update(user(2), "propose_domain_transfer", ("missingname".to_string(), user(4), None::<u64>));
+    assert_eq!(result.unwrap_err(), RegistryError::DomainNotFound);
+
+    // The recipient's wallet limit is checked when they accept
+    let (result,): (Result<PendingTransfer, RegistryError>,) =
+        registry.update(user(2), "propose_domain_transfer", ("ownedname".to_string(), user(3), None::<u64>));
+    result.unwrap();
+    let (result,): (Result<(), RegistryError>,) =
+        registry.update(user(3), "accept_domain_transfer", ("ownedname".to_string(),));
+    assert_eq!(result, Err(RegistryError::WalletAlreadyOwns { domain: "othername".to_string() }));
+    assert_eq!(registry.domain_info("ownedname").unwrap().owner, user(2));
+}
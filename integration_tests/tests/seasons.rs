@@ -0,0 +1,138 @@
+// Season creation, validation, capacity and address-authorized admin creation
+mod common;
+
+use common::*;
+
+#[test]
+fn season_validation_rules() {
+    let registry = Registry::install();
+    let invalid = [
+        CreateSeasonRequest { min_letters: 0, max_letters: None, total_allowed: 10, price_icp: 5 },
+        CreateSeasonRequest { min_letters: 5, max_letters: Some(3), total_allowed: 10, price_icp: 5 },
+        CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed: 0, price_icp: 5 },
+        CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed: 10, price_icp: 0 },
+    ];
+    for request in invalid {
+        let (result,): (Result<u64, RegistryError>,) =
+            registry.update(registry.admin, "create_registration_season", (request,));
+        assert!(matches!(result, Err(RegistryError::InvalidInput { .. })), "{:?}", result);
+    }
+
+    let request = CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed: 10, price_icp: 5 };
+    let (result,): (Result<u64, RegistryError>,) =
+        registry.update(user(2), "create_registration_season", (request,));
+    assert_eq!(result, Err(RegistryError::NotAdmin));
+}
+
+#[test]
+fn only_one_active_season_at_a_time() {
+    let registry = Registry::install();
+    registry.create_season(4, Some(10), 4, 10);
+
+    let request = CreateSeasonRequest { min_letters: 1, max_letters: Some(3), total_allowed: 10, price_icp: 100 };
+    let (result,): (Result<u64, RegistryError>,) =
+        registry.update(registry.admin, "create_registration_season", (request,));
+    assert_eq!(result, Err(RegistryError::ActiveSeasonExists));
+}
+
+#[test]
+fn season_capacity_is_enforced_and_season_completes() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, Some(10), 2, 10);
+
+    let receipt = registry.register(user(2), "first").unwrap();
+    assert_eq!(receipt.season_id, Some(season));
+    assert_eq!(receipt.fee_e8s, 10 * 100_000_000);
+    registry.register(user(3), "second").unwrap();
+
+    let stats = registry.season_stats(season);
+    assert_eq!(stats.names_taken, 2);
+    assert_eq!(stats.status, SeasonStatus::Completed);
+
+    assert_eq!(registry.register(user(4), "third").unwrap_err(), RegistryError::NoSeason);
+
+    // A new season can start once the previous one is complete
+    let next = registry.create_season(4, Some(10), 2, 5);
+    assert_eq!(registry.register(user(4), "third").unwrap().season_id, Some(next));
+}
+
+#[test]
+fn deactivated_season_stops_registrations() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, None, 10, 10);
+    registry.register(user(2), "before").unwrap();
+
+    let (result,): (Result<(), RegistryError>,) = registry.update(registry.admin, "deactivate_season", (season,));
+    result.unwrap();
+
+    assert_eq!(registry.register(user(3), "after").unwrap_err(), RegistryError::NoSeason);
+    assert_eq!(registry.domain_info("before").unwrap().status, DomainStatus::Active);
+}
+
+#[test]
+fn admin_registrations_are_free_and_bypass_seasons() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, None, 10, 10);
+
+    let receipt = registry.register(registry.admin, "adminone").unwrap();
+    assert_eq!(receipt.fee_e8s, 0);
+    assert_eq!(receipt.season_id, None);
+    registry.register_directly(registry.admin, "admintwo").unwrap();
+    assert_eq!(registry.register_directly(user(2), "usertwo").unwrap_err(), RegistryError::NotAdmin);
+
+    assert!(registry.domain_info("adminone").unwrap().was_gifted);
+    assert_eq!(registry.season_stats(season).names_taken, 0);
+}
+
+#[test]
+fn gifts_consume_season_slots() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, None, 1, 10);
+
+    let receipt = registry.gift(user(2), "present").unwrap();
+    assert_eq!(receipt.owner, user(2));
+    assert_eq!(receipt.fee_e8s, 0);
+    assert_eq!(registry.season_stats(season).status, SeasonStatus::Completed);
+
+    assert_eq!(registry.gift(user(3), "another").unwrap_err(), RegistryError::NoSeason);
+}
+
+#[test]
+fn admin_creation_requires_an_authorized_address() {
+    let registry = Registry::install();
+    let season = registry.create_season(4, None, 2, 10);
+
+    let (result,): (Result<(), RegistryError>,) =
+        registry.update(registry.admin, "admin_add_address_to_season", (season, "alice123".to_string()));
+    result.unwrap();
+    let (authorized,): (bool,) = registry.query("is_address_authorized_for_current_season", ("alice123".to_string(),));
+    assert!(authorized);
+
+    let create = |domain_name: &str, recipient, address: &str| {
+        let request = AdminCreateDomainRequest {
+            domain_name: domain_name.to_string(),
+            recipient,
+            administrator: recipient,
+            operator: recipient,
+            recipient_address: address.to_string(),
+        };
+        let (result,): (Result<RegistrationReceipt, RegistryError>,) =
+            registry.update(registry.admin, "admin_create_domain_with_address", (request,));
+        result
+    };
+
+    assert_eq!(
+        create("stranger", user(3), "mallory").unwrap_err(),
+        RegistryError::AddressNotAuthorized { address: "mallory".to_string() }
+    );
+
+    let receipt = create("alicedomain", user(2), "alice123").unwrap();
+    assert_eq!(receipt.owner, user(2));
+    assert_eq!(registry.season_stats(season).names_taken, 1);
+
+    // Filling the season closes it to new addresses
+    registry.register(user(4), "filler").unwrap();
+    let (result,): (Result<(), RegistryError>,) =
+        registry.update(registry.admin, "admin_add_address_to_season", (season, "late".to_string()));
+    assert_eq!(result, Err(RegistryError::SeasonNotActive));
+}
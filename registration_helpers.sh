@@ -0,0 +1,32 @@
+#!/bin/bash
+
+# Registration helpers shared by the dfx test scripts. Source this file.
+#
+# Only admins can call register_domain. Everyone else commits to the name first and
+# reveals it once the commitment is a minute old.
+
+# Prints the commitment for a name, owner principal and salt as a Candid blob literal
+registration_commitment() {
+    python3 - "$1" "$2" "$3" <<'PY'
+import base64, hashlib, sys
+name, principal, salt = sys.argv[1:]
+text = principal.replace("-", "").upper()
+raw = base64.b32decode(text + "=" * (-len(text) % 8))[4:]
+digest = hashlib.sha256(name.encode() + b"\0" + raw + salt.encode()).digest()
+print("".join("\\%02x" % byte for byte in digest))
+PY
+}
+
+# Registers a name to the current identity: register_committed <name> <principal> <payment_block>
+register_committed() {
+    local name="$1" principal="$2" payment_block="$3" salt="dfx-test-salt"
+    dfx canister call registry commit_registration "(blob \"$(registration_commitment "$name" "$principal" "$salt")\")" > /dev/null
+    sleep 61
+    dfx canister call registry reveal_registration "(record {
+        domain_name = \"${name}\";
+        salt = blob \"${salt}\";
+        administrator = principal \"${principal}\";
+        operator = principal \"${principal}\";
+        payment_block = ${payment_block};
+    })"
+}
@@ -0,0 +1,128 @@
+// src/audit.rs - Append-only log of admin-gated calls, kept in stable memory
+use candid::{CandidType, Decode, Encode, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Log, Memory, Storable};
+use serde::{Deserialize, Serialize};
+use std::borrow::Cow;
+
+use crate::error::RegistryError;
+
+pub const MAX_AUDIT_PAGE_SIZE: u64 = 100;
+// Longer argument summaries are cut short so that no single entry grows the log by much
+const MAX_ARGS_CHARS: usize = 256;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum AuditResult {
+    Ok,
+    Err(String),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AuditEntry {
+    pub id: u64,
+    pub caller: Principal,
+    pub timestamp: u64,
+    pub method: String,
+    pub args: String, // Human-readable summary; large arguments such as wasm are hashed
+    pub result: AuditResult,
+}
+
+impl Storable for AuditEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        Cow::Owned(Encode!(self).expect("Failed to encode audit entry"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        Decode!(bytes.as_ref(), Self).expect("Failed to decode audit entry")
+    }
+
+    const BOUND: Bound = Bound::Unbounded;
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuditPage {
+    pub entries: Vec<AuditEntry>,
+    pub total: u64,
+    pub next_offset: Option<u64>, // None when there are no more results
+}
+
+pub struct AuditLog<M: Memory> {
+    log: Log<AuditEntry, M, M>,
+}
+
+impl<M: Memory> AuditLog<M> {
+    pub fn init(index_memory: M, data_memory: M) -> Self {
+        let log = Log::init(index_memory, data_memory)
+            .unwrap_or_else(|e| panic!("Failed to load the audit log: {:?}", e));
+        AuditLog { log }
+    }
+
+    /// Appends an entry for a finished call. Calls refused with `NotAdmin` came from outside
+    /// the admin roles and are not kept, so that anyone cannot flood the log.
+    pub fn record<T>(
+        &self,
+        caller: Principal,
+        timestamp: u64,
+        method: &str,
+        mut args: String,
+        result: &Result<T, RegistryError>,
+    ) {
+        let result = match result {
+            Ok(_) => AuditResult::Ok,
+            Err(RegistryError::NotAdmin) => return,
+            Err(e) => AuditResult::Err(e.to_string()),
+        };
+        if let Some((cut, _)) = args.char_indices().nth(MAX_ARGS_CHARS) {
+            args.truncate(cut);
+            args.push('…');
+        }
+        let entry = AuditEntry { id: self.log.len(), caller, timestamp, method: method.to_string(), args, result };
+        // Failing here traps the call, so that nothing changes without a record of it
+        self.log.append(&entry).expect("Failed to grow the audit log");
+    }
+
+    /// Newest first.
+    pub fn page(&self, offset: u64, limit: u64) -> AuditPage {
+        let total = self.log.len();
+        let limit = limit.min(MAX_AUDIT_PAGE_SIZE);
+        let entries: Vec<AuditEntry> = (0..total.saturating_sub(offset))
+            .rev()
+            .take(limit as usize)
+            .filter_map(|id| self.log.get(id))
+            .collect();
+        let end = offset.saturating_add(entries.len() as u64);
+        AuditPage { entries, total, next_offset: (end < total).then_some(end) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::AdminRole;
+    use ic_stable_structures::VectorMemory;
+
+    #[test]
+    fn entries_are_kept_newest_first_and_survive_reloading() {
+        let (index, data) = (VectorMemory::default(), VectorMemory::default());
+        let log = AuditLog::init(index.clone(), data.clone());
+        let caller = Principal::from_slice(&[1]);
+        log.record(caller, 10, "set_base_fee", "100".to_string(), &Ok::<(), RegistryError>(()));
+        log.record(caller, 11, "set_base_fee", "1".repeat(1000), &Err::<(), _>(RegistryError::MissingRole {
+            role: AdminRole::Treasurer,
+        }));
+        log.record(Principal::anonymous(), 12, "add_admin", String::new(), &Err::<(), _>(RegistryError::NotAdmin));
+
+        let log = AuditLog::init(index, data);
+        let page = log.page(0, 1);
+        assert_eq!(page.total, 2);
+        assert_eq!(page.entries[0].id, 1);
+        assert_eq!(page.entries[0].args.chars().count(), MAX_ARGS_CHARS + 1);
+        assert!(matches!(page.entries[0].result, AuditResult::Err(_)));
+        assert_eq!(page.next_offset, Some(1));
+
+        let rest = log.page(1, 10);
+        assert_eq!(rest.entries[0].result, AuditResult::Ok);
+        assert_eq!(rest.entries[0].args, "100");
+        assert_eq!(rest.next_offset, None);
+    }
+}
@@ -0,0 +1,140 @@
+// src/error.rs - Errors returned by the registry's update methods
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+use crate::registry::{AdminRole, Permission};
+
+/// Clients should match on the variant; the `Display` text is for humans and may change.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum RegistryError {
+    NotAdmin,
+    Unauthorized, // Caller has no role on the domain
+    InvalidName,
+    Reserved,
+    ShortNameRequiresApproval,
+    Unavailable,
+    WalletAlreadyOwns { domain: String },
+    DomainNotFound,
+    NoSeason,
+    SeasonFull,
+    SeasonNotFound,
+    SeasonNotActive,
+    ActiveSeasonExists,
+    AddressNotAuthorized { address: String },
+    PaymentInvalid { reason: String },
+    CanisterCreationFailed { reason: String },
+    InvalidInput { reason: String },
+    ContentTooLarge { max_bytes: u64 },
+    NotCanisterName,
+    InvalidEndpoint { reason: String },
+    EndpointHostDenied { host: String },
+    EndpointHostNotAllowed { host: String },
+    NoPendingChange,
+    InvalidManifest { reason: String },
+    LastAdmin,
+    RegistrationInProgress { domain: String }, // The name or the owner's wallet is locked by another call
+    ReservationExpired,
+    CommitmentNotFound,
+    CommitmentTooNew { reveal_after: u64 },
+    CommitmentExpired,
+    AuctionNotFound,
+    AuctionClosed,
+    BidTooLow { minimum_e8s: u64 },
+    ListingNotFound,
+    ListingPriceChanged { price_e8s: u64 },
+    SaleInProgress,
+    OfferNotFound,
+    TransferNotFound,
+    TransferExpired,
+    WalletLimitReached { limit: u64 },
+    SubdomainNotFound,
+    SubdomainFeeChanged { fee_e8s: u64 },
+    SubdomainNotRevocable,
+    PermissionDenied { permission: Permission }, // Caller has a role on the domain, but not this permission
+    ProposalRequired { threshold: u64 },
+    ProposalNotFound,
+    ProposalClosed,
+    AlreadyVoted,
+    MissingRole { role: AdminRole }, // Caller is an admin, but without this role
+    LedgerUnavailable, // No ledger is configured to hold the funds
+}
+
+impl RegistryError {
+    pub fn invalid_input(reason: impl Into<String>) -> Self {
+        RegistryError::InvalidInput { reason: reason.into() }
+    }
+}
+
+impl fmt::Display for RegistryError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            RegistryError::NotAdmin => write!(f, "Only admins can perform this action"),
+            RegistryError::Unauthorized => write!(f, "Unauthorized"),
+            RegistryError::InvalidName => write!(f, "Invalid domain name format"),
+            RegistryError::Reserved => write!(f, "Domain name is reserved"),
+            RegistryError::ShortNameRequiresApproval => write!(f, "Short domain names require approval"),
+            RegistryError::Unavailable => write!(f, "Domain name is not available"),
+            RegistryError::WalletAlreadyOwns { domain } => write!(f, "Wallet already owns domain: {}", domain),
+            RegistryError::DomainNotFound => write!(f, "Domain not found"),
+            RegistryError::NoSeason => write!(f, "No available registration season for this domain"),
+            RegistryError::SeasonFull => write!(f, "Registration season is full"),
+            RegistryError::SeasonNotFound => write!(f, "Season not found"),
+            RegistryError::SeasonNotActive => write!(f, "Season is no longer active"),
+            RegistryError::ActiveSeasonExists => write!(f, "There is already an active season"),
+            RegistryError::AddressNotAuthorized { address } => {
+                write!(f, "Address '{}' is not authorized for the current season", address)
+            }
+            RegistryError::PaymentInvalid { reason } => write!(f, "Payment rejected: {}", reason),
+            RegistryError::CanisterCreationFailed { reason } => write!(f, "Canister creation failed: {}", reason),
+            RegistryError::InvalidInput { reason } => write!(f, "{}", reason),
+            RegistryError::ContentTooLarge { max_bytes } => write!(f, "Content exceeds {} bytes", max_bytes),
+            RegistryError::NotCanisterName => write!(f, "Only canister names support this operation"),
+            RegistryError::InvalidEndpoint { reason } => write!(f, "{}", reason),
+            RegistryError::EndpointHostDenied { host } => write!(f, "Endpoint host '{}' is denied", host),
+            RegistryError::EndpointHostNotAllowed { host } => {
+                write!(f, "Endpoint host '{}' is not on the allowlist", host)
+            }
+            RegistryError::NoPendingChange => write!(f, "No pending endpoint change for this domain"),
+            RegistryError::InvalidManifest { reason } => write!(f, "{}", reason),
+            RegistryError::LastAdmin => write!(f, "Cannot remove the last admin"),
+            RegistryError::RegistrationInProgress { domain } => {
+                write!(f, "A registration of '{}' is already in progress", domain)
+            }
+            RegistryError::ReservationExpired => write!(f, "Registration took too long and was cancelled"),
+            RegistryError::CommitmentNotFound => write!(f, "No matching commitment from this caller"),
+            RegistryError::CommitmentTooNew { reveal_after } => {
+                write!(f, "Commitment cannot be revealed before {}", reveal_after)
+            }
+            RegistryError::CommitmentExpired => write!(f, "Commitment has expired"),
+            RegistryError::AuctionNotFound => write!(f, "Auction not found"),
+            RegistryError::AuctionClosed => write!(f, "Auction is not open for bids"),
+            RegistryError::BidTooLow { minimum_e8s } => write!(f, "Bid must be at least {} e8s", minimum_e8s),
+            RegistryError::ListingNotFound => write!(f, "Domain is not listed for sale"),
+            RegistryError::ListingPriceChanged { price_e8s } => {
+                write!(f, "Listing price changed to {} e8s", price_e8s)
+            }
+            RegistryError::SaleInProgress => write!(f, "Another purchase of this domain is in progress"),
+            RegistryError::OfferNotFound => write!(f, "Offer not found"),
+            RegistryError::TransferNotFound => write!(f, "No pending transfer of this domain to the caller"),
+            RegistryError::TransferExpired => write!(f, "The transfer proposal has expired"),
+            RegistryError::WalletLimitReached { limit } => {
+                write!(f, "Wallet cannot own more than {} domains here", limit)
+            }
+            RegistryError::SubdomainNotFound => write!(f, "Subdomain not found"),
+            RegistryError::SubdomainFeeChanged { fee_e8s } => write!(f, "Subdomain fee is now {} e8s", fee_e8s),
+            RegistryError::SubdomainNotRevocable => write!(f, "This subdomain cannot be revoked by its parent"),
+            RegistryError::PermissionDenied { permission } => {
+                write!(f, "Missing the {:?} permission on this domain", permission)
+            }
+            RegistryError::ProposalRequired { threshold } => {
+                write!(f, "This action needs a proposal approved by {} admins", threshold)
+            }
+            RegistryError::ProposalNotFound => write!(f, "Proposal not found"),
+            RegistryError::ProposalClosed => write!(f, "The proposal is no longer open"),
+            RegistryError::AlreadyVoted => write!(f, "You already voted on this proposal"),
+            RegistryError::MissingRole { role } => write!(f, "This action needs the {:?} role", role),
+            RegistryError::LedgerUnavailable => write!(f, "Payments are unavailable until a ledger is configured"),
+        }
+    }
+}
@@ -0,0 +1,315 @@
+// src/health.rs - MCP endpoint liveness probing
+use candid::CandidType;
+use ic_cdk::api::management_canister::http_request::{
+    http_request, CanisterHttpRequestArgument, HttpHeader, HttpMethod, HttpResponse, TransformContext,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+// Protocol version advertised in the probe's initialize request
+const PROBE_PROTOCOL_VERSION: &str = "2025-03-26";
+const PROBE_MAX_RESPONSE_BYTES: u64 = 16 * 1024;
+// Generous upper bound for a 16KB outcall on a 13-node subnet; unused cycles are refunded
+const PROBE_CYCLES: u128 = 1_000_000_000;
+// Slower answers are reported as Degraded
+const SLOW_PROBE_MS: u64 = 10_000;
+// Failed probes in a row before a previously healthy endpoint is marked Unreachable
+const UNREACHABLE_AFTER_FAILURES: u32 = 3;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum EndpointStatus {
+    Healthy,
+    Degraded,
+    Unreachable,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct EndpointHealth {
+    pub endpoint: String,
+    pub status: EndpointStatus,
+    pub last_checked: u64,
+    pub last_seen: Option<u64>, // Last time the endpoint answered the probe correctly
+    pub latency_ms: Option<u64>,
+    pub protocol_version: Option<String>,
+    pub http_status: Option<u16>,
+    pub consecutive_failures: u32,
+    pub error: Option<String>,
+}
+
+pub struct ProbeResponse {
+    pub status: u16,
+    pub body: Vec<u8>,
+}
+
+/// Transport used to reach an MCP endpoint. The canister uses HTTPS outcalls; tests
+/// substitute canned responses.
+pub trait EndpointProber {
+    async fn probe(&self, endpoint: &str) -> Result<ProbeResponse, String>;
+}
+
+/// Sends an MCP `initialize` request through the management canister. Responses are
+/// passed through `transform_method` so that all replicas agree on them.
+pub struct HttpsOutcallProber {
+    pub transform_method: &'static str,
+}
+
+impl EndpointProber for HttpsOutcallProber {
+    async fn probe(&self, endpoint: &str) -> Result<ProbeResponse, String> {
+        let request = CanisterHttpRequestArgument {
+            url: endpoint.to_string(),
+            max_response_bytes: Some(PROBE_MAX_RESPONSE_BYTES),
+            method: HttpMethod::POST,
+            headers: vec![
+                HttpHeader { name: "Content-Type".to_string(), value: "application/json".to_string() },
+                HttpHeader { name: "Accept".to_string(), value: "application/json, text/event-stream".to_string() },
+            ],
+            body: Some(initialize_request_body()),
+            transform: Some(TransformContext::from_name(self.transform_method.to_string(), vec![])),
+        };
+
+        let (response,) = http_request(request, PROBE_CYCLES)
+            .await
+            .map_err(|(code, message)| format!("{:?}: {}", code, message))?;
+
+        Ok(ProbeResponse {
+            status: u16::try_from(&response.status.0).unwrap_or(0),
+            body: response.body,
+        })
+    }
+}
+
+/// Probes one endpoint and folds the outcome into its health record.
+pub async fn check_endpoint<P: EndpointProber>(
+    prober: &P,
+    endpoint: &str,
+    previous: Option<&EndpointHealth>,
+    now: impl Fn() -> u64,
+) -> EndpointHealth {
+    let started = now();
+    let outcome = prober.probe(endpoint).await;
+    assess(endpoint, previous, outcome, started, now())
+}
+
+fn assess(
+    endpoint: &str,
+    previous: Option<&EndpointHealth>,
+    outcome: Result<ProbeResponse, String>,
+    started: u64,
+    finished: u64,
+) -> EndpointHealth {
+    // History only counts if it was recorded for the same URL
+    let previous = previous.filter(|p| p.endpoint == endpoint);
+    let last_seen = previous.and_then(|p| p.last_seen);
+    let failures = previous.map_or(0, |p| p.consecutive_failures) + 1;
+
+    let mut health = EndpointHealth {
+        endpoint: endpoint.to_string(),
+        status: EndpointStatus::Unreachable,
+        last_checked: finished,
+        last_seen,
+        latency_ms: None,
+        protocol_version: None,
+        http_status: None,
+        consecutive_failures: failures,
+        error: None,
+    };
+
+    match outcome {
+        Ok(response) => {
+            let latency_ms = finished.saturating_sub(started) / 1_000_000;
+            let protocol_version = protocol_version(&response.body);
+            let answered = (200..300).contains(&response.status) && protocol_version.is_some();
+
+            health.latency_ms = Some(latency_ms);
+            health.http_status = Some(response.status);
+            if answered {
+                health.status = if latency_ms <= SLOW_PROBE_MS {
+                    EndpointStatus::Healthy
+                } else {
+                    EndpointStatus::Degraded
+                };
+                health.last_seen = Some(finished);
+                health.protocol_version = protocol_version;
+                health.consecutive_failures = 0;
+            } else {
+                health.status = if failures >= UNREACHABLE_AFTER_FAILURES {
+                    EndpointStatus::Unreachable
+                } else {
+                    EndpointStatus::Degraded
+                };
+                health.error = Some(format!("HTTP {} without a valid initialize result", response.status));
+            }
+        }
+        Err(error) => {
+            health.status = if last_seen.is_some() && failures < UNREACHABLE_AFTER_FAILURES {
+                EndpointStatus::Degraded
+            } else {
+                EndpointStatus::Unreachable
+            };
+            health.error = Some(error);
+        }
+    }
+
+    health
+}
+
+fn initialize_request_body() -> Vec<u8> {
+    serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "initialize",
+        "params": {
+            "protocolVersion": PROBE_PROTOCOL_VERSION,
+            "capabilities": {},
+            "clientInfo": { "name": "icp-registry-probe", "version": env!("CARGO_PKG_VERSION") }
+        }
+    })
+    .to_string()
+    .into_bytes()
+}
+
+/// Protocol version from an initialize response: a JSON-RPC body, a server-sent event
+/// stream carrying one, or the canonical body produced by `canonical_probe_response`.
+fn protocol_version(body: &[u8]) -> Option<String> {
+    let body = std::str::from_utf8(body).ok()?;
+    let from_json = |json: &str| -> Option<String> {
+        let value: Value = serde_json::from_str(json.trim()).ok()?;
+        value.pointer("/result/protocolVersion")
+            .or_else(|| value.get("protocolVersion"))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+    };
+
+    from_json(body).or_else(|| {
+        body.lines()
+            .filter_map(|line| line.strip_prefix("data:"))
+            .find_map(from_json)
+    })
+}
+
+/// Reduces a probe response to its status and protocol version. Headers and the rest of
+/// the body (server info, session ids, timestamps) differ between replicas.
+pub fn canonical_probe_response(response: HttpResponse) -> HttpResponse {
+    let body = match protocol_version(&response.body) {
+        Some(version) => serde_json::json!({ "protocolVersion": version }).to_string().into_bytes(),
+        None => Vec::new(),
+    };
+
+    HttpResponse {
+        status: response.status,
+        headers: Vec::new(),
+        body,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use candid::Nat;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    struct MockProber {
+        response: Result<(u16, &'static str), &'static str>,
+    }
+
+    impl EndpointProber for MockProber {
+        async fn probe(&self, _endpoint: &str) -> Result<ProbeResponse, String> {
+            self.response
+                .map(|(status, body)| ProbeResponse { status, body: body.as_bytes().to_vec() })
+                .map_err(str::to_string)
+        }
+    }
+
+    // The mock never suspends, so a single poll completes the probe
+    fn run<F: Future>(future: F) -> F::Output {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("mock probe should complete immediately"),
+        }
+    }
+
+    // Each call advances the clock by `step_ms`
+    fn clock(step_ms: u64) -> impl Fn() -> u64 {
+        let now = Cell::new(1_000_000_000);
+        move || {
+            now.set(now.get() + step_ms * 1_000_000);
+            now.get()
+        }
+    }
+
+    const INITIALIZE_RESULT: &str =
+        r#"{"jsonrpc":"2.0","id":1,"result":{"protocolVersion":"2025-03-26","capabilities":{}}}"#;
+
+    #[test]
+    fn valid_initialize_response_is_healthy() {
+        let prober = MockProber { response: Ok((200, INITIALIZE_RESULT)) };
+        let health = run(check_endpoint(&prober, "https://mcp.example.com", None, clock(250)));
+
+        assert_eq!(health.status, EndpointStatus::Healthy);
+        assert_eq!(health.protocol_version.as_deref(), Some("2025-03-26"));
+        assert_eq!(health.latency_ms, Some(250));
+        assert_eq!(health.last_seen, Some(health.last_checked));
+        assert_eq!(health.consecutive_failures, 0);
+    }
+
+    #[test]
+    fn event_stream_responses_are_understood() {
+        let body = "event: message\ndata: {\"jsonrpc\":\"2.0\",\"id\":1,\"result\":{\"protocolVersion\":\"2025-06-18\"}}\n\n";
+        assert_eq!(protocol_version(body.as_bytes()).as_deref(), Some("2025-06-18"));
+    }
+
+    #[test]
+    fn slow_or_broken_answers_are_degraded() {
+        let slow = MockProber { response: Ok((200, INITIALIZE_RESULT)) };
+        let health = run(check_endpoint(&slow, "https://mcp.example.com", None, clock(SLOW_PROBE_MS + 1)));
+        assert_eq!(health.status, EndpointStatus::Degraded);
+
+        let broken = MockProber { response: Ok((502, "Bad Gateway")) };
+        let health = run(check_endpoint(&broken, "https://mcp.example.com", None, clock(100)));
+        assert_eq!(health.status, EndpointStatus::Degraded);
+        assert_eq!(health.http_status, Some(502));
+        assert_eq!(health.consecutive_failures, 1);
+    }
+
+    #[test]
+    fn transport_failures_degrade_then_mark_unreachable() {
+        let endpoint = "https://mcp.example.com";
+        let healthy = run(check_endpoint(
+            &MockProber { response: Ok((200, INITIALIZE_RESULT)) },
+            endpoint,
+            None,
+            clock(100),
+        ));
+
+        let down = MockProber { response: Err("SysTransient: connection refused") };
+        let mut health = healthy;
+        for _ in 1..UNREACHABLE_AFTER_FAILURES {
+            health = run(check_endpoint(&down, endpoint, Some(&health), clock(100)));
+            assert_eq!(health.status, EndpointStatus::Degraded);
+        }
+        health = run(check_endpoint(&down, endpoint, Some(&health), clock(100)));
+        assert_eq!(health.status, EndpointStatus::Unreachable);
+        assert!(health.last_seen.is_some());
+
+        // An endpoint that was never seen is unreachable straight away
+        let fresh = run(check_endpoint(&down, "https://other.example.com", Some(&health), clock(100)));
+        assert_eq!(fresh.status, EndpointStatus::Unreachable);
+        assert_eq!(fresh.consecutive_failures, 1);
+    }
+
+    #[test]
+    fn canonical_response_drops_replica_specific_data() {
+        let response = HttpResponse {
+            status: Nat::from(200u16),
+            headers: vec![HttpHeader { name: "Date".to_string(), value: "now".to_string() }],
+            body: INITIALIZE_RESULT.as_bytes().to_vec(),
+        };
+        let canonical = canonical_probe_response(response);
+
+        assert!(canonical.headers.is_empty());
+        assert_eq!(protocol_version(&canonical.body).as_deref(), Some("2025-03-26"));
+    }
+}
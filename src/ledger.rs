@@ -0,0 +1,175 @@
+// src/ledger.rs - ICP ledger wire types, account identifiers and payment checks
+use candid::{CandidType, Nat, Principal, Reserved};
+use serde::Deserialize;
+use sha2::{Digest, Sha224};
+
+pub type AccountIdentifier = Vec<u8>;
+
+/// Fee the ICP ledger charges the sender of each transfer.
+pub const TRANSFER_FEE_E8S: u64 = 10_000;
+
+/// Account identifier of a principal's default subaccount, as the ICP ledger records it.
+pub fn account_identifier(owner: Principal) -> AccountIdentifier {
+    let mut hasher = Sha224::new();
+    hasher.update(b"\x0Aaccount-id");
+    hasher.update(owner.as_slice());
+    hasher.update([0u8; 32]);
+    let hash = hasher.finalize();
+
+    let mut account = crc32fast::hash(&hash).to_be_bytes().to_vec();
+    account.extend_from_slice(&hash);
+    account
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy)]
+pub struct GetBlocksArgs {
+    pub start: u64,
+    pub length: u64,
+}
+
+#[derive(CandidType, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub struct Tokens {
+    pub e8s: u64,
+}
+
+// Only the transfer fields the registry checks; Candid skips the rest when decoding
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub enum Operation {
+    Mint(Reserved),
+    Burn(Reserved),
+    Transfer { from: AccountIdentifier, to: AccountIdentifier, amount: Tokens },
+    Approve(Reserved),
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Transaction {
+    pub operation: Option<Operation>,
+}
+
+#[derive(CandidType, Deserialize, Clone, Debug, PartialEq)]
+pub struct Block {
+    pub transaction: Transaction,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct BlockRange {
+    pub blocks: Vec<Block>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum QueryArchiveError {
+    BadFirstBlockIndex { requested_index: u64, first_valid_index: u64 },
+    Other { error_code: u64, error_message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum QueryArchiveResult {
+    Ok(BlockRange),
+    Err(QueryArchiveError),
+}
+
+candid::define_function!(pub QueryArchiveFn : (GetBlocksArgs) -> (QueryArchiveResult) query);
+
+#[derive(CandidType, Deserialize)]
+pub struct ArchivedBlocksRange {
+    pub start: u64,
+    pub length: u64,
+    pub callback: QueryArchiveFn,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct QueryBlocksResponse {
+    pub chain_length: u64,
+    pub blocks: Vec<Block>,
+    pub first_block_index: u64,
+    pub archived_blocks: Vec<ArchivedBlocksRange>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct Account {
+    pub owner: Principal,
+    pub subaccount: Option<Vec<u8>>,
+}
+
+#[derive(CandidType, Deserialize)]
+pub struct TransferArg {
+    pub from_subaccount: Option<Vec<u8>>,
+    pub to: Account,
+    pub amount: Nat,
+    pub fee: Option<Nat>,
+    pub memo: Option<Vec<u8>>,
+    pub created_at_time: Option<u64>,
+}
+
+#[derive(CandidType, Deserialize, Debug)]
+pub enum TransferError {
+    BadFee { expected_fee: Nat },
+    BadBurn { min_burn_amount: Nat },
+    InsufficientFunds { balance: Nat },
+    TooOld,
+    CreatedInFuture { ledger_time: u64 },
+    TemporarilyUnavailable,
+    Duplicate { duplicate_of: Nat },
+    GenericError { error_code: Nat, message: String },
+}
+
+#[derive(CandidType, Deserialize)]
+pub enum TransferResult {
+    Ok(Nat),
+    Err(TransferError),
+}
+
+/// Checks that `block` is a transfer of at least `amount_e8s` from `payer` to `recipient`.
+pub fn check_payment(block: &Block, payer: Principal, recipient: Principal, amount_e8s: u64) -> Result<(), String> {
+    let Some(Operation::Transfer { from, to, amount }) = &block.transaction.operation else {
+        return Err("Block is not a transfer".to_string());
+    };
+    if *from != account_identifier(payer) {
+        return Err("Block was not paid by the caller".to_string());
+    }
+    if *to != account_identifier(recipient) {
+        return Err("Block was not paid to the registry".to_string());
+    }
+    if amount.e8s < amount_e8s {
+        return Err(format!("Block pays {} e8s, {} e8s are due", amount.e8s, amount_e8s));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn transfer(from: Principal, to: Principal, e8s: u64) -> Block {
+        Block {
+            transaction: Transaction {
+                operation: Some(Operation::Transfer {
+                    from: account_identifier(from),
+                    to: account_identifier(to),
+                    amount: Tokens { e8s },
+                }),
+            },
+        }
+    }
+
+    #[test]
+    fn account_identifiers_match_the_ledger() {
+        let hex: String = account_identifier(Principal::anonymous()).iter().map(|b| format!("{:02x}", b)).collect();
+        assert_eq!(hex, "1c7a48ba6a562aa9eaa2481a9049cdf0433b9738c992d698c31d8abf89cadc79");
+    }
+
+    #[test]
+    fn payments_must_come_from_the_payer_to_the_registry_in_full() {
+        let payer = Principal::self_authenticating([1]);
+        let registry = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 1, 1, 1]);
+
+        assert_eq!(check_payment(&transfer(payer, registry, 100), payer, registry, 100), Ok(()));
+        assert_eq!(check_payment(&transfer(payer, registry, 150), payer, registry, 100), Ok(()));
+        assert!(check_payment(&transfer(payer, registry, 99), payer, registry, 100).is_err());
+        assert!(check_payment(&transfer(registry, registry, 100), payer, registry, 100).is_err());
+        assert!(check_payment(&transfer(payer, payer, 100), payer, registry, 100).is_err());
+
+        let mint = Block { transaction: Transaction { operation: Some(Operation::Mint(Reserved)) } };
+        assert!(check_payment(&mint, payer, registry, 100).is_err());
+    }
+}
@@ -1,13 +1,37 @@
 // src/lib.rs - Fixed for ic-cdk 0.13+
-use ic_cdk::api::management_canister::main::{
-    create_canister, CreateCanisterArgument, CanisterSettings
-};
-use ic_cdk::{caller, id, api::time};
+use ic_cdk::api::management_canister::http_request::{HttpResponse, TransformArgs};
+use ic_cdk::{caller, api::time};
 use ic_cdk_macros::*;
 use candid::{CandidType, Principal};
 use serde::{Deserialize, Serialize};
 use std::cell::RefCell;
-use std::collections::{HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+use std::rc::Rc;
+
+mod audit;
+mod endpoint_url;
+mod error;
+mod health;
+mod ledger;
+mod mcp;
+mod memory;
+mod registry;
+mod search;
+mod services;
+
+use audit::{AuditLog, AuditPage};
+use error::RegistryError;
+use health::{EndpointHealth, EndpointStatus, HttpsOutcallProber};
+use mcp::McpManifest;
+use registry::{
+    describe_wasm, is_valid_domain_name, season_stats, split_subdomain, AdminAction, AdminRole, Auction, AuctionInfo,
+    Bid, BuyDomainRequest, CreateAuctionRequest, Environment, Grantee, ListDomainRequest, Listing, MakeOfferRequest,
+    MintSubdomainRequest, Offer, Payout, PendingTransfer, Permission, PermissionMatrix, PlaceBidRequest, Proposal,
+    ProposalInfo, ProposalPage, Registry, ReservedImport, ReservedNamePage, RevealBidRequest, Sale, SalePage,
+    SubdomainPolicy, SubdomainRecord,
+};
+use search::TextIndex;
+use services::{IcClock, IcLedger, IcManagement};
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
 pub struct DomainRecord {
@@ -17,10 +41,18 @@ pub struct DomainRecord {
     pub canister_id: Principal,
     pub registration_time: u64,
     pub expiration_time: u64,
+    pub last_modified: u64,
     pub last_payment_block: u64,
     pub custom_mcp_endpoint: Option<String>,
     pub was_gifted: bool,
     pub registration_season_id: Option<u64>, // Track which season was used
+    pub target: Option<Principal>, // Identity or canister the name points to; None means the owner
+}
+
+impl DomainRecord {
+    pub fn target_address(&self) -> Principal {
+        self.target.unwrap_or(self.owner)
+    }
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
@@ -30,19 +62,32 @@ pub struct DomainInfo {
     pub administrator: Principal,
     pub operator: Principal,
     pub canister_id: Principal,
+    pub registration_time: u64,
     pub expiration_time: u64,
+    pub last_modified: u64,
     pub mcp_endpoint: String,
     pub status: DomainStatus,
     pub was_gifted: bool,
+    pub parent: Option<String>, // Set for subdomains
+    pub target: Principal,
+    pub address_type: AddressType,
 }
 
-#[derive(CandidType, Serialize, Deserialize, Clone)]
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq)]
 pub enum DomainStatus {
     Active,
     Expired,
     Reserved,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Default)]
+pub struct RegistryConfig {
+    // Default MCP endpoint, with {name} and {canister_id} placeholders
+    pub mcp_endpoint_template: Option<String>,
+    // ICP ledger that payments are checked on; see `set_ledger_canister`
+    pub ledger_canister: Option<Principal>,
+}
+
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct RegistrationRequest {
     pub domain_name: String,
@@ -51,7 +96,17 @@ pub struct RegistrationRequest {
     pub payment_block: u64,
 }
 
+/// Second step of a commit-reveal registration; the caller becomes the owner.
 #[derive(CandidType, Serialize, Deserialize)]
+pub struct RevealRequest {
+    pub domain_name: String,
+    pub salt: Vec<u8>,
+    pub administrator: Principal,
+    pub operator: Principal,
+    pub payment_block: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq)]
 pub struct AdminGiftRequest {
     pub domain_name: String,
     pub recipient: Principal,
@@ -68,6 +123,19 @@ pub struct AdminCreateDomainRequest {
     pub recipient_address: String, // The address that must exist in the season
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct RegistrationReceipt {
+    pub domain: String,
+    pub canister_id: Principal,
+    pub owner: Principal,
+    pub season_id: Option<u64>,
+    pub fee_e8s: u64,
+    pub payment_block: Option<u64>, // None for admin gifts and address-based creation
+    pub expiration_time: u64,
+    pub sequence: u64, // Increases by one with every registration
+    pub summary: Option<String>, // Human-readable form of the receipt
+}
+
 #[derive(CandidType, Serialize, Deserialize)]
 pub struct SearchResult {
     pub domain: String,
@@ -76,9 +144,81 @@ pub struct SearchResult {
     pub tools_count: u32,
     pub resources_count: u32,
     pub was_gifted: bool,
+    pub tags: Vec<String>,
+    pub endpoint_status: Option<EndpointStatus>, // None until the endpoint has been probed
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq)]
+pub enum EndpointChangeStatus {
+    Applied,
+    PendingApproval,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct PendingEndpointChange {
+    pub domain_name: String,
+    pub endpoint: String,
+    pub requested_by: Principal,
+    pub requested_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct EndpointHostPolicy {
+    pub denied_hosts: Vec<String>,
+    pub allowed_hosts: Vec<String>, // Empty means every host not denied is allowed
+    pub approval_required: bool,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct TextSearchHit {
+    pub domain: String,
+    pub score: f64,
+    pub snippet: String, // Matching words are wrapped in **
+    pub mcp_endpoint: String,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct TextSearchPage {
+    pub hits: Vec<TextSearchHit>,
+    pub total: u64,
+    pub next_offset: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq)]
+pub enum AddressType {
+    Identity,
+    Canister,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Default)]
+pub struct DomainMetadata {
+    pub display_name: Option<String>,
+    pub description: Option<String>,
+    pub website: Option<String>,
+    pub tags: Vec<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Default)]
+pub struct DomainFiles {
+    pub knowledge_file: Option<String>, // Markdown
+    pub did_file: Option<String>,       // Candid interface, canister names only
+}
+
+#[derive(CandidType, Serialize, Deserialize, Default)]
+pub struct SearchFilters {
+    pub season_id: Option<u64>,
+    pub owner: Option<Principal>,
+    pub address_type: Option<AddressType>,
+    pub has_metadata: Option<bool>,
+    pub has_knowledge_file: Option<bool>,
+    pub has_did_file: Option<bool>,
+    pub was_gifted: Option<bool>,
+    pub status: Option<DomainStatus>,
+    pub tags: Option<Vec<String>>, // Domain must carry every listed tag
+    pub hide_unreachable: Option<bool>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
 pub enum RegistrationMode {
     Open,
     WhitelistOnly,
@@ -103,14 +243,16 @@ pub struct RegistrationSeason {
     pub created_by: Principal,
     pub created_at: u64,
     pub status: SeasonStatus,
+    pub max_per_wallet: Option<u64>, // Domains one wallet may register this season
 }
 
-#[derive(CandidType, Serialize, Deserialize)]
+#[derive(CandidType, Serialize, Deserialize, Clone)]
 pub struct CreateSeasonRequest {
     pub min_letters: u64,
     pub max_letters: Option<u64>,
     pub total_allowed: u64,
     pub price_icp: u64,
+    pub max_per_wallet: Option<u64>,
 }
 
 #[derive(CandidType, Serialize, Deserialize, Clone)]
@@ -122,635 +264,1263 @@ pub struct SeasonStats {
     pub status: SeasonStatus,
 }
 
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct DomainPage {
+    pub domains: Vec<DomainInfo>,
+    pub total: u64,
+    pub next_offset: Option<u64>, // None when there are no more results
+}
+
+const MAX_PAGE_SIZE: u64 = 1_000;
+const DEFAULT_MCP_ENDPOINT_TEMPLATE: &str = "https://mcp.ctx.xyz/{name}";
+const MAX_SEARCH_RESULTS: usize = 100;
+const MAX_METADATA_BYTES: usize = 10 * 1024;
+const MAX_KNOWLEDGE_FILE_BYTES: usize = 100 * 1024;
+const MAX_DID_FILE_BYTES: usize = 50 * 1024;
+const MAX_TAGS: usize = 20;
+const MAX_TAG_LENGTH: usize = 32;
+const HEALTH_PROBE_INTERVAL_NS: u64 = 15 * 60 * 1_000_000_000; // 15 minutes
+const MAX_PROBES_PER_ROUND: usize = 50;
+
 thread_local! {
-    static DOMAINS: RefCell<HashMap<String, DomainRecord>> = RefCell::new(HashMap::new());
-    static RESERVED_NAMES: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
-    static ADMIN_PRINCIPALS: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
-    static SHORT_NAME_MODE: RefCell<RegistrationMode> = RefCell::new(RegistrationMode::WhitelistOnly);
-    static APPROVED_SHORT_USERS: RefCell<HashSet<Principal>> = RefCell::new(HashSet::new());
-    static BASE_FEE: RefCell<u64> = RefCell::new(100_000_000);
-    static DOMAIN_CANISTER_WASM: RefCell<Vec<u8>> = RefCell::new(Vec::new());
-    static REGISTRATION_SEASONS: RefCell<HashMap<u64, RegistrationSeason>> = RefCell::new(HashMap::new());
-    static NEXT_SEASON_ID: RefCell<u64> = RefCell::new(1);
-    static WALLET_TO_DOMAIN: RefCell<HashMap<Principal, String>> = RefCell::new(HashMap::new());
-    static SEASON_ADDRESSES: RefCell<HashMap<u64, HashSet<String>>> = RefCell::new(HashMap::new());
-}
-
-fn find_applicable_season(domain_name: &str) -> Option<(u64, RegistrationSeason)> {
-    let domain_length = domain_name.len() as u64;
-    
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .iter()
-            .filter(|(_, season)| {
-                matches!(season.status, SeasonStatus::Active) && 
-                domain_length >= season.min_letters &&
-                (season.max_letters.is_none() || domain_length <= season.max_letters.unwrap()) &&
-                season.registered_count < season.total_allowed
-            })
-            .min_by_key(|(_, season)| season.price_icp)
-            .map(|(id, season)| (*id, season.clone()))
-    })
+    // Domains, seasons and admin settings. Shared through an Rc so that async flows can
+    // hold on to it across awaits without keeping it borrowed.
+    static REGISTRY: Rc<RefCell<Registry>> = Rc::new(RefCell::new(Registry::default()));
+    static DOMAIN_METADATA: RefCell<HashMap<String, DomainMetadata>> = RefCell::new(HashMap::new());
+    static DOMAIN_FILES: RefCell<HashMap<String, DomainFiles>> = RefCell::new(HashMap::new());
+    static MCP_MANIFESTS: RefCell<HashMap<String, McpManifest>> = RefCell::new(HashMap::new());
+    // Lowercased MCP tool name -> domains whose manifest exposes it
+    static TOOL_INDEX: RefCell<HashMap<String, HashSet<String>>> = RefCell::new(HashMap::new());
+    // Metadata tag -> domains carrying it
+    static TAG_INDEX: RefCell<HashMap<String, HashSet<String>>> = RefCell::new(HashMap::new());
+    static MCP_ENDPOINT_TEMPLATE: RefCell<String> = RefCell::new(DEFAULT_MCP_ENDPOINT_TEMPLATE.to_string());
+    static DENIED_ENDPOINT_HOSTS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static ALLOWED_ENDPOINT_HOSTS: RefCell<HashSet<String>> = RefCell::new(HashSet::new());
+    static ENDPOINT_APPROVAL_REQUIRED: RefCell<bool> = const { RefCell::new(false) };
+    static PENDING_ENDPOINT_CHANGES: RefCell<HashMap<String, PendingEndpointChange>> = RefCell::new(HashMap::new());
+    static ENDPOINT_HEALTH: RefCell<HashMap<String, EndpointHealth>> = RefCell::new(HashMap::new());
+    // Last domain probed, so that each round continues where the previous one stopped
+    static PROBE_CURSOR: RefCell<Option<String>> = const { RefCell::new(None) };
+    // When the next round of health probes is due
+    static NEXT_HEALTH_PROBE: RefCell<u64> = const { RefCell::new(0) };
+    // Full-text index over metadata and knowledge files, keyed by domain name
+    static TEXT_INDEX: RefCell<TextIndex> = RefCell::new(TextIndex::default());
+    // Admin-gated calls, newest last. Lives in stable memory and is never rewritten
+    static AUDIT_LOG: AuditLog<memory::Memory> =
+        AuditLog::init(memory::get(memory::AUDIT_LOG_INDEX), memory::get(memory::AUDIT_LOG_DATA));
+}
+
+fn with_registry<R>(f: impl FnOnce(&Registry) -> R) -> R {
+    REGISTRY.with(|registry| f(&registry.borrow()))
+}
+
+fn with_registry_mut<R>(f: impl FnOnce(&mut Registry) -> R) -> R {
+    REGISTRY.with(|registry| f(&mut registry.borrow_mut()))
+}
+
+// The registration flows as they run inside the canister
+fn ic_environment() -> Environment<IcClock, IcLedger, IcManagement> {
+    Environment {
+        clock: IcClock,
+        ledger: IcLedger { ledger: with_registry(|registry| registry.ledger_canister) },
+        management: IcManagement,
+    }
 }
 
-fn calculate_registration_fee(domain_name: &str) -> Result<u64, String> {
-    match find_applicable_season(domain_name) {
-        Some((_, season)) => Ok(season.price_icp * 100_000_000), // Convert ICP to e8s
-        None => Err("No available registration season for this domain length".to_string()),
-    }
+fn is_admin(principal: Principal) -> bool {
+    with_registry(|registry| registry.is_admin(principal))
 }
 
-fn calculate_renewal_fee() -> u64 {
-    BASE_FEE.with(|base| *base.borrow())
+fn require_role(role: AdminRole) -> Result<(), RegistryError> {
+    with_registry(|registry| registry.require_role(caller(), role))
 }
 
-fn is_valid_domain_name(name: &str) -> bool {
-    if name.is_empty() || name.len() > 63 {
-        return false;
-    }
-    
-    if name.starts_with('-') || name.ends_with('-') {
-        return false;
-    }
-    
-    name.chars().all(|c| c.is_alphanumeric() || c == '-')
+// Runs an admin-gated call and records it in the audit log
+fn audited<T>(method: &str, args: String, call: impl FnOnce() -> Result<T, RegistryError>) -> Result<T, RegistryError> {
+    let result = call();
+    AUDIT_LOG.with(|log| log.record(caller(), time(), method, args, &result));
+    result
 }
 
-fn is_reserved_name(name: &str) -> bool {
-    RESERVED_NAMES.with(|reserved| {
-        reserved.borrow().contains(name)
-    })
+fn render_endpoint_template(template: &str, name: &str, canister_id: Principal) -> String {
+    template
+        .replace("{name}", name)
+        .replace("{canister_id}", &canister_id.to_text())
 }
 
-fn is_admin(caller: Principal) -> bool {
-    ADMIN_PRINCIPALS.with(|admins| {
-        admins.borrow().contains(&caller)
+// Custom endpoint if the owner set one, otherwise the configured gateway template
+fn effective_mcp_endpoint(name: &str, domain: &DomainRecord) -> String {
+    domain.custom_mcp_endpoint.clone().unwrap_or_else(|| {
+        MCP_ENDPOINT_TEMPLATE.with(|template| {
+            render_endpoint_template(&template.borrow(), name, domain.canister_id)
+        })
     })
 }
 
-fn can_register_short_domain(domain_name: &str, caller: Principal) -> bool {
-    let length = domain_name.len();
+fn build_domain_info(name: &str, domain: &DomainRecord, current_time: u64) -> DomainInfo {
+    let status = if domain.expiration_time > current_time {
+        DomainStatus::Active
+    } else {
+        DomainStatus::Expired
+    };
     
-    if length >= 5 {
-        return true;
+    DomainInfo {
+        name: name.to_string(),
+        owner: domain.owner,
+        administrator: domain.administrator,
+        operator: domain.operator,
+        canister_id: domain.canister_id,
+        registration_time: domain.registration_time,
+        expiration_time: domain.expiration_time,
+        last_modified: domain.last_modified,
+        mcp_endpoint: effective_mcp_endpoint(name, domain),
+        status,
+        was_gifted: domain.was_gifted,
+        parent: None,
+        target: domain.target_address(),
+        address_type: address_type_of(&domain.target_address()),
     }
-    
-    if is_admin(caller) {
-        return true;
+}
+
+// Subdomains share their parent's expiry, and resolve to its canister unless they have a target
+fn build_subdomain_info(name: &str, subdomain: &SubdomainRecord, parent: &DomainRecord) -> DomainInfo {
+    let canister_id = subdomain.target.unwrap_or(parent.canister_id);
+    let mcp_endpoint = subdomain.custom_mcp_endpoint.clone().unwrap_or_else(|| {
+        MCP_ENDPOINT_TEMPLATE.with(|template| render_endpoint_template(&template.borrow(), name, canister_id))
+    });
+    DomainInfo {
+        name: name.to_string(),
+        owner: subdomain.owner,
+        administrator: subdomain.owner,
+        operator: subdomain.operator,
+        canister_id,
+        registration_time: subdomain.created_at,
+        expiration_time: parent.expiration_time,
+        last_modified: subdomain.last_modified,
+        mcp_endpoint,
+        status: DomainStatus::Active,
+        was_gifted: false,
+        parent: Some(subdomain.parent.clone()),
+        target: canister_id,
+        address_type: address_type_of(&canister_id),
     }
-    
-    SHORT_NAME_MODE.with(|mode| {
-        match *mode.borrow() {
-            RegistrationMode::Open => true,
-            RegistrationMode::WhitelistOnly => {
-                APPROVED_SHORT_USERS.with(|users| users.borrow().contains(&caller))
-            },
-            RegistrationMode::Closed => false,
-        }
-    })
 }
 
-fn wallet_already_has_domain(wallet: Principal) -> Option<String> {
-    WALLET_TO_DOMAIN.with(|mapping| {
-        mapping.borrow().get(&wallet).cloned()
-    })
+fn subdomain_info(registry: &Registry, name: &str, current_time: u64) -> Option<DomainInfo> {
+    let subdomain = registry.live_subdomain(name, current_time)?;
+    Some(build_subdomain_info(name, subdomain, registry.domains.get(&subdomain.parent)?))
 }
 
-fn has_active_season() -> bool {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .any(|season| matches!(season.status, SeasonStatus::Active))
-    })
+fn validate_endpoint_template(template: &str) -> Result<(), RegistryError> {
+    if !template.contains("{name}") && !template.contains("{canister_id}") {
+        return Err(RegistryError::invalid_input("Endpoint template must contain {name} or {canister_id}"));
+    }
+    // Render with a sample domain so the result goes through the usual URL checks
+    let sample = render_endpoint_template(template, "example", Principal::management_canister());
+    endpoint_url::normalize_endpoint(&sample)
+        .map(|_| ())
+        .map_err(|e| RegistryError::InvalidEndpoint {
+            reason: format!("Endpoint template does not produce a valid URL: {}", e),
+        })
+}
+
+fn address_type_of(principal: &Principal) -> AddressType {
+    // Canister ids are opaque principals (class byte 0x01); the management canister is empty
+    match principal.as_slice().last() {
+        None | Some(0x01) => AddressType::Canister,
+        Some(_) => AddressType::Identity,
+    }
 }
 
-fn complete_season_if_full(season_id: u64) {
-    REGISTRATION_SEASONS.with(|seasons| {
-        if let Some(season) = seasons.borrow_mut().get_mut(&season_id) {
-            if season.registered_count >= season.total_allowed {
-                season.status = SeasonStatus::Completed;
+// Everything full-text search sees for a domain: metadata fields, tags, MCP tools and the knowledge file
+fn searchable_text(domain_name: &str) -> String {
+    let mut parts: Vec<String> = Vec::new();
+    
+    DOMAIN_METADATA.with(|metadata| {
+        if let Some(metadata) = metadata.borrow().get(domain_name) {
+            parts.extend(metadata.display_name.clone());
+            parts.extend(metadata.description.clone());
+            parts.extend(metadata.tags.iter().cloned());
+        }
+    });
+    MCP_MANIFESTS.with(|manifests| {
+        if let Some(manifest) = manifests.borrow().get(domain_name) {
+            for tool in &manifest.tools {
+                parts.push(tool.name.clone());
+                parts.extend(tool.description.clone());
             }
         }
     });
+    DOMAIN_FILES.with(|files| {
+        if let Some(knowledge) = files.borrow().get(domain_name).and_then(|f| f.knowledge_file.clone()) {
+            parts.push(knowledge);
+        }
+    });
+    
+    parts.join("\n")
 }
 
-fn is_address_in_season(season_id: u64, address: &str) -> bool {
-    SEASON_ADDRESSES.with(|addresses| {
-        addresses.borrow()
-            .get(&season_id)
-            .map(|addr_set| addr_set.contains(address))
-            .unwrap_or(false)
-    })
+fn reindex_domain_text(domain_name: &str) {
+    let text = searchable_text(domain_name);
+    TEXT_INDEX.with(|index| {
+        index.borrow_mut().index_document(domain_name, &text);
+    });
 }
 
-fn add_address_to_season(season_id: u64, address: String) -> Result<(), String> {
-    // Check if season exists and is active
-    let season_status = REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .get(&season_id)
-            .map(|s| s.status.clone())
+// Drops metadata and files left behind by a previous (expired) registration of the name
+fn clear_domain_content(domain_name: &str) {
+    store_domain_metadata(domain_name, None);
+    DOMAIN_FILES.with(|files| {
+        files.borrow_mut().remove(domain_name);
     });
-    
-    match season_status {
-        Some(SeasonStatus::Active) => {
-            SEASON_ADDRESSES.with(|addresses| {
-                addresses.borrow_mut()
-                    .entry(season_id)
-                    .or_insert_with(HashSet::new)
-                    .insert(address);
-            });
-            Ok(())
-        }
-        Some(SeasonStatus::Completed) => Err("Cannot add address to completed season".to_string()),
-        Some(SeasonStatus::Deactivated) => Err("Cannot add address to deactivated season".to_string()),
-        None => Err("Season not found".to_string()),
-    }
+    store_mcp_manifest(domain_name, None);
+    ENDPOINT_HEALTH.with(|health| {
+        health.borrow_mut().remove(domain_name);
+    });
+    PENDING_ENDPOINT_CHANGES.with(|pending| {
+        pending.borrow_mut().remove(domain_name);
+    });
+    reindex_domain_text(domain_name);
 }
 
-#[init]
-fn init(admin: Principal) {
-    ADMIN_PRINCIPALS.with(|admins| {
-        admins.borrow_mut().insert(admin);
+fn store_domain_metadata(domain_name: &str, metadata: Option<DomainMetadata>) {
+    let tags = |metadata: &DomainMetadata| metadata.tags.clone();
+    
+    let previous = DOMAIN_METADATA.with(|stored| {
+        let mut stored = stored.borrow_mut();
+        match metadata.clone() {
+            Some(metadata) => stored.insert(domain_name.to_string(), metadata),
+            None => stored.remove(domain_name),
+        }
     });
     
-    RESERVED_NAMES.with(|reserved| {
-        let mut names = reserved.borrow_mut();
-        names.insert("icp".to_string());
-        names.insert("api".to_string());
-        names.insert("www".to_string());
-        names.insert("admin".to_string());
-        names.insert("root".to_string());
-        names.insert("system".to_string());
-        names.insert("registry".to_string());
-        names.insert("canister".to_string());
-        names.insert("dfinity".to_string());
-        names.insert("ic".to_string());
+    TAG_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tag in previous.as_ref().map(tags).unwrap_or_default() {
+            if let Some(domains) = index.get_mut(&tag) {
+                domains.remove(domain_name);
+                if domains.is_empty() {
+                    index.remove(&tag);
+                }
+            }
+        }
+        for tag in metadata.as_ref().map(tags).unwrap_or_default() {
+            index.entry(tag).or_default().insert(domain_name.to_string());
+        }
     });
 }
 
-#[update]
-async fn register_domain(request: RegistrationRequest) -> Result<String, String> {
-    let caller = caller();
-    
-    if !is_valid_domain_name(&request.domain_name) {
-        return Err("Invalid domain name format".to_string());
-    }
-    
-    if is_reserved_name(&request.domain_name) {
-        return Err("Domain name is reserved".to_string());
-    }
-    
-    if !can_register_short_domain(&request.domain_name, caller) {
-        return Err("Short domain names require approval".to_string());
-    }
+fn store_mcp_manifest(domain_name: &str, manifest: Option<McpManifest>) {
+    let tool_names = |manifest: &McpManifest| -> Vec<String> {
+        manifest.tools.iter().map(|tool| tool.name.to_lowercase()).collect()
+    };
     
-    // Check if wallet already has a domain (unless admin)
-    let is_admin_caller = is_admin(caller);
-    if !is_admin_caller {
-        if let Some(existing_domain) = wallet_already_has_domain(caller) {
-            return Err(format!("Wallet already owns domain: {}", existing_domain));
+    let previous = MCP_MANIFESTS.with(|manifests| {
+        let mut manifests = manifests.borrow_mut();
+        match manifest.clone() {
+            Some(manifest) => manifests.insert(domain_name.to_string(), manifest),
+            None => manifests.remove(domain_name),
         }
-    }
+    });
     
-    let is_available = DOMAINS.with(|domains| {
-        match domains.borrow().get(&request.domain_name) {
-            Some(domain) => {
-                let current_time = time();
-                domain.expiration_time < current_time
+    TOOL_INDEX.with(|index| {
+        let mut index = index.borrow_mut();
+        for tool in previous.as_ref().map(tool_names).unwrap_or_default() {
+            if let Some(domains) = index.get_mut(&tool) {
+                domains.remove(domain_name);
+                if domains.is_empty() {
+                    index.remove(&tool);
+                }
             }
-            None => true,
         }
+        for tool in manifest.as_ref().map(tool_names).unwrap_or_default() {
+            index.entry(tool).or_default().insert(domain_name.to_string());
+        }
+    });
+}
+
+fn to_search_result(name: &str, domain: &DomainRecord, metadata: Option<&DomainMetadata>) -> SearchResult {
+    let mcp_endpoint = effective_mcp_endpoint(name, domain);
+    let description = metadata
+        .and_then(|m| m.description.clone())
+        .unwrap_or_else(|| format!("Domain {} - {}", name, if domain.was_gifted { "Admin Gift" } else { "Registered" }));
+    let (tools_count, resources_count) = MCP_MANIFESTS.with(|manifests| {
+        manifests.borrow()
+            .get(name)
+            .map(|m| (m.tools.len() as u32, m.resources.len() as u32))
+            .unwrap_or((0, 0))
+    });
+    // Health recorded for a previous endpoint URL says nothing about the current one
+    let endpoint_status = ENDPOINT_HEALTH.with(|health| {
+        health.borrow()
+            .get(name)
+            .filter(|h| h.endpoint == mcp_endpoint)
+            .map(|h| h.status)
     });
     
-    if !is_available {
-        return Err("Domain name is not available".to_string());
+    SearchResult {
+        domain: name.to_string(),
+        description,
+        mcp_endpoint,
+        tools_count,
+        resources_count,
+        was_gifted: domain.was_gifted,
+        tags: metadata.map(|m| m.tags.clone()).unwrap_or_default(),
+        endpoint_status,
     }
-    
-    // Find applicable season and calculate fee
-    let (season_id, required_fee) = if is_admin_caller {
-        (None, 0u64) // Admins register for free
-    } else {
-        match find_applicable_season(&request.domain_name) {
-            Some((id, season)) => (Some(id), season.price_icp * 100_000_000),
-            None => return Err("No available registration season for this domain length".to_string()),
+}
+
+// Orders results with the same match rank: live endpoints first, dead ones last
+fn endpoint_penalty(status: Option<EndpointStatus>) -> u8 {
+    match status {
+        Some(EndpointStatus::Healthy) => 0,
+        None => 1,
+        Some(EndpointStatus::Degraded) => 2,
+        Some(EndpointStatus::Unreachable) => 3,
+    }
+}
+
+fn normalize_metadata(mut metadata: DomainMetadata) -> Result<DomainMetadata, RegistryError> {
+    let mut tags: Vec<String> = Vec::new();
+    for tag in metadata.tags.iter().map(|tag| tag.trim().to_lowercase()) {
+        if tag.is_empty() || tag.len() > MAX_TAG_LENGTH {
+            return Err(RegistryError::invalid_input(format!("Tags must be between 1 and {} characters", MAX_TAG_LENGTH)));
+        }
+        if !tags.contains(&tag) {
+            tags.push(tag);
         }
-    };
-    
-    // Update season registration count if not admin
-    if let Some(id) = season_id {
-        REGISTRATION_SEASONS.with(|seasons| {
-            if let Some(season) = seasons.borrow_mut().get_mut(&id) {
-                if season.registered_count >= season.total_allowed {
-                    return Err("Registration season is full".to_string());
-                }
-                season.registered_count += 1;
-                Ok(())
-            } else {
-                Err("Season not found".to_string())
-            }
-        })?;
+    }
+    if tags.len() > MAX_TAGS {
+        return Err(RegistryError::invalid_input(format!("At most {} tags are allowed", MAX_TAGS)));
+    }
+    metadata.tags = tags;
+    
+    let size = [&metadata.display_name, &metadata.description, &metadata.website]
+        .into_iter()
+        .flatten()
+        .map(|field| field.len())
+        .chain(metadata.tags.iter().map(|tag| tag.len()))
+        .sum::<usize>();
+    if size > MAX_METADATA_BYTES {
+        return Err(RegistryError::ContentTooLarge { max_bytes: MAX_METADATA_BYTES as u64 });
     }
     
-    // Create new canister for this domain
-    let canister_id = create_domain_canister(
-        &request.domain_name, 
-        caller, 
-        request.administrator, 
-        request.operator
-    ).await.map_err(|e| {
-        // Rollback season count on canister creation failure
-        if let Some(id) = season_id {
-            REGISTRATION_SEASONS.with(|seasons| {
-                if let Some(season) = seasons.borrow_mut().get_mut(&id) {
-                    season.registered_count -= 1;
-                }
-            });
+    if let Some(ref website) = metadata.website {
+        if !website.starts_with("https://") {
+            return Err(RegistryError::invalid_input("Website must use HTTPS"));
         }
-        e
-    })?;
+    }
     
-    let domain_record = DomainRecord {
-        owner: caller,
-        administrator: request.administrator,
-        operator: request.operator,
-        canister_id,
-        registration_time: time(),
-        expiration_time: time() + (365 * 24 * 60 * 60 * 1_000_000_000), // 1 year
-        last_payment_block: request.payment_block,
-        custom_mcp_endpoint: None,
-        was_gifted: is_admin_caller,
-        registration_season_id: season_id,
-    };
+    Ok(metadata)
+}
+
+// Validates a custom endpoint against the URL rules and the admin host lists
+fn check_custom_endpoint(endpoint: &str) -> Result<String, RegistryError> {
+    let url = endpoint_url::normalize_endpoint(endpoint)
+        .map_err(|reason| RegistryError::InvalidEndpoint { reason })?;
+    let host = url.host_str().unwrap_or_default().trim_end_matches('.');
     
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(request.domain_name.clone(), domain_record);
+    let denied = DENIED_ENDPOINT_HOSTS.with(|hosts| {
+        hosts.borrow().iter().any(|rule| endpoint_url::host_matches(host, rule))
     });
+    if denied {
+        return Err(RegistryError::EndpointHostDenied { host: host.to_string() });
+    }
     
-    // Add wallet-to-domain mapping
-    WALLET_TO_DOMAIN.with(|mapping| {
-        mapping.borrow_mut().insert(caller, request.domain_name.clone());
+    let allowed = ALLOWED_ENDPOINT_HOSTS.with(|hosts| {
+        let hosts = hosts.borrow();
+        hosts.is_empty() || hosts.iter().any(|rule| endpoint_url::host_matches(host, rule))
     });
-    
-    // Check if season is now complete and mark it as such
-    if let Some(id) = season_id {
-        complete_season_if_full(id);
+    if !allowed {
+        return Err(RegistryError::EndpointHostNotAllowed { host: host.to_string() });
     }
     
-    let fee_info = if is_admin_caller {
-        "Free (admin registration)".to_string()
+    Ok(url.to_string())
+}
+
+// Loads a domain on which the caller holds `permission`
+fn authorized_domain(domain_name: &str, caller: Principal, permission: Permission) -> Result<DomainRecord, RegistryError> {
+    with_registry(|registry| registry.authorize(domain_name, caller, permission).cloned())
+}
+
+fn touch_domain(domain_name: &str) {
+    with_registry_mut(|registry| registry.touch(domain_name, time()));
+}
+
+fn matches_filters(
+    record: &DomainRecord,
+    metadata: Option<&DomainMetadata>,
+    files: Option<&DomainFiles>,
+    filters: &SearchFilters,
+    current_time: u64,
+) -> bool {
+    let status = if record.expiration_time > current_time {
+        DomainStatus::Active
     } else {
-        format!("Fee: {} ICP", required_fee as f64 / 100_000_000.0)
+        DomainStatus::Expired
     };
     
-    Ok(format!(
-        "Domain {} registered successfully with canister {}. {}",
-        request.domain_name, canister_id, fee_info
-    ))
+    filters.season_id.is_none_or(|id| record.registration_season_id == Some(id))
+        && filters.owner.is_none_or(|owner| record.owner == owner)
+        && filters.address_type.as_ref().is_none_or(|kind| address_type_of(&record.target_address()) == *kind)
+        && filters.has_metadata.is_none_or(|wanted| metadata.is_some() == wanted)
+        && filters.has_knowledge_file.is_none_or(|wanted| {
+            files.is_some_and(|f| f.knowledge_file.is_some()) == wanted
+        })
+        && filters.has_did_file.is_none_or(|wanted| {
+            files.is_some_and(|f| f.did_file.is_some()) == wanted
+        })
+        && filters.was_gifted.is_none_or(|wanted| record.was_gifted == wanted)
+        && filters.status.as_ref().is_none_or(|wanted| *wanted == status)
+        && filters.tags.as_ref().is_none_or(|tags| {
+            tags.iter().all(|tag| {
+                metadata.is_some_and(|m| m.tags.contains(&tag.trim().to_lowercase()))
+            })
+        })
 }
 
-#[update]
-async fn admin_gift_domain(request: AdminGiftRequest) -> Result<String, String> {
-    let caller = caller();
-    
-    if !is_admin(caller) {
-        return Err("Only admins can gift domains".to_string());
+// Lower is better: exact name, prefix, substring or exact tag, then description token match
+fn match_rank(
+    name: &str,
+    pattern: &str,
+    metadata: Option<&DomainMetadata>,
+    token_matches: &HashSet<String>,
+) -> Option<u8> {
+    if pattern.is_empty() {
+        return Some(0);
     }
     
-    if !is_valid_domain_name(&request.domain_name) {
-        return Err("Invalid domain name format".to_string());
+    let lowered = name.to_lowercase();
+    if lowered == pattern {
+        Some(0)
+    } else if lowered.starts_with(pattern) {
+        Some(1)
+    } else if lowered.contains(pattern) || metadata.is_some_and(|m| m.tags.iter().any(|tag| tag == pattern)) {
+        Some(2)
+    } else if token_matches.contains(name) {
+        Some(3)
+    } else {
+        None
     }
-    
-    if is_reserved_name(&request.domain_name) {
-        return Err("Domain name is reserved".to_string());
+}
+
+// Names a search has to look at: index hits for the pattern, narrowed to the owner's names
+fn search_candidates(registry: &Registry, pattern: &str, token_matches: &HashSet<String>, owner: Option<Principal>) -> Vec<String> {
+    let owned = owner.map(|owner| registry.wallet_domains(owner).into_iter().collect::<BTreeSet<String>>());
+    if pattern.is_empty() {
+        return owned.unwrap_or_else(|| registry.domains.keys().cloned().collect()).into_iter().collect();
     }
     
-    let is_available = DOMAINS.with(|domains| {
-        match domains.borrow().get(&request.domain_name) {
-            Some(domain) => {
-                let current_time = time();
-                domain.expiration_time < current_time
-            }
-            None => true,
-        }
+    let mut names = registry.names_containing(pattern);
+    TAG_INDEX.with(|index| {
+        names.extend(index.borrow().get(pattern).into_iter().flatten().cloned());
     });
-    
-    if !is_available {
-        return Err("Domain name is not available".to_string());
-    }
-    
-    // Check if recipient already has a domain
-    if let Some(existing_domain) = wallet_already_has_domain(request.recipient) {
-        return Err(format!("Recipient already owns domain: {}", existing_domain));
-    }
-    
-    // Find active season and check if it can accommodate this domain
-    let active_season_info = REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .find(|season| matches!(season.status, SeasonStatus::Active))
-            .map(|s| (s.season_id, s.registered_count, s.total_allowed))
+    names.extend(token_matches.iter().cloned());
+    names.into_iter()
+        .filter(|name| owned.as_ref().is_none_or(|owned| owned.contains(name)))
+        .collect()
+}
+
+// Ranked search results; `limit` caps how many are returned
+fn search_domains(pattern: &str, filters: &SearchFilters, limit: Option<usize>) -> Vec<SearchResult> {
+    let pattern = pattern.trim().to_lowercase();
+    let token_matches = TEXT_INDEX.with(|index| index.borrow().documents_matching_all(&pattern));
+    let current_time = time();
+    
+    let mut ranked: Vec<(u8, SearchResult)> = with_registry(|registry| {
+        DOMAIN_METADATA.with(|metadata| {
+            DOMAIN_FILES.with(|files| {
+                let metadata = metadata.borrow();
+                let files = files.borrow();
+                search_candidates(registry, &pattern, &token_matches, filters.owner)
+                    .into_iter()
+                    .filter_map(|name| {
+                        let domain = registry.domains.get(&name)?;
+                        let domain_metadata = metadata.get(&name);
+                        if !matches_filters(domain, domain_metadata, files.get(&name), filters, current_time) {
+                            return None;
+                        }
+                        let rank = match_rank(&name, &pattern, domain_metadata, &token_matches)?;
+                        let result = to_search_result(&name, domain, domain_metadata);
+                        if filters.hide_unreachable == Some(true)
+                            && result.endpoint_status == Some(EndpointStatus::Unreachable)
+                        {
+                            return None;
+                        }
+                        Some((rank, result))
+                    })
+                    .collect()
+            })
+        })
     });
     
-    let season_id = match active_season_info {
-        Some((id, registered, total)) => {
-            if registered >= total {
-                return Err("Cannot gift domain: active season is full".to_string());
-            }
-            Some(id)
-        }
-        None => return Err("Cannot gift domain: no active season available".to_string()),
-    };
-    
-    let canister_id = create_domain_canister(
-        &request.domain_name,
-        request.recipient,
-        request.administrator,
-        request.operator,
-    ).await?;
-    
-    let domain_record = DomainRecord {
-        owner: request.recipient,
-        administrator: request.administrator,
-        operator: request.operator,
-        canister_id,
-        registration_time: time(),
-        expiration_time: time() + (365 * 24 * 60 * 60 * 1_000_000_000), // 1 year
-        last_payment_block: 0,
-        custom_mcp_endpoint: None,
-        was_gifted: true,
-        registration_season_id: season_id, // Track season usage even for gifts
-    };
-    
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(request.domain_name.clone(), domain_record);
+    ranked.sort_by(|(a_rank, a), (b_rank, b)| {
+        a_rank.cmp(b_rank)
+            .then_with(|| endpoint_penalty(a.endpoint_status).cmp(&endpoint_penalty(b.endpoint_status)))
+            .then_with(|| a.domain.cmp(&b.domain))
+    });
+    ranked.into_iter()
+        .take(limit.unwrap_or(usize::MAX))
+        .map(|(_, result)| result)
+        .collect()
+}
+
+// Next batch of active domains to probe, continuing after the last domain probed
+fn next_probe_targets() -> Vec<(String, String)> {
+    let current_time = time();
+    let mut targets: Vec<(String, String)> = with_registry(|registry| {
+        registry.domains
+            .iter()
+            .filter(|(_, domain)| domain.expiration_time > current_time)
+            .map(|(name, domain)| (name.clone(), effective_mcp_endpoint(name, domain)))
+            .collect()
     });
+    targets.sort();
     
-    // Add wallet-to-domain mapping for recipient
-    WALLET_TO_DOMAIN.with(|mapping| {
-        mapping.borrow_mut().insert(request.recipient, request.domain_name.clone());
+    let start = PROBE_CURSOR.with(|cursor| {
+        cursor.borrow()
+            .as_ref()
+            .map_or(0, |last| targets.partition_point(|(name, _)| name <= last))
+    });
+    let batch: Vec<(String, String)> = targets.iter()
+        .cycle()
+        .skip(start)
+        .take(MAX_PROBES_PER_ROUND.min(targets.len()))
+        .cloned()
+        .collect();
+    
+    PROBE_CURSOR.with(|cursor| {
+        *cursor.borrow_mut() = batch.last().map(|(name, _)| name.clone());
     });
+    batch
+}
+
+async fn run_health_probes() -> u64 {
+    let prober = HttpsOutcallProber { transform_method: "transform_mcp_probe" };
+    let targets = next_probe_targets();
     
-    // Increment season count for gifts (they still consume season slots)
-    if let Some(id) = season_id {
-        REGISTRATION_SEASONS.with(|seasons| {
-            if let Some(season) = seasons.borrow_mut().get_mut(&id) {
-                season.registered_count += 1;
-            }
+    for (name, endpoint) in &targets {
+        let previous = ENDPOINT_HEALTH.with(|health| health.borrow().get(name).cloned());
+        let health = health::check_endpoint(&prober, endpoint, previous.as_ref(), time).await;
+        ENDPOINT_HEALTH.with(|stored| {
+            stored.borrow_mut().insert(name.clone(), health);
         });
-        complete_season_if_full(id);
     }
     
-    Ok(format!(
-        "Domain {} gifted to {} with canister {} (FREE admin gift)",
-        request.domain_name, request.recipient, canister_id
-    ))
+    targets.len() as u64
 }
 
-#[update]
-async fn admin_create_domain_with_address(request: AdminCreateDomainRequest) -> Result<String, String> {
-    let caller = caller();
-    
-    if !is_admin(caller) {
-        return Err("Only admins can create domains with addresses".to_string());
-    }
-    
-    if !is_valid_domain_name(&request.domain_name) {
-        return Err("Invalid domain name format".to_string());
+fn schedule_health_probes() {
+    NEXT_HEALTH_PROBE.with(|next| *next.borrow_mut() = time() + HEALTH_PROBE_INTERVAL_NS);
+    schedule_timer();
+}
+
+// The single global timer serves both the health probes and auction settlement
+fn schedule_timer() {
+    let next_probe = NEXT_HEALTH_PROBE.with(|next| *next.borrow());
+    let next_settlement = with_registry(|registry| registry.next_auction_end());
+    ic_cdk::api::set_global_timer(next_settlement.map_or(next_probe, |end| end.min(next_probe)));
+}
+
+async fn settle_auctions() {
+    let state = REGISTRY.with(Rc::clone);
+    for receipt in ic_environment().settle_auctions(&state).await {
+        clear_domain_content(&receipt.domain);
     }
-    
-    if is_reserved_name(&request.domain_name) {
-        return Err("Domain name is reserved".to_string());
+}
+
+#[export_name = "canister_global_timer"]
+extern "C" fn canister_global_timer() {
+    ic_cdk::setup();
+    with_registry_mut(|registry| {
+        registry.expire_market_entries(time());
+        registry.expire_transfers(time());
+        registry.expire_subdomains(time());
+        registry.expire_proposals(time());
+    });
+    let probes_due = NEXT_HEALTH_PROBE.with(|next| *next.borrow() <= time());
+    if probes_due {
+        NEXT_HEALTH_PROBE.with(|next| *next.borrow_mut() = time() + HEALTH_PROBE_INTERVAL_NS);
     }
-    
-    let is_available = DOMAINS.with(|domains| {
-        match domains.borrow().get(&request.domain_name) {
-            Some(domain) => {
-                let current_time = time();
-                domain.expiration_time < current_time
-            }
-            None => true,
+    // Due auctions move into settlement before the first await, so the timer set below
+    // already skips them
+    ic_cdk::spawn(async move {
+        settle_auctions().await;
+        if probes_due {
+            run_health_probes().await;
         }
     });
+    schedule_timer();
+}
+
+#[init]
+fn init(admin: Principal, config: Option<RegistryConfig>) {
+    initialize(admin, config);
+    schedule_health_probes();
+}
+
+fn initialize(admin: Principal, config: Option<RegistryConfig>) {
+    let config = config.unwrap_or_default();
+    with_registry_mut(|registry| {
+        *registry = Registry::new(admin);
+        registry.ledger_canister = config.ledger_canister;
+    });
     
-    if !is_available {
-        return Err("Domain name is not available".to_string());
+    if let Some(template) = config.mcp_endpoint_template {
+        if let Err(e) = validate_endpoint_template(&template) {
+            ic_cdk::trap(&e.to_string());
+        }
+        MCP_ENDPOINT_TEMPLATE.with(|current| {
+            *current.borrow_mut() = template;
+        });
     }
+}
+
+// Everything that lives on the heap and must survive an upgrade. Search indexes are
+// derived data and are rebuilt in post_upgrade instead.
+#[derive(CandidType, Deserialize)]
+struct UpgradeState {
+    domains: HashMap<String, DomainRecord>,
+    reserved_names: HashSet<String>,
+    admins: HashSet<Principal>,
+    short_name_mode: RegistrationMode,
+    approved_short_users: HashSet<Principal>,
+    base_fee: u64,
+    ledger_canister: Option<Principal>,
+    domain_canister_wasm: Vec<u8>,
+    seasons: HashMap<u64, RegistrationSeason>,
+    next_season_id: u64,
+    next_registration_sequence: u64,
+    season_addresses: HashMap<u64, HashSet<String>>,
+    domain_metadata: HashMap<String, DomainMetadata>,
+    domain_files: HashMap<String, DomainFiles>,
+    mcp_manifests: HashMap<String, McpManifest>,
+    mcp_endpoint_template: String,
+    denied_endpoint_hosts: HashSet<String>,
+    allowed_endpoint_hosts: HashSet<String>,
+    endpoint_approval_required: bool,
+    pending_endpoint_changes: HashMap<String, PendingEndpointChange>,
+    endpoint_health: HashMap<String, EndpointHealth>,
+    registration_commitments: HashMap<Principal, HashMap<Vec<u8>, u64>>,
+    auctions: HashMap<u64, Auction>,
+    next_auction_id: u64,
+    used_payment_blocks: HashSet<u64>,
+    payouts: Vec<Payout>,
+    next_payout_id: u64,
+    listings: HashMap<String, Listing>,
+    offers: HashMap<u64, Offer>,
+    next_offer_id: u64,
+    sales: Vec<Sale>,
+    market_fee_bps: u64,
+    collected_fees_e8s: u64,
+    pending_transfers: HashMap<String, PendingTransfer>,
+    max_domains_per_wallet: u64,
+    wallet_limit_overrides: HashMap<Principal, u64>,
+    subdomains: HashMap<String, SubdomainRecord>,
+    subdomain_policies: HashMap<String, SubdomainPolicy>,
+    domain_permissions: HashMap<String, PermissionMatrix>,
+    governance_threshold: u64,
+    proposals: BTreeMap<u64, Proposal>,
+    next_proposal_id: u64,
+    approved_gifts: Vec<AdminGiftRequest>,
+    admin_roles: HashMap<Principal, BTreeSet<AdminRole>>,
+    audit_log_public: bool,
+    reserved_patterns: BTreeSet<String>,
+}
+
+#[pre_upgrade]
+fn pre_upgrade() {
+    let mut registry = REGISTRY.with(|registry| registry.take());
+    // Calls suspended at an await do not resume after the upgrade
+    registry.release_all_reservations();
+    let state = UpgradeState {
+        domains: registry.domains,
+        reserved_names: registry.reserved_names,
+        admins: registry.admins,
+        short_name_mode: registry.short_name_mode,
+        approved_short_users: registry.approved_short_users,
+        base_fee: registry.base_fee,
+        ledger_canister: registry.ledger_canister,
+        domain_canister_wasm: registry.domain_canister_wasm,
+        seasons: registry.seasons,
+        next_season_id: registry.next_season_id,
+        next_registration_sequence: registry.next_registration_sequence,
+        season_addresses: registry.season_addresses,
+        domain_metadata: DOMAIN_METADATA.with(|m| m.take()),
+        domain_files: DOMAIN_FILES.with(|f| f.take()),
+        mcp_manifests: MCP_MANIFESTS.with(|m| m.take()),
+        mcp_endpoint_template: MCP_ENDPOINT_TEMPLATE.with(|t| t.take()),
+        denied_endpoint_hosts: DENIED_ENDPOINT_HOSTS.with(|h| h.take()),
+        allowed_endpoint_hosts: ALLOWED_ENDPOINT_HOSTS.with(|h| h.take()),
+        endpoint_approval_required: ENDPOINT_APPROVAL_REQUIRED.with(|r| *r.borrow()),
+        pending_endpoint_changes: PENDING_ENDPOINT_CHANGES.with(|p| p.take()),
+        endpoint_health: ENDPOINT_HEALTH.with(|h| h.take()),
+        registration_commitments: registry.commitments,
+        auctions: registry.auctions,
+        next_auction_id: registry.next_auction_id,
+        used_payment_blocks: registry.used_payment_blocks,
+        payouts: registry.payouts,
+        next_payout_id: registry.next_payout_id,
+        listings: registry.listings,
+        offers: registry.offers,
+        next_offer_id: registry.next_offer_id,
+        sales: registry.sales,
+        market_fee_bps: registry.market_fee_bps,
+        collected_fees_e8s: registry.collected_fees_e8s,
+        pending_transfers: registry.pending_transfers,
+        max_domains_per_wallet: registry.max_domains_per_wallet,
+        wallet_limit_overrides: registry.wallet_limit_overrides,
+        subdomains: registry.subdomains,
+        subdomain_policies: registry.subdomain_policies,
+        domain_permissions: registry.permissions,
+        governance_threshold: registry.governance_threshold,
+        proposals: registry.proposals,
+        next_proposal_id: registry.next_proposal_id,
+        approved_gifts: registry.approved_gifts,
+        admin_roles: registry.admin_roles,
+        audit_log_public: registry.audit_log_public,
+        reserved_patterns: registry.reserved_patterns,
+    };
     
-    // Check if recipient already has a domain
-    if let Some(existing_domain) = wallet_already_has_domain(request.recipient) {
-        return Err(format!("Recipient already owns domain: {}", existing_domain));
+    // The audit log shares stable memory, so the state goes into its own virtual memory
+    let bytes = candid::encode_one(&state)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to save state before upgrade: {}", e)));
+    if let Err(e) = memory::save_blob(&mut memory::get(memory::UPGRADE_STATE), &bytes) {
+        ic_cdk::trap(&format!("Failed to save state before upgrade: {}", e));
     }
-    
-    // Find active season and validate address exists in it
-    let active_season_info = REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .find(|season| matches!(season.status, SeasonStatus::Active))
-            .map(|s| (s.season_id, s.registered_count, s.total_allowed))
-    });
-    
-    let season_id = match active_season_info {
-        Some((id, registered, total)) => {
-            if registered >= total {
-                return Err("Cannot create domain: active season is full".to_string());
-            }
-            
-            // Validate that the address exists in this season
-            if !is_address_in_season(id, &request.recipient_address) {
-                return Err(format!("Address '{}' is not authorized for the current season", request.recipient_address));
-            }
-            
-            id
+}
+
+#[post_upgrade]
+fn post_upgrade() {
+    // Releases that did not save their state leave the memory empty. There is no prior state
+    // then, so start as `init` would, from the upgrade arguments when they name an admin.
+    let Some(bytes) = memory::load_blob(&memory::get(memory::UPGRADE_STATE)) else {
+        let arguments = candid::decode_args(&ic_cdk::api::call::arg_data_raw());
+        if let Ok((admin, config)) = arguments {
+            initialize(admin, config);
         }
-        None => return Err("Cannot create domain: no active season available".to_string()),
+        schedule_health_probes();
+        return;
     };
-    
-    let canister_id = create_domain_canister(
-        &request.domain_name,
-        request.recipient,
-        request.administrator,
-        request.operator,
-    ).await?;
-    
-    let domain_record = DomainRecord {
-        owner: request.recipient,
-        administrator: request.administrator,
-        operator: request.operator,
-        canister_id,
-        registration_time: time(),
-        expiration_time: time() + (365 * 24 * 60 * 60 * 1_000_000_000), // 1 year
-        last_payment_block: 0,
-        custom_mcp_endpoint: None,
-        was_gifted: false, // This is admin creation, not a gift
-        registration_season_id: Some(season_id),
+    let state: UpgradeState = candid::decode_one(&bytes)
+        .unwrap_or_else(|e| ic_cdk::trap(&format!("Failed to restore state after upgrade: {}", e)));
+    
+    let domain_names: Vec<String> = state.domains.keys().cloned().collect();
+    let registry = Registry {
+        domains: state.domains,
+        reserved_names: state.reserved_names,
+        admins: state.admins,
+        short_name_mode: state.short_name_mode,
+        approved_short_users: state.approved_short_users,
+        base_fee: state.base_fee,
+        ledger_canister: state.ledger_canister,
+        domain_canister_wasm: state.domain_canister_wasm,
+        seasons: state.seasons,
+        next_season_id: state.next_season_id,
+        next_registration_sequence: state.next_registration_sequence,
+        season_addresses: state.season_addresses,
+        commitments: state.registration_commitments,
+        auctions: state.auctions,
+        next_auction_id: state.next_auction_id,
+        used_payment_blocks: state.used_payment_blocks,
+        payouts: state.payouts,
+        next_payout_id: state.next_payout_id,
+        listings: state.listings,
+        offers: state.offers,
+        next_offer_id: state.next_offer_id,
+        sales: state.sales,
+        market_fee_bps: state.market_fee_bps,
+        collected_fees_e8s: state.collected_fees_e8s,
+        pending_transfers: state.pending_transfers,
+        max_domains_per_wallet: state.max_domains_per_wallet,
+        wallet_limit_overrides: state.wallet_limit_overrides,
+        subdomains: state.subdomains,
+        subdomain_policies: state.subdomain_policies,
+        permissions: state.domain_permissions,
+        governance_threshold: state.governance_threshold,
+        proposals: state.proposals,
+        next_proposal_id: state.next_proposal_id,
+        approved_gifts: state.approved_gifts,
+        admin_roles: state.admin_roles,
+        audit_log_public: state.audit_log_public,
+        reserved_patterns: state.reserved_patterns,
+        ..Registry::default()
     };
-    
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(request.domain_name.clone(), domain_record);
-    });
-    
-    // Add wallet-to-domain mapping for recipient
-    WALLET_TO_DOMAIN.with(|mapping| {
-        mapping.borrow_mut().insert(request.recipient, request.domain_name.clone());
-    });
-    
-    // Increment season count
-    REGISTRATION_SEASONS.with(|seasons| {
-        if let Some(season) = seasons.borrow_mut().get_mut(&season_id) {
-            season.registered_count += 1;
-        }
+    with_registry_mut(|current| {
+        *current = registry;
+        current.rebuild_indexes();
     });
-    complete_season_if_full(season_id);
+    DOMAIN_FILES.with(|f| *f.borrow_mut() = state.domain_files);
+    MCP_ENDPOINT_TEMPLATE.with(|t| *t.borrow_mut() = state.mcp_endpoint_template);
+    DENIED_ENDPOINT_HOSTS.with(|h| *h.borrow_mut() = state.denied_endpoint_hosts);
+    ALLOWED_ENDPOINT_HOSTS.with(|h| *h.borrow_mut() = state.allowed_endpoint_hosts);
+    ENDPOINT_APPROVAL_REQUIRED.with(|r| *r.borrow_mut() = state.endpoint_approval_required);
+    PENDING_ENDPOINT_CHANGES.with(|p| *p.borrow_mut() = state.pending_endpoint_changes);
+    ENDPOINT_HEALTH.with(|h| *h.borrow_mut() = state.endpoint_health);
+    
+    // Rebuild the tag, tool and full-text indexes from the restored metadata, manifests and content
+    for (domain_name, metadata) in state.domain_metadata {
+        store_domain_metadata(&domain_name, Some(metadata));
+    }
+    for (domain_name, manifest) in state.mcp_manifests {
+        store_mcp_manifest(&domain_name, Some(manifest));
+    }
+    for domain_name in &domain_names {
+        reindex_domain_text(domain_name);
+    }
     
-    Ok(format!(
-        "Domain {} created for address '{}' and assigned to {} with canister {}",
-        request.domain_name, request.recipient_address, request.recipient, canister_id
-    ))
+    // The global timer does not survive upgrades
+    schedule_health_probes();
 }
 
 #[update]
-fn admin_add_address_to_season(season_id: u64, address: String) -> Result<(), String> {
-    let caller = caller();
-    
-    if !is_admin(caller) {
-        return Err("Only admins can add addresses to seasons".to_string());
-    }
-    
-    add_address_to_season(season_id, address)
+async fn register_domain(request: RegistrationRequest) -> Result<RegistrationReceipt, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    let receipt = ic_environment().register_domain(&state, caller(), request).await?;
+    clear_domain_content(&receipt.domain);
+    Ok(receipt)
 }
 
-#[query]
-fn get_season_addresses(season_id: u64) -> Vec<String> {
-    SEASON_ADDRESSES.with(|addresses| {
-        addresses.borrow()
-            .get(&season_id)
-            .map(|addr_set| addr_set.iter().cloned().collect())
-            .unwrap_or_else(Vec::new)
-    })
+// Commits to registering a name without revealing it; see `registry::registration_commitment`
+#[update]
+fn commit_registration(commitment: Vec<u8>) -> Result<u64, RegistryError> {
+    with_registry_mut(|registry| registry.commit_registration(caller(), commitment, time()))
 }
 
-#[query]
-fn is_address_authorized_for_current_season(address: String) -> bool {
-    // Find active season and check if address is in it
-    REGISTRATION_SEASONS.with(|seasons| {
-        if let Some(active_season) = seasons.borrow()
-            .values()
-            .find(|season| matches!(season.status, SeasonStatus::Active)) {
-            is_address_in_season(active_season.season_id, &address)
-        } else {
-            false
-        }
-    })
+#[update]
+async fn reveal_registration(request: RevealRequest) -> Result<RegistrationReceipt, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    let receipt = ic_environment().reveal_registration(&state, caller(), request).await?;
+    clear_domain_content(&receipt.domain);
+    Ok(receipt)
 }
 
 #[update]
-async fn renew_domain(domain_name: String, payment_block: u64) -> Result<String, String> {
-    let caller = caller();
-    
-    let mut domain_record = DOMAINS.with(|domains| {
-        domains.borrow().get(&domain_name).cloned()
-    }).ok_or("Domain not found")?;
-    
-    if caller != domain_record.owner && caller != domain_record.administrator {
-        return Err("Unauthorized".to_string());
-    }
-    
-    let is_admin_caller = is_admin(caller);
-    let renewal_fee = calculate_renewal_fee();
-    
-    // Extend expiration by one year
-    domain_record.expiration_time += 365 * 24 * 60 * 60 * 1_000_000_000;
-    domain_record.last_payment_block = payment_block;
-    
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(domain_name.clone(), domain_record);
-    });
-    
-    let fee_info = if is_admin_caller {
-        "Free (admin renewal)".to_string()
-    } else {
-        format!("Fee: {} ICP", renewal_fee as f64 / 100_000_000.0)
-    };
-    
-    Ok(format!("Domain {} renewed successfully. {}", domain_name, fee_info))
+async fn admin_gift_domain(request: AdminGiftRequest) -> Result<RegistrationReceipt, RegistryError> {
+    let args = format!("{} to {}", request.domain_name, request.recipient);
+    let state = REGISTRY.with(Rc::clone);
+    let result = ic_environment().gift_domain(&state, caller(), request).await;
+    let receipt = audited("admin_gift_domain", args, || result)?;
+    clear_domain_content(&receipt.domain);
+    Ok(receipt)
+}
+
+#[update]
+async fn admin_create_domain_with_address(request: AdminCreateDomainRequest) -> Result<RegistrationReceipt, RegistryError> {
+    let args = format!("{} to {} for address {}", request.domain_name, request.recipient, request.recipient_address);
+    let state = REGISTRY.with(Rc::clone);
+    let result = ic_environment().create_domain_with_address(&state, caller(), request).await;
+    let receipt = audited("admin_create_domain_with_address", args, || result)?;
+    clear_domain_content(&receipt.domain);
+    Ok(receipt)
+}
+
+#[update]
+fn create_auction(request: CreateAuctionRequest) -> Result<u64, RegistryError> {
+    let args = format!(
+        "{:?} auction of {}, reserve {} e8s, {} ns",
+        request.mode, request.domain_name, request.reserve_price_e8s, request.duration_ns
+    );
+    let auction_id = audited("create_auction", args, || {
+        with_registry_mut(|registry| registry.create_auction(caller(), request, time()))
+    })?;
+    schedule_timer();
+    Ok(auction_id)
+}
+
+#[update]
+async fn cancel_auction(auction_id: u64) -> Result<(), RegistryError> {
+    audited("cancel_auction", auction_id.to_string(), || {
+        with_registry_mut(|registry| registry.cancel_auction(caller(), auction_id))
+    })?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(())
+}
+
+#[update]
+async fn place_bid(request: PlaceBidRequest) -> Result<(), RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().place_bid(&state, caller(), request).await
+}
+
+#[update]
+fn reveal_bid(request: RevealBidRequest) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.reveal_bid(caller(), request, time()))
+}
+
+#[query]
+fn get_active_auctions() -> Vec<AuctionInfo> {
+    with_registry(|registry| registry.active_auctions())
+}
+
+#[query]
+fn get_auction(auction_id: u64) -> Option<AuctionInfo> {
+    with_registry(|registry| registry.auctions.get(&auction_id).map(Auction::info))
+}
+
+// Sealed auctions only show callers their own bids until they settle
+#[query]
+fn get_auction_bids(auction_id: u64) -> Vec<Bid> {
+    with_registry(|registry| registry.auction_bids(caller(), auction_id))
+}
+
+#[query]
+fn get_pending_payouts() -> Result<Vec<Payout>, RegistryError> {
+    with_registry(|registry| {
+        registry.require_role(caller(), AdminRole::Treasurer)?;
+        Ok(registry.payouts.clone())
+    })
+}
+
+#[update]
+fn list_domain(request: ListDomainRequest) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.list_domain(caller(), request, time()))
+}
+
+#[update]
+fn cancel_listing(domain_name: String) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.cancel_listing(caller(), &domain_name, time()))
+}
+
+#[update]
+async fn buy_domain(request: BuyDomainRequest) -> Result<Sale, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().buy_domain(&state, caller(), request).await
+}
+
+#[update]
+async fn make_offer(request: MakeOfferRequest) -> Result<u64, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().make_offer(&state, caller(), request).await
+}
+
+#[update]
+async fn cancel_offer(offer_id: u64) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.cancel_offer(caller(), offer_id))?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(())
+}
+
+#[update]
+async fn accept_offer(offer_id: u64) -> Result<Sale, RegistryError> {
+    let sale = with_registry_mut(|registry| registry.accept_offer(caller(), offer_id, time()))?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(sale)
+}
+
+#[query]
+fn get_listings() -> Vec<Listing> {
+    with_registry(|registry| registry.active_listings(time()))
+}
+
+#[query]
+fn get_listing(domain_name: String) -> Option<Listing> {
+    with_registry(|registry| {
+        registry.active_listings(time()).into_iter().find(|listing| listing.domain_name == domain_name)
+    })
+}
+
+#[query]
+fn get_offers(domain_name: String) -> Vec<Offer> {
+    with_registry(|registry| registry.domain_offers(&domain_name, time()))
+}
+
+#[query]
+fn get_sales_history(domain_name: Option<String>, offset: u64, limit: u64) -> SalePage {
+    with_registry(|registry| registry.sales_page(domain_name.as_deref(), offset, limit))
+}
+
+#[update]
+fn set_market_fee(fee_bps: u64) -> Result<(), RegistryError> {
+    audited("set_market_fee", format!("{} bps", fee_bps), || {
+        with_registry_mut(|registry| registry.set_market_fee(caller(), fee_bps))
+    })
+}
+
+#[query]
+fn get_market_fee() -> u64 {
+    with_registry(|registry| registry.market_fee_bps)
+}
+
+#[update]
+async fn withdraw_market_fees(to: Principal) -> Result<u64, RegistryError> {
+    let amount_e8s = audited("withdraw_market_fees", format!("to {}", to), || {
+        with_registry_mut(|registry| registry.withdraw_market_fees(caller(), to))
+    })?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(amount_e8s)
+}
+
+#[update]
+fn admin_add_address_to_season(season_id: u64, address: String) -> Result<(), RegistryError> {
+    audited("admin_add_address_to_season", format!("season {}, {}", season_id, address), || {
+        with_registry_mut(|registry| registry.add_address_to_season(caller(), season_id, address))
+    })
+}
+
+#[query]
+fn get_season_addresses(season_id: u64) -> Vec<String> {
+    with_registry(|registry| {
+        registry.season_addresses
+            .get(&season_id)
+            .map(|addr_set| addr_set.iter().cloned().collect())
+            .unwrap_or_default()
+    })
+}
+
+#[query]
+fn is_address_authorized_for_current_season(address: String) -> bool {
+    with_registry(|registry| {
+        registry.active_season()
+            .is_some_and(|season| registry.is_address_in_season(season.season_id, &address))
+    })
+}
+
+#[update]
+async fn renew_domain(domain_name: String, payment_block: u64) -> Result<String, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().renew_domain(&state, caller(), &domain_name, payment_block).await
 }
 
 #[update]
 async fn set_custom_mcp_endpoint(
     domain_name: String, 
     custom_endpoint: Option<String>
-) -> Result<(), String> {
+) -> Result<EndpointChangeStatus, RegistryError> {
     let caller = caller();
+    with_registry(|registry| match split_subdomain(&domain_name) {
+        Some(_) => registry.check_subdomain_editor(&domain_name, caller, true, time()),
+        None => registry.authorize(&domain_name, caller, Permission::SetEndpoint).map(|_| ()),
+    })?;
     
-    let mut domain_record = DOMAINS.with(|domains| {
-        domains.borrow().get(&domain_name).cloned()
-    }).ok_or("Domain not found")?;
+    let custom_endpoint = custom_endpoint.as_deref().map(check_custom_endpoint).transpose()?;
     
-    if caller != domain_record.owner && caller != domain_record.administrator {
-        return Err("Unauthorized".to_string());
+    // A newer request always supersedes one still waiting for approval
+    PENDING_ENDPOINT_CHANGES.with(|pending| {
+        pending.borrow_mut().remove(&domain_name);
+    });
+    
+    let needs_approval = ENDPOINT_APPROVAL_REQUIRED.with(|required| *required.borrow()) && !is_admin(caller);
+    if let (Some(endpoint), true) = (&custom_endpoint, needs_approval) {
+        PENDING_ENDPOINT_CHANGES.with(|pending| {
+            pending.borrow_mut().insert(domain_name.clone(), PendingEndpointChange {
+                domain_name,
+                endpoint: endpoint.clone(),
+                requested_by: caller,
+                requested_at: time(),
+            });
+        });
+        return Ok(EndpointChangeStatus::PendingApproval);
     }
     
-    if let Some(ref endpoint) = custom_endpoint {
-        if !endpoint.starts_with("https://") {
-            return Err("Custom endpoint must use HTTPS".to_string());
-        }
-        if endpoint.len() > 200 {
-            return Err("Custom endpoint too long".to_string());
-        }
+    with_registry_mut(|registry| registry.store_custom_endpoint(&domain_name, custom_endpoint, time()))?;
+    
+    Ok(EndpointChangeStatus::Applied)
+}
+
+#[update]
+fn approve_endpoint_change(domain_name: String) -> Result<(), RegistryError> {
+    audited("approve_endpoint_change", domain_name.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        let change = PENDING_ENDPOINT_CHANGES.with(|pending| {
+            pending.borrow_mut().remove(&domain_name)
+        }).ok_or(RegistryError::NoPendingChange)?;
+        
+        // The host lists may have changed since the request was queued
+        let endpoint = check_custom_endpoint(&change.endpoint)?;
+        
+        with_registry_mut(|registry| registry.store_custom_endpoint(&domain_name, Some(endpoint), time()))
+    })
+}
+
+#[update]
+fn reject_endpoint_change(domain_name: String) -> Result<(), RegistryError> {
+    audited("reject_endpoint_change", domain_name.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        PENDING_ENDPOINT_CHANGES.with(|pending| {
+            pending.borrow_mut().remove(&domain_name)
+        }).ok_or(RegistryError::NoPendingChange)?;
+        
+        Ok(())
+    })
+}
+
+#[query]
+fn get_pending_endpoint_changes() -> Vec<PendingEndpointChange> {
+    PENDING_ENDPOINT_CHANGES.with(|pending| {
+        let mut changes: Vec<PendingEndpointChange> = pending.borrow().values().cloned().collect();
+        changes.sort_by_key(|change| change.requested_at);
+        changes
+    })
+}
+
+#[update]
+fn set_domain_metadata(domain_name: String, metadata: Option<DomainMetadata>) -> Result<(), RegistryError> {
+    authorized_domain(&domain_name, caller(), Permission::EditMetadata)?;
+    
+    let metadata = metadata.map(normalize_metadata).transpose()?;
+    
+    store_domain_metadata(&domain_name, metadata);
+    reindex_domain_text(&domain_name);
+    touch_domain(&domain_name);
+    
+    Ok(())
+}
+
+#[update]
+fn set_knowledge_file(domain_name: String, content: Option<String>) -> Result<(), RegistryError> {
+    authorized_domain(&domain_name, caller(), Permission::UploadFiles)?;
+    
+    if content.as_ref().is_some_and(|c| c.len() > MAX_KNOWLEDGE_FILE_BYTES) {
+        return Err(RegistryError::ContentTooLarge { max_bytes: MAX_KNOWLEDGE_FILE_BYTES as u64 });
     }
     
-    domain_record.custom_mcp_endpoint = custom_endpoint;
+    DOMAIN_FILES.with(|files| {
+        files.borrow_mut().entry(domain_name.clone()).or_default().knowledge_file = content;
+    });
+    reindex_domain_text(&domain_name);
+    touch_domain(&domain_name);
+    
+    Ok(())
+}
+
+#[update]
+fn set_did_file(domain_name: String, content: Option<String>) -> Result<(), RegistryError> {
+    let domain_record = authorized_domain(&domain_name, caller(), Permission::UploadFiles)?;
     
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(domain_name, domain_record);
+    if content.is_some() && address_type_of(&domain_record.target_address()) != AddressType::Canister {
+        return Err(RegistryError::NotCanisterName);
+    }
+    if content.as_ref().is_some_and(|c| c.len() > MAX_DID_FILE_BYTES) {
+        return Err(RegistryError::ContentTooLarge { max_bytes: MAX_DID_FILE_BYTES as u64 });
+    }
+    
+    DOMAIN_FILES.with(|files| {
+        files.borrow_mut().entry(domain_name.clone()).or_default().did_file = content;
     });
+    touch_domain(&domain_name);
+    
+    Ok(())
+}
+
+#[update]
+fn set_mcp_manifest(domain_name: String, manifest_json: Option<String>) -> Result<(), RegistryError> {
+    authorized_domain(&domain_name, caller(), Permission::UploadFiles)?;
+    
+    let manifest = manifest_json.as_deref()
+        .map(mcp::parse_manifest)
+        .transpose()
+        .map_err(|reason| RegistryError::InvalidManifest { reason })?;
+    
+    store_mcp_manifest(&domain_name, manifest);
+    reindex_domain_text(&domain_name);
+    touch_domain(&domain_name);
     
     Ok(())
 }
 
 #[query]
-fn get_domain_info(domain_name: String) -> Option<DomainInfo> {
-    DOMAINS.with(|domains| {
-        domains.borrow().get(&domain_name).map(|domain| {
-            let current_time = time();
-            let status = if domain.expiration_time > current_time {
-                DomainStatus::Active
-            } else {
-                DomainStatus::Expired
-            };
-            
-            let mcp_endpoint = domain.custom_mcp_endpoint.clone()
-                .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", domain_name));
-            
-            DomainInfo {
-                name: domain_name.clone(),
-                owner: domain.owner,
-                administrator: domain.administrator,
-                operator: domain.operator,
-                canister_id: domain.canister_id,
-                expiration_time: domain.expiration_time,
-                mcp_endpoint,
-                status,
-                was_gifted: domain.was_gifted,
-            }
+fn get_mcp_manifest(domain_name: String) -> Option<McpManifest> {
+    MCP_MANIFESTS.with(|manifests| manifests.borrow().get(&domain_name).cloned())
+}
+
+#[query]
+fn find_domains_by_tool(tool_name: String) -> Vec<SearchResult> {
+    let current_time = time();
+    let mut names: Vec<String> = TOOL_INDEX.with(|index| {
+        index.borrow()
+            .get(&tool_name.to_lowercase())
+            .map(|domains| domains.iter().cloned().collect())
+            .unwrap_or_default()
+    });
+    names.sort();
+    
+    with_registry(|registry| {
+        DOMAIN_METADATA.with(|metadata| {
+            let domains = &registry.domains;
+            let metadata = metadata.borrow();
+            names.iter()
+                .filter_map(|name| {
+                    let domain = domains.get(name).filter(|d| d.expiration_time > current_time)?;
+                    Some(to_search_result(name, domain, metadata.get(name)))
+                })
+                .take(MAX_SEARCH_RESULTS)
+                .collect()
         })
     })
 }
 
+#[query]
+fn get_endpoint_health(domain_name: String) -> Option<EndpointHealth> {
+    ENDPOINT_HEALTH.with(|health| health.borrow().get(&domain_name).cloned())
+}
+
+#[update]
+async fn probe_mcp_endpoints() -> Result<u64, RegistryError> {
+    audited("probe_mcp_endpoints", String::new(), || require_role(AdminRole::Moderator))?;
+    
+    Ok(run_health_probes().await)
+}
+
+#[query]
+fn transform_mcp_probe(args: TransformArgs) -> HttpResponse {
+    health::canonical_probe_response(args.response)
+}
+
+#[query]
+fn get_domain_metadata(domain_name: String) -> Option<DomainMetadata> {
+    DOMAIN_METADATA.with(|metadata| metadata.borrow().get(&domain_name).cloned())
+}
+
+#[query]
+fn get_knowledge_file(domain_name: String) -> Option<String> {
+    DOMAIN_FILES.with(|files| {
+        files.borrow().get(&domain_name).and_then(|f| f.knowledge_file.clone())
+    })
+}
+
+#[query]
+fn get_did_file(domain_name: String) -> Option<String> {
+    DOMAIN_FILES.with(|files| {
+        files.borrow().get(&domain_name).and_then(|f| f.did_file.clone())
+    })
+}
+
+#[query]
+fn get_domain_info(domain_name: String) -> Option<DomainInfo> {
+    with_registry(|registry| {
+        if split_subdomain(&domain_name).is_some() {
+            return subdomain_info(registry, &domain_name, time());
+        }
+        registry.domains
+            .get(&domain_name)
+            .map(|domain| build_domain_info(&domain_name, domain, time()))
+    })
+}
+
 #[query]
 fn get_mcp_endpoint(domain_name: String) -> Option<String> {
-    DOMAINS.with(|domains| {
-        domains.borrow().get(&domain_name).map(|domain| {
-            domain.custom_mcp_endpoint.clone()
-                .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", domain_name))
-        })
+    with_registry(|registry| {
+        if split_subdomain(&domain_name).is_some() {
+            return subdomain_info(registry, &domain_name, time()).map(|info| info.mcp_endpoint);
+        }
+        registry.domains
+            .get(&domain_name)
+            .map(|domain| effective_mcp_endpoint(&domain_name, domain))
     })
 }
 
 #[query]
 fn list_domains(owner: Option<Principal>) -> Vec<DomainInfo> {
-    DOMAINS.with(|domains| {
-        domains.borrow()
+    let current_time = time();
+    with_registry(|registry| {
+        registry.domains
             .iter()
             .filter(|(_, domain)| {
                 match owner {
@@ -758,29 +1528,7 @@ fn list_domains(owner: Option<Principal>) -> Vec<DomainInfo> {
                     None => true,
                 }
             })
-            .map(|(name, domain)| {
-                let current_time = time();
-                let status = if domain.expiration_time > current_time {
-                    DomainStatus::Active
-                } else {
-                    DomainStatus::Expired
-                };
-                
-                let mcp_endpoint = domain.custom_mcp_endpoint.clone()
-                    .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", name));
-                
-                DomainInfo {
-                    name: name.clone(),
-                    owner: domain.owner,
-                    administrator: domain.administrator,
-                    operator: domain.operator,
-                    canister_id: domain.canister_id,
-                    expiration_time: domain.expiration_time,
-                    mcp_endpoint,
-                    status,
-                    was_gifted: domain.was_gifted,
-                }
-            })
+            .map(|(name, domain)| build_domain_info(name, domain, current_time))
             .collect()
     })
 }
@@ -790,297 +1538,442 @@ fn get_registration_fee(domain_name: String) -> u64 {
     if !is_valid_domain_name(&domain_name) {
         return 0;
     }
-    if is_reserved_name(&domain_name) {
-        return 0;
-    }
-    calculate_registration_fee(&domain_name).unwrap_or(0)
+    with_registry(|registry| {
+        if registry.is_reserved_name(&domain_name) {
+            return 0;
+        }
+        registry.registration_fee(&domain_name).unwrap_or(0)
+    })
 }
 
 #[query]
 fn get_renewal_fee() -> u64 {
-    calculate_renewal_fee()
+    with_registry(|registry| registry.renewal_fee())
 }
 
 #[query]
 fn can_register_domain(domain_name: String, user: Principal) -> bool {
-    if !is_valid_domain_name(&domain_name) {
-        return false;
-    }
-    if is_reserved_name(&domain_name) {
-        return false;
-    }
-    
-    let is_available = DOMAINS.with(|domains| {
-        match domains.borrow().get(&domain_name) {
-            Some(domain) => {
-                let current_time = time();
-                domain.expiration_time < current_time
-            }
-            None => true,
-        }
+    with_registry(|registry| registry.can_register(&domain_name, user, time()))
+}
+
+/// Every active match, unpaged. Use `full_text_search` or `get_names_between` to page through large registries.
+#[query]
+fn discover_domains(query: String) -> Vec<SearchResult> {
+    let filters = SearchFilters {
+        status: Some(DomainStatus::Active),
+        ..Default::default()
+    };
+    search_domains(&query, &filters, None)
+}
+
+#[query]
+fn search_names(pattern: String, filters: SearchFilters) -> Vec<SearchResult> {
+    search_domains(&pattern, &filters, Some(MAX_SEARCH_RESULTS))
+}
+
+#[query]
+fn full_text_search(query: String, offset: u64, limit: u64) -> TextSearchPage {
+    let limit = limit.clamp(1, MAX_SEARCH_RESULTS as u64);
+    let current_time = time();
+    
+    let ranked = TEXT_INDEX.with(|index| index.borrow().search(&query));
+    let active: Vec<(String, f64, String)> = with_registry(|registry| {
+        let domains = &registry.domains;
+        ranked
+            .into_iter()
+            .filter_map(|(name, score)| {
+                let domain = domains.get(&name).filter(|d| d.expiration_time > current_time)?;
+                let mcp_endpoint = effective_mcp_endpoint(&name, domain);
+                Some((name, score, mcp_endpoint))
+            })
+            .collect()
     });
     
-    if !is_available {
-        return false;
-    }
+    let total = active.len() as u64;
+    let hits: Vec<TextSearchHit> = active
+        .into_iter()
+        .skip(offset as usize)
+        .take(limit as usize)
+        .map(|(domain, score, mcp_endpoint)| TextSearchHit {
+            snippet: search::snippet(&searchable_text(&domain), &query).unwrap_or_default(),
+            domain,
+            score,
+            mcp_endpoint,
+        })
+        .collect();
     
-    can_register_short_domain(&domain_name, user)
+    let next = offset + hits.len() as u64;
+    TextSearchPage {
+        hits,
+        total,
+        next_offset: if next < total { Some(next) } else { None },
+    }
+}
+
+// Admin functions
+#[update]
+fn add_admin(new_admin: Principal) -> Result<(), RegistryError> {
+    audited("add_admin", new_admin.to_string(), || {
+        with_registry_mut(|registry| registry.add_admin(caller(), new_admin))
+    })
+}
+
+#[update]
+fn remove_admin(admin_to_remove: Principal) -> Result<(), RegistryError> {
+    audited("remove_admin", admin_to_remove.to_string(), || {
+        with_registry_mut(|registry| registry.remove_admin(caller(), admin_to_remove))
+    })
+}
+
+#[update]
+fn add_reserved_name(name: String) -> Result<(), RegistryError> {
+    audited("add_reserved_name", name.clone(), || {
+        with_registry_mut(|registry| registry.add_reserved_name(caller(), name))
+    })
+}
+
+/// Reserves up to 10,000 names at once. Entries that are not valid names are reported back.
+#[update]
+fn add_reserved_names(names: Vec<String>) -> Result<ReservedImport, RegistryError> {
+    audited("add_reserved_names", format!("{} names", names.len()), || {
+        with_registry_mut(|registry| registry.add_reserved_names(caller(), names))
+    })
+}
+
+#[update]
+fn remove_reserved_name(name: String) -> Result<(), RegistryError> {
+    audited("remove_reserved_name", name.clone(), || {
+        with_registry_mut(|registry| registry.remove_reserved_name(caller(), &name))
+    })
+}
+
+/// Reserves every name matching a `*` pattern, such as `*-official` or `icp*`.
+#[update]
+fn add_reserved_pattern(pattern: String) -> Result<(), RegistryError> {
+    audited("add_reserved_pattern", pattern.clone(), || {
+        with_registry_mut(|registry| registry.add_reserved_pattern(caller(), &pattern))
+    })
+}
+
+#[update]
+fn remove_reserved_pattern(pattern: String) -> Result<(), RegistryError> {
+    audited("remove_reserved_pattern", pattern.clone(), || {
+        with_registry_mut(|registry| registry.remove_reserved_pattern(caller(), &pattern))
+    })
+}
+
+/// Registers a reserved name to `recipient` for free and lifts its exact reservation.
+#[update]
+async fn release_reserved_name(domain_name: String, recipient: Principal) -> Result<RegistrationReceipt, RegistryError> {
+    let args = format!("{} to {}", domain_name, recipient);
+    let state = REGISTRY.with(Rc::clone);
+    let result = ic_environment().release_reserved_name(&state, caller(), domain_name, recipient).await;
+    let receipt = audited("release_reserved_name", args, || result)?;
+    clear_domain_content(&receipt.domain);
+    Ok(receipt)
+}
+
+#[query]
+fn get_reserved_names(offset: u64, limit: u64) -> ReservedNamePage {
+    with_registry(|registry| registry.reserved_names_page(offset, limit))
+}
+
+#[query]
+fn get_reserved_patterns() -> Vec<String> {
+    with_registry(|registry| registry.reserved_patterns.iter().cloned().collect())
+}
+
+/// True when the name is reserved by name or by pattern.
+#[query]
+fn is_name_reserved(name: String) -> bool {
+    with_registry(|registry| registry.is_reserved_name(&name))
+}
+
+#[update]
+fn approve_user_for_short_names(user: Principal) -> Result<(), RegistryError> {
+    audited("approve_user_for_short_names", user.to_string(), || {
+        with_registry_mut(|registry| registry.approve_user_for_short_names(caller(), user))
+    })
+}
+
+#[update]
+fn revoke_short_name_approval(user: Principal) -> Result<(), RegistryError> {
+    audited("revoke_short_name_approval", user.to_string(), || {
+        with_registry_mut(|registry| registry.revoke_short_name_approval(caller(), user))
+    })
+}
+
+#[update]
+fn set_short_name_mode(mode: RegistrationMode) -> Result<(), RegistryError> {
+    audited("set_short_name_mode", format!("{:?}", mode), || {
+        with_registry_mut(|registry| registry.set_short_name_mode(caller(), mode))
+    })
+}
+
+#[update]
+fn set_base_fee(new_fee: u64) -> Result<(), RegistryError> {
+    audited("set_base_fee", format!("{} e8s", new_fee), || {
+        with_registry_mut(|registry| registry.set_base_fee(caller(), new_fee))
+    })
+}
+
+#[update]
+fn set_domain_canister_wasm(wasm: Vec<u8>) -> Result<(), RegistryError> {
+    audited("set_domain_canister_wasm", describe_wasm(&wasm), || {
+        with_registry_mut(|registry| registry.set_domain_canister_wasm(caller(), wasm))
+    })
+}
+
+/// Opens a proposal for a sensitive admin action, with the caller's approval counted.
+// Proposals that run at once may queue payouts, such as a fee withdrawal
+#[update]
+async fn propose_admin_action(action: AdminAction, duration_ns: Option<u64>) -> Result<ProposalInfo, RegistryError> {
+    let proposal = audited("propose_admin_action", action.summary(), || {
+        with_registry_mut(|registry| registry.propose_admin_action(caller(), action, duration_ns, time()))
+    })?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(proposal)
+}
+
+#[update]
+async fn approve_proposal(proposal_id: u64) -> Result<ProposalInfo, RegistryError> {
+    let proposal = audited("approve_proposal", proposal_id.to_string(), || {
+        with_registry_mut(|registry| registry.vote_on_proposal(caller(), proposal_id, true, time()))
+    })?;
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().process_payouts(&state).await;
+    Ok(proposal)
+}
+
+#[update]
+fn reject_proposal(proposal_id: u64) -> Result<ProposalInfo, RegistryError> {
+    audited("reject_proposal", proposal_id.to_string(), || {
+        with_registry_mut(|registry| registry.vote_on_proposal(caller(), proposal_id, false, time()))
+    })
+}
+
+#[query]
+fn get_proposal(proposal_id: u64) -> Option<ProposalInfo> {
+    with_registry(|registry| registry.proposals.get(&proposal_id).map(|proposal| registry.proposal_info(proposal)))
+}
+
+#[query]
+fn get_proposals(open_only: bool, offset: u64, limit: u64) -> ProposalPage {
+    with_registry(|registry| registry.proposals_page(open_only, offset, limit))
+}
+
+#[update]
+fn set_approval_threshold(threshold: u64) -> Result<(), RegistryError> {
+    audited("set_approval_threshold", threshold.to_string(), || {
+        with_registry_mut(|registry| registry.set_approval_threshold(caller(), threshold))
+    })
+}
+
+/// Takes payments on `ledger`, or switches escrowed payments off with None. Treasurer-only.
+#[update]
+fn set_ledger_canister(ledger: Option<Principal>) -> Result<(), RegistryError> {
+    audited("set_ledger_canister", format!("{:?}", ledger), || {
+        with_registry_mut(|registry| registry.set_ledger_canister(caller(), ledger))
+    })
+}
+
+#[query]
+fn get_ledger_canister() -> Option<Principal> {
+    with_registry(|registry| registry.ledger_canister)
+}
+
+#[query]
+fn get_approval_threshold() -> u64 {
+    with_registry(|registry| registry.governance_threshold)
+}
+
+#[update]
+fn set_mcp_endpoint_template(template: String) -> Result<(), RegistryError> {
+    audited("set_mcp_endpoint_template", template.clone(), || {
+        require_role(AdminRole::SuperAdmin)?;
+        
+        validate_endpoint_template(&template)?;
+        MCP_ENDPOINT_TEMPLATE.with(|current| {
+            *current.borrow_mut() = template;
+        });
+        
+        Ok(())
+    })
+}
+
+#[query]
+fn get_mcp_endpoint_template() -> String {
+    MCP_ENDPOINT_TEMPLATE.with(|template| template.borrow().clone())
 }
 
-#[query]
-fn discover_domains(query: String) -> Vec<SearchResult> {
-    DOMAINS.with(|domains| {
-        domains.borrow()
-            .iter()
-            .filter(|(name, domain)| {
-                let current_time = time();
-                domain.expiration_time > current_time &&
-                (query.is_empty() || name.to_lowercase().contains(&query.to_lowercase()))
-            })
-            .map(|(name, domain)| {
-                let mcp_endpoint = domain.custom_mcp_endpoint.clone()
-                    .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", name));
-                
-                SearchResult {
-                    domain: name.clone(),
-                    description: format!("Domain {} - {}", name, if domain.was_gifted { "Admin Gift" } else { "Registered" }),
-                    mcp_endpoint,
-                    tools_count: 0,
-                    resources_count: 0,
-                    was_gifted: domain.was_gifted,
-                }
-            })
-            .collect()
+#[update]
+fn add_denied_endpoint_host(host: String) -> Result<(), RegistryError> {
+    audited("add_denied_endpoint_host", host.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        let host = endpoint_url::normalize_host_rule(&host).map_err(RegistryError::invalid_input)?;
+        DENIED_ENDPOINT_HOSTS.with(|hosts| {
+            hosts.borrow_mut().insert(host);
+        });
+        
+        Ok(())
     })
 }
 
-// Admin functions
 #[update]
-fn add_admin(new_admin: Principal) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can add other admins".to_string());
-    }
-    
-    ADMIN_PRINCIPALS.with(|admins| {
-        admins.borrow_mut().insert(new_admin);
-    });
-    
-    Ok(())
+fn remove_denied_endpoint_host(host: String) -> Result<(), RegistryError> {
+    audited("remove_denied_endpoint_host", host.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        let host = endpoint_url::normalize_host_rule(&host).map_err(RegistryError::invalid_input)?;
+        DENIED_ENDPOINT_HOSTS.with(|hosts| {
+            hosts.borrow_mut().remove(&host);
+        });
+        
+        Ok(())
+    })
 }
 
 #[update]
-fn remove_admin(admin_to_remove: Principal) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can remove other admins".to_string());
-    }
-    
-    ADMIN_PRINCIPALS.with(|admins| {
-        let mut admin_set = admins.borrow_mut();
-        if admin_set.len() <= 1 {
-            return Err("Cannot remove the last admin".to_string());
-        }
-        admin_set.remove(&admin_to_remove);
+fn add_allowed_endpoint_host(host: String) -> Result<(), RegistryError> {
+    audited("add_allowed_endpoint_host", host.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        let host = endpoint_url::normalize_host_rule(&host).map_err(RegistryError::invalid_input)?;
+        ALLOWED_ENDPOINT_HOSTS.with(|hosts| {
+            hosts.borrow_mut().insert(host);
+        });
+        
         Ok(())
     })
 }
 
 #[update]
-fn add_reserved_name(name: String) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can add reserved names".to_string());
-    }
-    
-    RESERVED_NAMES.with(|reserved| {
-        reserved.borrow_mut().insert(name);
-    });
-    
-    Ok(())
+fn remove_allowed_endpoint_host(host: String) -> Result<(), RegistryError> {
+    audited("remove_allowed_endpoint_host", host.clone(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        let host = endpoint_url::normalize_host_rule(&host).map_err(RegistryError::invalid_input)?;
+        ALLOWED_ENDPOINT_HOSTS.with(|hosts| {
+            hosts.borrow_mut().remove(&host);
+        });
+        
+        Ok(())
+    })
 }
 
 #[update]
-fn approve_user_for_short_names(user: Principal) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can approve users for short names".to_string());
-    }
-    
-    APPROVED_SHORT_USERS.with(|users| {
-        users.borrow_mut().insert(user);
-    });
-    
-    Ok(())
+fn set_endpoint_approval_required(required: bool) -> Result<(), RegistryError> {
+    audited("set_endpoint_approval_required", required.to_string(), || {
+        require_role(AdminRole::Moderator)?;
+        
+        ENDPOINT_APPROVAL_REQUIRED.with(|approval| {
+            *approval.borrow_mut() = required;
+        });
+        
+        Ok(())
+    })
 }
 
-#[update]
-fn revoke_short_name_approval(user: Principal) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can revoke short name approvals".to_string());
-    }
-    
-    APPROVED_SHORT_USERS.with(|users| {
-        users.borrow_mut().remove(&user);
-    });
+#[query]
+fn get_endpoint_host_policy() -> EndpointHostPolicy {
+    let sorted = |hosts: &HashSet<String>| {
+        let mut hosts: Vec<String> = hosts.iter().cloned().collect();
+        hosts.sort();
+        hosts
+    };
     
-    Ok(())
+    EndpointHostPolicy {
+        denied_hosts: DENIED_ENDPOINT_HOSTS.with(|hosts| sorted(&hosts.borrow())),
+        allowed_hosts: ALLOWED_ENDPOINT_HOSTS.with(|hosts| sorted(&hosts.borrow())),
+        approval_required: ENDPOINT_APPROVAL_REQUIRED.with(|required| *required.borrow()),
+    }
 }
 
-#[update]
-fn set_short_name_mode(mode: RegistrationMode) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can change short name mode".to_string());
-    }
-    
-    SHORT_NAME_MODE.with(|current_mode| {
-        *current_mode.borrow_mut() = mode;
-    });
-    
-    Ok(())
+#[query]
+fn get_admins() -> Vec<Principal> {
+    with_registry(|registry| registry.admins.iter().cloned().collect())
+}
+
+/// True for SuperAdmins. Use `get_admin_roles` for the narrower roles.
+#[query]
+fn is_user_admin(user: Principal) -> bool {
+    is_admin(user)
 }
 
 #[update]
-fn set_base_fee(new_fee: u64) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can set fees".to_string());
-    }
-    
-    BASE_FEE.with(|fee| {
-        *fee.borrow_mut() = new_fee;
-    });
-    
-    Ok(())
+fn assign_admin_role(principal: Principal, role: AdminRole) -> Result<(), RegistryError> {
+    audited("assign_admin_role", format!("{:?} to {}", role, principal), || {
+        with_registry_mut(|registry| registry.assign_role(caller(), principal, role))
+    })
 }
 
 #[update]
-fn set_domain_canister_wasm(wasm: Vec<u8>) -> Result<(), String> {
-    let caller = caller();
-    if !is_admin(caller) {
-        return Err("Only admins can set domain canister WASM".to_string());
-    }
-    
-    DOMAIN_CANISTER_WASM.with(|stored_wasm| {
-        *stored_wasm.borrow_mut() = wasm;
-    });
-    
-    Ok(())
+fn revoke_admin_role(principal: Principal, role: AdminRole) -> Result<(), RegistryError> {
+    audited("revoke_admin_role", format!("{:?} from {}", role, principal), || {
+        with_registry_mut(|registry| registry.revoke_role(caller(), principal, role))
+    })
 }
 
 #[query]
-fn get_admins() -> Vec<Principal> {
-    ADMIN_PRINCIPALS.with(|admins| {
-        admins.borrow().iter().cloned().collect()
-    })
+fn get_admin_roles(principal: Principal) -> Vec<AdminRole> {
+    with_registry(|registry| registry.roles_of(principal))
 }
 
 #[query]
-fn is_user_admin(user: Principal) -> bool {
-    is_admin(user)
+fn get_role_assignments() -> Vec<(Principal, Vec<AdminRole>)> {
+    with_registry(|registry| registry.role_assignments())
 }
 
+/// Admin-gated calls, newest first. Readable by any admin role, or by anyone once public.
 #[query]
-fn get_approved_short_users() -> Vec<Principal> {
-    APPROVED_SHORT_USERS.with(|users| {
-        users.borrow().iter().cloned().collect()
+fn get_audit_log(offset: u64, limit: u64) -> Result<AuditPage, RegistryError> {
+    with_registry(|registry| registry.check_audit_log_reader(caller()))?;
+    Ok(AUDIT_LOG.with(|log| log.page(offset, limit)))
+}
+
+#[update]
+fn set_audit_log_public(public: bool) -> Result<(), RegistryError> {
+    audited("set_audit_log_public", public.to_string(), || {
+        with_registry_mut(|registry| registry.set_audit_log_public(caller(), public))
     })
 }
 
+#[query]
+fn get_approved_short_users() -> Vec<Principal> {
+    with_registry(|registry| registry.approved_short_users.iter().cloned().collect())
+}
+
 #[query]
 fn get_short_name_mode() -> RegistrationMode {
-    SHORT_NAME_MODE.with(|mode| mode.borrow().clone())
+    with_registry(|registry| registry.short_name_mode.clone())
 }
 
 #[update]
-fn create_registration_season(request: CreateSeasonRequest) -> Result<u64, String> {
-    let caller = caller();
-    
-    if !is_admin(caller) {
-        return Err("Only admins can create registration seasons".to_string());
-    }
-    
-    if request.min_letters == 0 || request.min_letters > 64 {
-        return Err("Min letters must be between 1 and 64".to_string());
-    }
-    
-    if let Some(max) = request.max_letters {
-        if max < request.min_letters || max > 64 {
-            return Err("Max letters must be >= min letters and <= 64".to_string());
-        }
-    }
-    
-    if request.total_allowed == 0 {
-        return Err("Total allowed must be greater than 0".to_string());
-    }
-    
-    if request.price_icp == 0 {
-        return Err("Price must be greater than 0".to_string());
-    }
-    
-    // Check if there's already an active season
-    if has_active_season() {
-        return Err("Cannot create new season: there is already an active season".to_string());
-    }
-    
-    let season_id = NEXT_SEASON_ID.with(|id| {
-        let current_id = *id.borrow();
-        *id.borrow_mut() = current_id + 1;
-        current_id
-    });
-    
-    let season = RegistrationSeason {
-        season_id,
-        min_letters: request.min_letters,
-        max_letters: request.max_letters,
-        total_allowed: request.total_allowed,
-        registered_count: 0,
-        price_icp: request.price_icp,
-        created_by: caller,
-        created_at: time(),
-        status: SeasonStatus::Active,
-    };
-    
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow_mut().insert(season_id, season);
-    });
-    
-    Ok(season_id)
+fn create_registration_season(request: CreateSeasonRequest) -> Result<u64, RegistryError> {
+    let args = AdminAction::CreateSeason(request.clone()).summary();
+    audited("create_registration_season", args, || {
+        with_registry_mut(|registry| registry.create_season(caller(), request, time()))
+    })
 }
 
 #[update]
-fn deactivate_season(season_id: u64) -> Result<(), String> {
-    let caller = caller();
-    
-    if !is_admin(caller) {
-        return Err("Only admins can deactivate seasons".to_string());
-    }
-    
-    REGISTRATION_SEASONS.with(|seasons| {
-        let mut seasons_ref = seasons.borrow_mut();
-        match seasons_ref.get_mut(&season_id) {
-            Some(season) => {
-                season.status = SeasonStatus::Deactivated;
-                Ok(())
-            }
-            None => Err("Season not found".to_string())
-        }
+fn deactivate_season(season_id: u64) -> Result<(), RegistryError> {
+    audited("deactivate_season", season_id.to_string(), || {
+        with_registry_mut(|registry| registry.deactivate_season(caller(), season_id))
     })
 }
 
 #[query]
 fn get_registration_season(season_id: u64) -> Option<RegistrationSeason> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow().get(&season_id).cloned()
-    })
+    with_registry(|registry| registry.seasons.get(&season_id).cloned())
 }
 
 #[query]
 fn get_active_seasons() -> Vec<RegistrationSeason> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
+    with_registry(|registry| {
+        registry.seasons
             .values()
             .filter(|season| matches!(season.status, SeasonStatus::Active))
             .cloned()
@@ -1090,218 +1983,321 @@ fn get_active_seasons() -> Vec<RegistrationSeason> {
 
 #[query]
 fn get_all_seasons() -> Vec<RegistrationSeason> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .cloned()
-            .collect()
-    })
+    with_registry(|registry| registry.seasons.values().cloned().collect())
 }
 
 #[query]
 fn get_applicable_season_for_domain(domain_name: String) -> Option<RegistrationSeason> {
-    find_applicable_season(&domain_name).map(|(_, season)| season)
+    with_registry(|registry| registry.find_applicable_season(&domain_name).map(|(_, season)| season))
 }
 
 #[query]
 fn get_season_stats(season_id: u64) -> Option<SeasonStats> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow().get(&season_id).map(|season| {
-            SeasonStats {
-                season_number: season.season_id,
-                names_available: season.total_allowed,
-                names_taken: season.registered_count,
-                price_icp: season.price_icp,
-                status: season.status.clone(),
-            }
-        })
-    })
+    with_registry(|registry| registry.seasons.get(&season_id).map(season_stats))
 }
 
 #[query]
 fn get_season_by_number(season_number: u64) -> Option<RegistrationSeason> {
-    if season_number == 0 {
-        // Return the latest season (highest ID)
-        REGISTRATION_SEASONS.with(|seasons| {
-            seasons.borrow()
-                .values()
-                .max_by_key(|season| season.season_id)
-                .cloned()
-        })
-    } else {
-        // Return specific season by ID
-        REGISTRATION_SEASONS.with(|seasons| {
-            seasons.borrow().get(&season_number).cloned()
-        })
-    }
+    // 0 selects the latest season
+    with_registry(|registry| registry.season_by_number(season_number).cloned())
 }
 
 #[query]
 fn get_season_stats_by_number(season_number: u64) -> Option<SeasonStats> {
-    if let Some(season) = get_season_by_number(season_number) {
-        Some(SeasonStats {
-            season_number: season.season_id,
-            names_available: season.total_allowed,
-            names_taken: season.registered_count,
-            price_icp: season.price_icp,
-            status: season.status.clone(),
-        })
-    } else {
-        None
-    }
+    with_registry(|registry| registry.season_by_number(season_number).map(season_stats))
 }
 
 #[query]
 fn get_all_season_stats() -> Vec<SeasonStats> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .map(|season| SeasonStats {
-                season_number: season.season_id,
-                names_available: season.total_allowed,
-                names_taken: season.registered_count,
-                price_icp: season.price_icp,
-                status: season.status.clone(),
-            })
-            .collect()
-    })
+    with_registry(|registry| registry.seasons.values().map(season_stats).collect())
 }
 
 #[query]
 fn get_current_season() -> Option<RegistrationSeason> {
-    REGISTRATION_SEASONS.with(|seasons| {
-        seasons.borrow()
-            .values()
-            .find(|season| matches!(season.status, SeasonStatus::Active))
+    with_registry(|registry| registry.active_season().cloned())
+}
+
+#[query]
+fn get_wallet_domain(wallet: Principal) -> Option<String> {
+    with_registry(|registry| registry.wallet_domain(wallet))
+}
+
+#[query]
+fn get_wallet_domains(wallet: Principal) -> Vec<String> {
+    with_registry(|registry| registry.wallet_domains(wallet))
+}
+
+/// The wallet's own limit, or None when it is unlimited.
+#[query]
+fn get_wallet_limit(wallet: Principal) -> Option<u64> {
+    with_registry(|registry| registry.wallet_limit(wallet))
+}
+
+#[update]
+fn set_max_domains_per_wallet(limit: u64) -> Result<(), RegistryError> {
+    audited("set_max_domains_per_wallet", limit.to_string(), || {
+        with_registry_mut(|registry| registry.set_max_domains_per_wallet(caller(), limit))
+    })
+}
+
+#[update]
+fn set_wallet_limit(wallet: Principal, limit: Option<u64>) -> Result<(), RegistryError> {
+    audited("set_wallet_limit", format!("{} to {:?}", wallet, limit), || {
+        with_registry_mut(|registry| registry.set_wallet_limit(caller(), wallet, limit))
+    })
+}
+
+#[update]
+fn set_season_wallet_cap(season_id: u64, cap: Option<u64>) -> Result<(), RegistryError> {
+    audited("set_season_wallet_cap", format!("season {} to {:?}", season_id, cap), || {
+        with_registry_mut(|registry| registry.set_season_wallet_cap(caller(), season_id, cap))
+    })
+}
+
+#[update]
+fn set_domain_administrator(domain_name: String, administrator: Principal) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_domain_administrator(caller(), &domain_name, administrator, time()))
+}
+
+#[update]
+fn set_domain_operator(domain_name: String, operator: Principal) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_domain_operator(caller(), &domain_name, operator, time()))
+}
+
+// Points the name at an identity or a canister; None points it back at the owner
+#[update]
+fn set_domain_target(domain_name: String, target: Option<Principal>) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_domain_target(caller(), &domain_name, target, time()))
+}
+
+#[update]
+fn grant_domain_permissions(
+    domain_name: String,
+    grantee: Grantee,
+    permissions: Vec<Permission>,
+) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.grant_permissions(caller(), &domain_name, grantee, permissions, time()))
+}
+
+#[update]
+fn revoke_domain_permissions(
+    domain_name: String,
+    grantee: Grantee,
+    permissions: Vec<Permission>,
+) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.revoke_permissions(caller(), &domain_name, grantee, permissions, time()))
+}
+
+#[query]
+fn get_domain_permissions(domain_name: String) -> PermissionMatrix {
+    with_registry(|registry| registry.domain_permissions(&domain_name))
+}
+
+/// What `principal` may do on the domain; everything for its owner.
+#[query]
+fn get_permissions_of(domain_name: String, principal: Principal) -> Vec<Permission> {
+    with_registry(|registry| registry.permissions_of(&domain_name, principal))
+}
+
+// Ownership only moves once the recipient accepts
+#[update]
+fn propose_domain_transfer(
+    domain_name: String,
+    new_owner: Principal,
+    window_ns: Option<u64>,
+) -> Result<PendingTransfer, RegistryError> {
+    with_registry_mut(|registry| registry.propose_transfer(caller(), &domain_name, new_owner, window_ns, time()))
+}
+
+#[update]
+fn accept_domain_transfer(domain_name: String) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.accept_transfer(caller(), &domain_name, time()))
+}
+
+#[update]
+fn cancel_domain_transfer(domain_name: String) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.cancel_transfer(caller(), &domain_name))
+}
+
+// Transfers the caller proposed or was offered
+#[query]
+fn get_pending_transfers() -> Vec<PendingTransfer> {
+    with_registry(|registry| registry.transfers_involving(caller(), time()))
+}
+
+#[query]
+fn get_pending_transfer(domain_name: String) -> Option<PendingTransfer> {
+    let current_time = time();
+    with_registry(|registry| {
+        registry.pending_transfers
+            .get(&domain_name)
+            .filter(|transfer| transfer.expires_at > current_time)
             .cloned()
     })
 }
 
+#[update]
+fn set_subdomain_policy(parent: String, policy: SubdomainPolicy) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_subdomain_policy(caller(), &parent, policy))
+}
+
 #[query]
-fn get_wallet_domain(wallet: Principal) -> Option<String> {
-    wallet_already_has_domain(wallet)
+fn get_subdomain_policy(parent: String) -> SubdomainPolicy {
+    with_registry(|registry| registry.subdomain_policy(&parent))
 }
 
+/// Returns the full name of the new subdomain.
 #[update]
-fn transfer_domain_ownership(domain_name: String, new_owner: Principal) -> Result<(), String> {
-    let caller = caller();
-    
-    // Get the current domain record
-    let mut domain_record = DOMAINS.with(|domains| {
-        domains.borrow().get(&domain_name).cloned()
-    }).ok_or("Domain not found")?;
-    
-    // Check authorization - only current owner or administrator can transfer
-    if caller != domain_record.owner && caller != domain_record.administrator {
-        return Err("Unauthorized: only domain owner or administrator can transfer ownership".to_string());
-    }
-    
-    // Check if new owner already has a domain (unless admin)
-    if !is_admin(new_owner) {
-        if let Some(existing_domain) = wallet_already_has_domain(new_owner) {
-            return Err(format!("New owner already owns domain: {}", existing_domain));
-        }
-    }
-    
-    let old_owner = domain_record.owner;
-    
-    // Update domain record
-    domain_record.owner = new_owner;
-    
-    // Save updated domain record
-    DOMAINS.with(|domains| {
-        domains.borrow_mut().insert(domain_name.clone(), domain_record);
-    });
-    
-    // Update wallet-to-domain mappings
-    WALLET_TO_DOMAIN.with(|mapping| {
-        let mut map = mapping.borrow_mut();
-        // Remove old owner's mapping
-        map.remove(&old_owner);
-        // Add new owner's mapping
-        map.insert(new_owner, domain_name);
-    });
-    
-    Ok(())
+async fn mint_subdomain(request: MintSubdomainRequest) -> Result<String, RegistryError> {
+    let state = REGISTRY.with(Rc::clone);
+    ic_environment().mint_subdomain(&state, caller(), request).await
+}
+
+#[query]
+fn get_subdomains(parent: String) -> Vec<DomainInfo> {
+    let current_time = time();
+    with_registry(|registry| {
+        registry.subdomains_of(&parent, current_time)
+            .iter()
+            .filter_map(|(name, _)| subdomain_info(registry, name, current_time))
+            .collect()
+    })
+}
+
+#[update]
+fn set_subdomain_target(name: String, target: Option<Principal>) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_subdomain_target(caller(), &name, target, time()))
+}
+
+#[update]
+fn set_subdomain_operator(name: String, operator: Principal) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.set_subdomain_operator(caller(), &name, operator, time()))
+}
+
+#[update]
+fn transfer_subdomain(name: String, new_owner: Principal) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.transfer_subdomain(caller(), &name, new_owner, time()))
+}
+
+/// Releases the caller's subdomain, or revokes one under the caller's domain.
+#[update]
+fn remove_subdomain(name: String) -> Result<(), RegistryError> {
+    with_registry_mut(|registry| registry.remove_subdomain(caller(), &name, time()))
 }
 
 #[query]
 fn get_domains_since_timestamp(timestamp: u64) -> Vec<(String, DomainInfo)> {
-    DOMAINS.with(|domains| {
-        domains.borrow()
+    let current_time = time();
+    with_registry(|registry| {
+        registry.domains
             .iter()
             .filter(|(_, record)| record.registration_time > timestamp)
-            .map(|(name, record)| {
-                let mcp_endpoint = record.custom_mcp_endpoint.clone()
-                    .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", name));
-                
-                let status = if record.expiration_time > time() {
-                    DomainStatus::Active
-                } else {
-                    DomainStatus::Expired
-                };
-                
-                let info = DomainInfo {
-                    name: name.clone(),
-                    owner: record.owner,
-                    administrator: record.administrator,
-                    operator: record.operator,
-                    canister_id: record.canister_id,
-                    expiration_time: record.expiration_time,
-                    mcp_endpoint,
-                    status,
-                    was_gifted: record.was_gifted,
-                };
-                
-                (name.clone(), info)
-            })
+            .map(|(name, record)| (name.clone(), build_domain_info(name, record, current_time)))
             .collect()
     })
 }
 
 #[query]
 fn get_all_domains_with_timestamps() -> Vec<(String, u64, DomainInfo)> {
-    DOMAINS.with(|domains| {
-        domains.borrow()
+    let current_time = time();
+    with_registry(|registry| {
+        registry.domains
             .iter()
             .map(|(name, record)| {
-                let mcp_endpoint = record.custom_mcp_endpoint.clone()
-                    .unwrap_or_else(|| format!("https://mcp.ctx.xyz/{}", name));
-                
-                let status = if record.expiration_time > time() {
-                    DomainStatus::Active
-                } else {
-                    DomainStatus::Expired
-                };
-                
-                let info = DomainInfo {
-                    name: name.clone(),
-                    owner: record.owner,
-                    administrator: record.administrator,
-                    operator: record.operator,
-                    canister_id: record.canister_id,
-                    expiration_time: record.expiration_time,
-                    mcp_endpoint,
-                    status,
-                    was_gifted: record.was_gifted,
-                };
-                
-                (name.clone(), record.registration_time, info)
+                (name.clone(), record.registration_time, build_domain_info(name, record, current_time))
             })
             .collect()
     })
 }
 
-// Helper function to create domain canister (simplified for now)
-async fn create_domain_canister(
-    _domain_name: &str,
-    _owner: Principal,
-    _administrator: Principal,
-    _operator: Principal,
-) -> Result<Principal, String> {
-    // For testing, return a dummy principal instead of creating actual canister
-    // This avoids the cycles issue
-    Ok(Principal::from_text("aaaaa-aa").unwrap())
+#[query]
+fn get_names_between(start: u64, end: u64, offset: u64, limit: u64) -> DomainPage {
+    paginate_domains(
+        |record| record.registration_time >= start && record.registration_time <= end,
+        |record| record.registration_time,
+        offset,
+        limit,
+    )
+}
+
+#[query]
+fn get_names_modified_since(timestamp: u64, offset: u64, limit: u64) -> DomainPage {
+    paginate_domains(
+        |record| record.last_modified > timestamp,
+        |record| record.last_modified,
+        offset,
+        limit,
+    )
+}
+
+// Collects matching domains ordered by `sort_key` (ties broken by name) and returns one page
+fn paginate_domains<F, K>(filter: F, sort_key: K, offset: u64, limit: u64) -> DomainPage
+where
+    F: Fn(&DomainRecord) -> bool,
+    K: Fn(&DomainRecord) -> u64,
+{
+    let limit = limit.clamp(1, MAX_PAGE_SIZE);
+    let current_time = time();
+    
+    with_registry(|registry| {
+        let domains = &registry.domains;
+        let mut matching: Vec<(&String, &DomainRecord)> = domains
+            .iter()
+            .filter(|(_, record)| filter(record))
+            .collect();
+        matching.sort_by(|(a_name, a), (b_name, b)| {
+            sort_key(a).cmp(&sort_key(b)).then_with(|| a_name.cmp(b_name))
+        });
+        
+        let total = matching.len() as u64;
+        let page: Vec<DomainInfo> = matching
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|(name, record)| build_domain_info(name, record, current_time))
+            .collect();
+        
+        let next = offset + page.len() as u64;
+        DomainPage {
+            domains: page,
+            total,
+            next_offset: if next < total { Some(next) } else { None },
+        }
+    })
 }
 
-ic_cdk::export_candid!();
\ No newline at end of file
+ic_cdk::export_candid!();
+#[cfg(test)]
+mod candid_interface {
+    use candid_parser::utils::{service_compatible, service_equal, CandidSource};
+    use std::path::PathBuf;
+
+    fn interface_file(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/registry_backend").join(name)
+    }
+
+    // The interface of the last release, which deployed clients are built against. Only a
+    // release replaces it, by copying the working interface over it.
+    #[test]
+    fn implementation_is_compatible_with_the_released_interface() {
+        let generated = super::__export_service();
+        let released = interface_file("registry_backend.released.did");
+        service_compatible(CandidSource::Text(&generated), CandidSource::File(&released))
+            .unwrap_or_else(|e| panic!("implementation breaks the released Candid interface: {:#}", e));
+    }
+
+    // Run with UPDATE_CANDID=1 to rewrite the working interface after an intended change
+    #[test]
+    fn working_interface_matches_implementation() {
+        let generated = super::__export_service();
+        let path = interface_file("registry_backend.did");
+        if std::env::var_os("UPDATE_CANDID").is_some() {
+            std::fs::write(&path, &generated).expect("failed to write the interface file");
+        }
+
+        service_equal(CandidSource::Text(&generated), CandidSource::File(&path))
+            .unwrap_or_else(|e| {
+                panic!("registry_backend.did is out of date, rerun with UPDATE_CANDID=1: {:#}", e)
+            });
+    }
+}
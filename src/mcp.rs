@@ -0,0 +1,339 @@
+// src/mcp.rs - MCP server manifests: parsing and validation against the MCP schema
+use candid::CandidType;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashSet;
+
+const MAX_MANIFEST_BYTES: usize = 64 * 1024;
+const MAX_MANIFEST_ENTRIES: usize = 256;
+const MAX_NAME_LENGTH: usize = 128;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct McpManifest {
+    pub protocol_version: Option<String>,
+    pub tools: Vec<McpTool>,
+    pub resources: Vec<McpResource>,
+    pub prompts: Vec<McpPrompt>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct McpTool {
+    pub name: String,
+    pub description: Option<String>,
+    pub input_schema: String, // JSON Schema, serialized
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct McpResource {
+    pub uri: String,
+    pub name: String,
+    pub description: Option<String>,
+    pub mime_type: Option<String>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct McpPrompt {
+    pub name: String,
+    pub description: Option<String>,
+    pub arguments: Vec<McpPromptArgument>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct McpPromptArgument {
+    pub name: String,
+    pub description: Option<String>,
+    pub required: bool,
+}
+
+// Wire format, as returned by an MCP server's initialize / tools/list / resources/list / prompts/list
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireManifest {
+    protocol_version: Option<String>,
+    #[serde(default)]
+    tools: Vec<WireTool>,
+    #[serde(default)]
+    resources: Vec<WireResource>,
+    #[serde(default)]
+    prompts: Vec<WirePrompt>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireTool {
+    name: String,
+    description: Option<String>,
+    input_schema: Value,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct WireResource {
+    uri: String,
+    name: String,
+    description: Option<String>,
+    mime_type: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct WirePrompt {
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    arguments: Vec<WirePromptArgument>,
+}
+
+#[derive(Deserialize)]
+struct WirePromptArgument {
+    name: String,
+    description: Option<String>,
+    #[serde(default)]
+    required: bool,
+}
+
+/// Parses a manifest in MCP wire format (camelCase JSON) and validates it.
+pub fn parse_manifest(json: &str) -> Result<McpManifest, String> {
+    if json.len() > MAX_MANIFEST_BYTES {
+        return Err("Manifest too large".to_string());
+    }
+
+    let wire: WireManifest = serde_json::from_str(json)
+        .map_err(|e| format!("Manifest is not valid MCP JSON: {}", e))?;
+
+    if wire.tools.len() > MAX_MANIFEST_ENTRIES
+        || wire.resources.len() > MAX_MANIFEST_ENTRIES
+        || wire.prompts.len() > MAX_MANIFEST_ENTRIES
+    {
+        return Err(format!("Manifest lists more than {} tools, resources or prompts", MAX_MANIFEST_ENTRIES));
+    }
+
+    let mut tool_names = HashSet::new();
+    let mut tools = Vec::with_capacity(wire.tools.len());
+    for tool in wire.tools {
+        validate_name("Tool", &tool.name)?;
+        if !tool_names.insert(tool.name.clone()) {
+            return Err(format!("Duplicate tool name '{}'", tool.name));
+        }
+        validate_input_schema(&tool.name, &tool.input_schema)?;
+        tools.push(McpTool {
+            name: tool.name,
+            description: tool.description,
+            input_schema: tool.input_schema.to_string(),
+        });
+    }
+
+    let mut resource_uris = HashSet::new();
+    let mut resources = Vec::with_capacity(wire.resources.len());
+    for resource in wire.resources {
+        if resource.name.trim().is_empty() {
+            return Err(format!("Resource '{}' has no name", resource.uri));
+        }
+        if !has_uri_scheme(&resource.uri) {
+            return Err(format!("Resource URI '{}' must include a scheme", resource.uri));
+        }
+        if !resource_uris.insert(resource.uri.clone()) {
+            return Err(format!("Duplicate resource URI '{}'", resource.uri));
+        }
+        resources.push(McpResource {
+            uri: resource.uri,
+            name: resource.name,
+            description: resource.description,
+            mime_type: resource.mime_type,
+        });
+    }
+
+    let mut prompt_names = HashSet::new();
+    let mut prompts = Vec::with_capacity(wire.prompts.len());
+    for prompt in wire.prompts {
+        validate_name("Prompt", &prompt.name)?;
+        if !prompt_names.insert(prompt.name.clone()) {
+            return Err(format!("Duplicate prompt name '{}'", prompt.name));
+        }
+        let mut argument_names = HashSet::new();
+        for argument in &prompt.arguments {
+            if argument.name.trim().is_empty() || !argument_names.insert(argument.name.as_str()) {
+                return Err(format!("Prompt '{}' has an empty or duplicate argument name", prompt.name));
+            }
+        }
+        prompts.push(McpPrompt {
+            name: prompt.name,
+            description: prompt.description,
+            arguments: prompt.arguments
+                .into_iter()
+                .map(|argument| McpPromptArgument {
+                    name: argument.name,
+                    description: argument.description,
+                    required: argument.required,
+                })
+                .collect(),
+        });
+    }
+
+    Ok(McpManifest {
+        protocol_version: wire.protocol_version,
+        tools,
+        resources,
+        prompts,
+    })
+}
+
+fn validate_name(kind: &str, name: &str) -> Result<(), String> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LENGTH
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '.'));
+    if valid {
+        Ok(())
+    } else {
+        Err(format!(
+            "{} name '{}' must be 1-{} characters of letters, digits, '_', '-' or '.'",
+            kind, name, MAX_NAME_LENGTH
+        ))
+    }
+}
+
+// MCP requires tool input schemas to be JSON Schema objects of type "object"
+fn validate_input_schema(tool: &str, schema: &Value) -> Result<(), String> {
+    let Some(schema) = schema.as_object() else {
+        return Err(format!("Tool '{}' inputSchema must be a JSON object", tool));
+    };
+    if schema.get("type").and_then(Value::as_str) != Some("object") {
+        return Err(format!("Tool '{}' inputSchema must have type \"object\"", tool));
+    }
+
+    let properties = match schema.get("properties") {
+        None => None,
+        Some(Value::Object(properties)) => Some(properties),
+        Some(_) => return Err(format!("Tool '{}' inputSchema properties must be an object", tool)),
+    };
+    if properties.is_some_and(|p| p.values().any(|property| !property.is_object() && !property.is_boolean())) {
+        return Err(format!("Tool '{}' inputSchema properties must be schemas", tool));
+    }
+
+    if let Some(required) = schema.get("required") {
+        let Some(required) = required.as_array() else {
+            return Err(format!("Tool '{}' inputSchema required must be an array", tool));
+        };
+        for field in required {
+            let declared = field
+                .as_str()
+                .is_some_and(|field| properties.is_some_and(|p| p.contains_key(field)));
+            if !declared {
+                return Err(format!("Tool '{}' requires an undeclared property {}", tool, field));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn has_uri_scheme(uri: &str) -> bool {
+    match uri.split_once(':') {
+        Some((scheme, rest)) => {
+            !rest.is_empty()
+                && scheme.starts_with(|c: char| c.is_ascii_alphabetic())
+                && scheme.chars().all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'))
+        }
+        None => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn tool(name: &str, input_schema: Value) -> Value {
+        json!({ "name": name, "description": "Does things", "inputSchema": input_schema })
+    }
+
+    fn object_schema() -> Value {
+        json!({ "type": "object", "properties": { "query": { "type": "string" } }, "required": ["query"] })
+    }
+
+    fn parse(manifest: Value) -> Result<McpManifest, String> {
+        parse_manifest(&manifest.to_string())
+    }
+
+    #[test]
+    fn valid_manifests_are_stored_in_registry_form() {
+        let manifest = parse(json!({
+            "protocolVersion": "2025-06-18",
+            "tools": [tool("search", object_schema()), tool("ping", json!({ "type": "object" }))],
+            "resources": [{ "uri": "file:///docs/readme.md", "name": "Readme", "mimeType": "text/markdown" }],
+            "prompts": [{ "name": "summarize", "arguments": [{ "name": "topic", "required": true }] }],
+        }))
+        .unwrap();
+
+        assert_eq!(manifest.protocol_version.as_deref(), Some("2025-06-18"));
+        assert_eq!(manifest.tools.len(), 2);
+        assert_eq!(serde_json::from_str::<Value>(&manifest.tools[0].input_schema).unwrap(), object_schema());
+        assert_eq!(manifest.resources[0].mime_type.as_deref(), Some("text/markdown"));
+        assert!(manifest.prompts[0].arguments[0].required);
+    }
+
+    #[test]
+    fn tool_names_must_be_unique_and_well_formed() {
+        let duplicate = parse(json!({ "tools": [tool("search", object_schema()), tool("search", object_schema())] }));
+        assert_eq!(duplicate.unwrap_err(), "Duplicate tool name 'search'");
+
+        assert!(parse(json!({ "tools": [tool("", object_schema())] })).is_err());
+        assert!(parse(json!({ "tools": [tool("web search", object_schema())] })).is_err());
+        assert!(parse(json!({ "tools": [tool(&"a".repeat(MAX_NAME_LENGTH + 1), object_schema())] })).is_err());
+    }
+
+    #[test]
+    fn input_schemas_must_be_object_schemas() {
+        let not_object = parse(json!({ "tools": [tool("search", json!("string"))] }));
+        assert_eq!(not_object.unwrap_err(), "Tool 'search' inputSchema must be a JSON object");
+
+        let wrong_type = parse(json!({ "tools": [tool("search", json!({ "type": "array" }))] }));
+        assert!(wrong_type.unwrap_err().contains("must have type \"object\""));
+
+        let bad_properties = json!({ "type": "object", "properties": { "query": 5 } });
+        assert!(parse(json!({ "tools": [tool("search", bad_properties)] })).is_err());
+
+        let missing_tool_schema = parse(json!({ "tools": [{ "name": "search" }] }));
+        assert!(missing_tool_schema.unwrap_err().starts_with("Manifest is not valid MCP JSON"));
+    }
+
+    #[test]
+    fn required_properties_must_be_declared() {
+        let undeclared = json!({ "type": "object", "properties": { "query": {} }, "required": ["limit"] });
+        let error = parse(json!({ "tools": [tool("search", undeclared)] })).unwrap_err();
+        assert_eq!(error, "Tool 'search' requires an undeclared property \"limit\"");
+
+        let no_properties = json!({ "type": "object", "required": ["query"] });
+        assert!(parse(json!({ "tools": [tool("search", no_properties)] })).is_err());
+
+        let not_array = json!({ "type": "object", "properties": { "query": {} }, "required": "query" });
+        assert!(parse(json!({ "tools": [tool("search", not_array)] })).is_err());
+    }
+
+    #[test]
+    fn resource_uris_need_a_scheme() {
+        for uri in ["docs/readme.md", "://docs", "1file:readme", "file:"] {
+            let manifest = json!({ "resources": [{ "uri": uri, "name": "Readme" }] });
+            assert!(parse(manifest).is_err(), "{} should be rejected", uri);
+        }
+        assert!(parse(json!({ "resources": [{ "uri": "postgres://db/table", "name": "Table" }] })).is_ok());
+
+        let duplicate = json!({ "resources": [
+            { "uri": "file:///a", "name": "A" },
+            { "uri": "file:///a", "name": "B" },
+        ] });
+        assert_eq!(parse(duplicate).unwrap_err(), "Duplicate resource URI 'file:///a'");
+    }
+
+    #[test]
+    fn manifests_are_limited_in_size_and_entries() {
+        let padded = json!({ "protocolVersion": "x".repeat(MAX_MANIFEST_BYTES) });
+        assert_eq!(parse(padded).unwrap_err(), "Manifest too large");
+
+        let prompts: Vec<Value> = (0..=MAX_MANIFEST_ENTRIES).map(|i| json!({ "name": format!("p{}", i) })).collect();
+        let error = parse(json!({ "prompts": prompts })).unwrap_err();
+        assert!(error.starts_with("Manifest lists more than 256"));
+
+        let prompts: Vec<Value> = (0..MAX_MANIFEST_ENTRIES).map(|i| json!({ "name": format!("p{}", i) })).collect();
+        assert_eq!(parse(json!({ "prompts": prompts })).unwrap().prompts.len(), MAX_MANIFEST_ENTRIES);
+    }
+}
@@ -0,0 +1,60 @@
+// src/memory.rs - Stable memory layout
+//
+// Stable memory is split by a MemoryManager into virtual memories: one holding the heap state
+// serialized across upgrades, and two backing the audit log, which lives in stable memory
+// permanently.
+use ic_stable_structures::memory_manager::{MemoryId, MemoryManager, VirtualMemory};
+use ic_stable_structures::reader::Reader;
+use ic_stable_structures::writer::Writer;
+use ic_stable_structures::DefaultMemoryImpl;
+
+pub type Memory = VirtualMemory<DefaultMemoryImpl>;
+
+pub const UPGRADE_STATE: MemoryId = MemoryId::new(0);
+pub const AUDIT_LOG_INDEX: MemoryId = MemoryId::new(1);
+pub const AUDIT_LOG_DATA: MemoryId = MemoryId::new(2);
+
+thread_local! {
+    static MEMORY_MANAGER: MemoryManager<DefaultMemoryImpl> = MemoryManager::init(DefaultMemoryImpl::default());
+}
+
+pub fn get(id: MemoryId) -> Memory {
+    MEMORY_MANAGER.with(|manager| manager.get(id))
+}
+
+/// Overwrites the memory with a length-prefixed blob.
+pub fn save_blob(memory: &mut impl ic_stable_structures::Memory, bytes: &[u8]) -> Result<(), String> {
+    let mut writer = Writer::new(memory, 0);
+    writer.write(&(bytes.len() as u64).to_le_bytes())
+        .and_then(|_| writer.write(bytes))
+        .map_err(|e| format!("{:?}", e))
+}
+
+/// The blob written by `save_blob`, or None when the memory is empty.
+pub fn load_blob(memory: &impl ic_stable_structures::Memory) -> Option<Vec<u8>> {
+    if memory.size() == 0 {
+        return None;
+    }
+    let mut reader = Reader::new(memory, 0);
+    let mut length = [0; 8];
+    reader.read(&mut length).ok()?;
+    let mut bytes = vec![0; u64::from_le_bytes(length) as usize];
+    reader.read(&mut bytes).ok()?;
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ic_stable_structures::VectorMemory;
+
+    #[test]
+    fn blobs_round_trip_and_shrink() {
+        let mut memory = VectorMemory::default();
+        assert_eq!(load_blob(&memory), None);
+
+        save_blob(&mut memory, &[7; 100]).unwrap();
+        save_blob(&mut memory, b"state").unwrap();
+        assert_eq!(load_blob(&memory), Some(b"state".to_vec()));
+    }
+}
@@ -0,0 +1,1791 @@
+// src/registry.rs - Registry state and business rules, independent of the IC runtime
+use candid::Principal;
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
+
+use crate::error::RegistryError;
+use crate::services::{Clock, Ledger, Management};
+use crate::{
+    AdminCreateDomainRequest, AdminGiftRequest, CreateSeasonRequest, DomainRecord, RegistrationMode,
+    RegistrationReceipt, RegistrationRequest, RegistrationSeason, RevealRequest, SeasonStats, SeasonStatus,
+};
+
+mod auction;
+mod governance;
+mod market;
+mod permissions;
+mod reserved;
+mod roles;
+mod subdomain;
+mod transfer;
+
+pub use auction::{Auction, AuctionInfo, Bid, CreateAuctionRequest, Payout, PlaceBidRequest, RevealBidRequest};
+pub use governance::{describe_wasm, AdminAction, Proposal, ProposalInfo, ProposalPage};
+pub use market::{BuyDomainRequest, ListDomainRequest, Listing, MakeOfferRequest, Offer, Sale, SalePage};
+pub use permissions::{Grantee, Permission, PermissionMatrix};
+pub use reserved::{ReservedImport, ReservedNamePage};
+pub use roles::AdminRole;
+pub use subdomain::{split_subdomain, MintSubdomainRequest, SubdomainPolicy, SubdomainRecord};
+pub use transfer::PendingTransfer;
+
+pub const REGISTRATION_PERIOD_NS: u64 = 365 * 24 * 60 * 60 * 1_000_000_000; // 1 year
+pub const E8S_PER_ICP: u64 = 100_000_000;
+// Registrations normally finish within seconds; one still reserved after this long
+// trapped after its first await and will never complete.
+pub const RESERVATION_TIMEOUT_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+// A commitment can be revealed once it has been on chain long enough that the name it
+// hides could not have been read from the same round of messages.
+pub const MIN_COMMITMENT_AGE_NS: u64 = 60 * 1_000_000_000; // 1 minute
+pub const MAX_COMMITMENT_AGE_NS: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours
+pub const MAX_COMMITMENTS_PER_CALLER: usize = 10;
+pub const DEFAULT_MAX_DOMAINS_PER_WALLET: u64 = 1;
+
+const DEFAULT_RESERVED_NAMES: &[&str] = &[
+    "icp", "api", "www", "admin", "root", "system", "registry", "canister", "dfinity", "ic",
+];
+
+pub fn season_stats(season: &RegistrationSeason) -> SeasonStats {
+    SeasonStats {
+        season_number: season.season_id,
+        names_available: season.total_allowed,
+        names_taken: season.registered_count,
+        price_icp: season.price_icp,
+        status: season.status.clone(),
+    }
+}
+
+/// The hash committed to by `commit_registration`: SHA-256 over the domain name, a zero
+/// byte, the owner's principal bytes and the salt.
+pub fn registration_commitment(domain_name: &str, owner: Principal, salt: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(domain_name.as_bytes());
+    hasher.update([0u8]);
+    hasher.update(owner.as_slice());
+    hasher.update(salt);
+    hasher.finalize().to_vec()
+}
+
+pub fn is_valid_domain_name(name: &str) -> bool {
+    if name.is_empty() || name.len() > 63 {
+        return false;
+    }
+
+    if name.starts_with('-') || name.ends_with('-') {
+        return false;
+    }
+
+    name.chars().all(|c| c.is_alphanumeric() || c == '-')
+}
+
+pub struct Registry {
+    pub domains: HashMap<String, DomainRecord>,
+    // Lowercased exact names, and `*` patterns over lowercased names
+    pub reserved_names: HashSet<String>,
+    pub reserved_patterns: BTreeSet<String>,
+    pub admins: HashSet<Principal>, // SuperAdmins
+    // Narrower admin roles, for principals that are not SuperAdmins
+    pub admin_roles: HashMap<Principal, BTreeSet<AdminRole>>,
+    // Whether callers without an admin role may read the audit log
+    pub audit_log_public: bool,
+    pub short_name_mode: RegistrationMode,
+    pub approved_short_users: HashSet<Principal>,
+    pub base_fee: u64,
+    // Ledger that payments are checked on and payouts are sent from. Without one, registration
+    // payments are recorded unchecked, and auctions, the marketplace and paid subdomains are off
+    pub ledger_canister: Option<Principal>,
+    pub domain_canister_wasm: Vec<u8>,
+    pub seasons: HashMap<u64, RegistrationSeason>,
+    pub next_season_id: u64,
+    pub next_registration_sequence: u64,
+    // Every domain each principal owns. Derived from `domains`, so not persisted
+    pub owner_index: HashMap<Principal, BTreeSet<String>>,
+    // Every suffix of every lowercased domain name, with the name it belongs to, so that
+    // substring searches are a range scan. Derived from `domains`, so not persisted
+    pub name_index: BTreeSet<(String, String)>,
+    // How many domains a wallet may own, unless overridden per principal. Admins are exempt
+    pub max_domains_per_wallet: u64,
+    pub wallet_limit_overrides: HashMap<Principal, u64>,
+    pub season_addresses: HashMap<u64, HashSet<String>>,
+    // Registrations between their first await and completion, keyed by domain name
+    pub reservations: HashMap<String, Reservation>,
+    pub next_reservation_id: u64,
+    // Time each open registration commitment was made, per committer
+    pub commitments: HashMap<Principal, HashMap<Vec<u8>, u64>>,
+    pub auctions: HashMap<u64, Auction>,
+    pub next_auction_id: u64,
+    // Payment blocks already credited to a bid, so that none is counted twice
+    pub used_payment_blocks: HashSet<u64>,
+    // Refunds and proceeds the registry still has to send
+    pub payouts: Vec<Payout>,
+    pub next_payout_id: u64,
+    pub listings: HashMap<String, Listing>,
+    pub offers: HashMap<u64, Offer>,
+    pub next_offer_id: u64,
+    pub sales: Vec<Sale>,
+    pub market_fee_bps: u64,
+    pub collected_fees_e8s: u64,
+    // Proposed ownership transfers awaiting the recipient, keyed by domain name
+    pub pending_transfers: HashMap<String, PendingTransfer>,
+    pub subdomains: HashMap<String, SubdomainRecord>,
+    pub subdomain_policies: HashMap<String, SubdomainPolicy>,
+    // Paid subdomain mints awaiting their payment, with the minter and when it started
+    pub pending_mints: HashMap<String, (Principal, u64)>,
+    // Domains whose owner changed the default permission matrix
+    pub permissions: HashMap<String, PermissionMatrix>,
+    // Approvals sensitive admin actions need, and the proposals made to reach them
+    pub governance_threshold: u64,
+    pub proposals: BTreeMap<u64, governance::Proposal>,
+    pub next_proposal_id: u64,
+    // Gifts approved by proposal, each good for one `admin_gift_domain` call
+    pub approved_gifts: Vec<AdminGiftRequest>,
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry {
+            domains: HashMap::new(),
+            reserved_names: HashSet::new(),
+            reserved_patterns: BTreeSet::new(),
+            admins: HashSet::new(),
+            admin_roles: HashMap::new(),
+            audit_log_public: false,
+            short_name_mode: RegistrationMode::WhitelistOnly,
+            approved_short_users: HashSet::new(),
+            base_fee: E8S_PER_ICP,
+            ledger_canister: None,
+            domain_canister_wasm: Vec::new(),
+            seasons: HashMap::new(),
+            next_season_id: 1,
+            next_registration_sequence: 1,
+            owner_index: HashMap::new(),
+            name_index: BTreeSet::new(),
+            max_domains_per_wallet: DEFAULT_MAX_DOMAINS_PER_WALLET,
+            wallet_limit_overrides: HashMap::new(),
+            season_addresses: HashMap::new(),
+            reservations: HashMap::new(),
+            next_reservation_id: 1,
+            commitments: HashMap::new(),
+            auctions: HashMap::new(),
+            next_auction_id: 1,
+            used_payment_blocks: HashSet::new(),
+            payouts: Vec::new(),
+            next_payout_id: 1,
+            listings: HashMap::new(),
+            offers: HashMap::new(),
+            next_offer_id: 1,
+            sales: Vec::new(),
+            market_fee_bps: market::DEFAULT_MARKET_FEE_BPS,
+            collected_fees_e8s: 0,
+            pending_transfers: HashMap::new(),
+            subdomains: HashMap::new(),
+            subdomain_policies: HashMap::new(),
+            pending_mints: HashMap::new(),
+            permissions: HashMap::new(),
+            governance_threshold: 1,
+            proposals: BTreeMap::new(),
+            next_proposal_id: 1,
+            approved_gifts: Vec::new(),
+        }
+    }
+}
+
+enum RegistrationKind {
+    Purchase,
+    Gift,
+    AddressCreation { address: String },
+    Auction { auction_id: u64 },
+    ReservedRelease,
+}
+
+/// Locks a name while a registration is in flight. It also counts toward its owner's
+/// wallet limit until it completes.
+#[derive(Clone, Debug)]
+pub struct Reservation {
+    pub id: u64,
+    pub owner: Principal,
+    pub season_id: Option<u64>,
+    pub started_at: u64,
+}
+
+/// A registration that passed validation and is waiting for its canister. Its reservation
+/// holds the name and season slot until it is completed, aborted or times out.
+pub struct PendingRegistration {
+    pub reservation_id: u64,
+    pub domain_name: String,
+    pub owner: Principal,
+    pub administrator: Principal,
+    pub operator: Principal,
+    pub season_id: Option<u64>,
+    pub fee_e8s: u64,
+    pub payment_block: Option<u64>,
+    kind: RegistrationKind,
+    was_gifted: bool,
+}
+
+impl Registry {
+    pub fn new(admin: Principal) -> Self {
+        let mut registry = Registry::default();
+        registry.admins.insert(admin);
+        registry.reserved_names.extend(DEFAULT_RESERVED_NAMES.iter().map(|name| name.to_string()));
+        registry
+    }
+
+    pub fn is_admin(&self, principal: Principal) -> bool {
+        self.admins.contains(&principal)
+    }
+
+    pub fn require_admin(&self, caller: Principal) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::SuperAdmin)
+    }
+
+    /// The wallet's earliest registered domain.
+    pub fn wallet_domain(&self, wallet: Principal) -> Option<String> {
+        self.owner_index
+            .get(&wallet)?
+            .iter()
+            .min_by_key(|name| self.domains.get(*name).map(|domain| domain.registration_time))
+            .cloned()
+    }
+
+    /// Every domain the wallet owns, by name.
+    pub fn wallet_domains(&self, wallet: Principal) -> Vec<String> {
+        self.owner_index.get(&wallet).map(|names| names.iter().cloned().collect()).unwrap_or_default()
+    }
+
+    /// Most domains `wallet` may own, or None when it is unlimited.
+    pub fn wallet_limit(&self, wallet: Principal) -> Option<u64> {
+        if self.is_admin(wallet) {
+            return None;
+        }
+        Some(self.wallet_limit_overrides.get(&wallet).copied().unwrap_or(self.max_domains_per_wallet))
+    }
+
+    /// Rebuilds the owner and name indexes from `domains`, after an upgrade.
+    pub fn rebuild_indexes(&mut self) {
+        self.owner_index.clear();
+        self.name_index.clear();
+        let entries: Vec<(String, Principal)> =
+            self.domains.iter().map(|(name, domain)| (name.clone(), domain.owner)).collect();
+        for (name, owner) in entries {
+            self.index_owner(&name, owner);
+            self.index_name(&name);
+        }
+    }
+
+    /// Domain names containing `pattern`, which must already be lowercased.
+    pub fn names_containing(&self, pattern: &str) -> BTreeSet<String> {
+        self.name_index
+            .range((pattern.to_string(), String::new())..)
+            .take_while(|(suffix, _)| suffix.starts_with(pattern))
+            .map(|(_, name)| name.clone())
+            .collect()
+    }
+
+    fn index_name(&mut self, domain_name: &str) {
+        let lowered = domain_name.to_lowercase();
+        for (start, _) in lowered.char_indices() {
+            self.name_index.insert((lowered[start..].to_string(), domain_name.to_string()));
+        }
+    }
+
+    fn index_owner(&mut self, domain_name: &str, owner: Principal) {
+        self.owner_index.entry(owner).or_default().insert(domain_name.to_string());
+    }
+
+    fn unindex_owner(&mut self, domain_name: &str, owner: Principal) {
+        if let Some(names) = self.owner_index.get_mut(&owner) {
+            names.remove(domain_name);
+            if names.is_empty() {
+                self.owner_index.remove(&owner);
+            }
+        }
+    }
+
+    pub fn is_available(&self, domain_name: &str, now: u64) -> bool {
+        self.domains
+            .get(domain_name)
+            .is_none_or(|domain| domain.expiration_time < now)
+            && !self.is_under_auction(domain_name)
+    }
+
+    pub fn can_register_short_domain(&self, domain_name: &str, caller: Principal) -> bool {
+        if domain_name.len() >= 5 || self.is_admin(caller) {
+            return true;
+        }
+
+        match self.short_name_mode {
+            RegistrationMode::Open => true,
+            RegistrationMode::WhitelistOnly => self.approved_short_users.contains(&caller),
+            RegistrationMode::Closed => false,
+        }
+    }
+
+    pub fn can_register(&self, domain_name: &str, user: Principal, now: u64) -> bool {
+        is_valid_domain_name(domain_name)
+            && !self.is_reserved_name(domain_name)
+            && self.is_available(domain_name, now)
+            && self.can_register_short_domain(domain_name, user)
+    }
+
+    /// Cheapest active season that covers the name's length and still has capacity.
+    pub fn find_applicable_season(&self, domain_name: &str) -> Option<(u64, RegistrationSeason)> {
+        let domain_length = domain_name.len() as u64;
+
+        self.seasons
+            .iter()
+            .filter(|(_, season)| {
+                matches!(season.status, SeasonStatus::Active)
+                    && domain_length >= season.min_letters
+                    && season.max_letters.is_none_or(|max| domain_length <= max)
+                    && season.registered_count < season.total_allowed
+            })
+            .min_by_key(|(_, season)| season.price_icp)
+            .map(|(id, season)| (*id, season.clone()))
+    }
+
+    pub fn registration_fee(&self, domain_name: &str) -> Result<u64, RegistryError> {
+        self.find_applicable_season(domain_name)
+            .map(|(_, season)| season.price_icp * E8S_PER_ICP)
+            .ok_or(RegistryError::NoSeason)
+    }
+
+    pub fn renewal_fee(&self) -> u64 {
+        self.base_fee
+    }
+
+    pub fn active_season(&self) -> Option<&RegistrationSeason> {
+        self.seasons
+            .values()
+            .find(|season| matches!(season.status, SeasonStatus::Active))
+    }
+
+    // A season is only completed once no reservation still holds one of its slots, so
+    // that an aborted registration can give its slot back.
+    fn complete_season_if_full(&mut self, season_id: u64) {
+        let in_flight = self.reservations.values().any(|reservation| reservation.season_id == Some(season_id));
+        if let Some(season) = self.seasons.get_mut(&season_id) {
+            if matches!(season.status, SeasonStatus::Active)
+                && season.registered_count >= season.total_allowed
+                && !in_flight
+            {
+                season.status = SeasonStatus::Completed;
+            }
+        }
+    }
+
+    fn reserve_slot(&mut self, season_id: u64) -> Result<(), RegistryError> {
+        let season = self.seasons.get_mut(&season_id).ok_or(RegistryError::SeasonNotFound)?;
+        if season.registered_count >= season.total_allowed {
+            return Err(RegistryError::SeasonFull);
+        }
+        season.registered_count += 1;
+        Ok(())
+    }
+
+    fn release_slot(&mut self, season_id: u64) {
+        if let Some(season) = self.seasons.get_mut(&season_id) {
+            season.registered_count -= 1;
+        }
+    }
+
+    /// Drops reservations older than `RESERVATION_TIMEOUT_NS` and gives back their slots.
+    pub fn release_stale_reservations(&mut self, now: u64) {
+        self.release_reservations_where(|reservation| {
+            now.saturating_sub(reservation.started_at) >= RESERVATION_TIMEOUT_NS
+        });
+    }
+
+    /// Drops every reservation. Calls in flight during an upgrade never resume.
+    pub fn release_all_reservations(&mut self) {
+        self.release_reservations_where(|_| true);
+        self.reopen_settling_auctions();
+        self.release_in_flight_payouts();
+    }
+
+    fn release_reservations_where(&mut self, stale: impl Fn(&Reservation) -> bool) {
+        let names: Vec<String> = self.reservations
+            .iter()
+            .filter(|(_, reservation)| stale(reservation))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for name in names {
+            if let Some(season_id) = self.reservations.remove(&name).and_then(|reservation| reservation.season_id) {
+                self.release_slot(season_id);
+            }
+        }
+    }
+
+    // Takes the name lock and the season slot for a validated registration
+    fn reserve(&mut self, mut pending: PendingRegistration, now: u64) -> Result<PendingRegistration, RegistryError> {
+        if self.reservations.contains_key(&pending.domain_name) {
+            return Err(RegistryError::RegistrationInProgress { domain: pending.domain_name.clone() });
+        }
+        self.check_wallet_capacity(pending.owner, pending.season_id)?;
+        if let Some(id) = pending.season_id {
+            self.reserve_slot(id)?;
+        }
+
+        pending.reservation_id = self.next_reservation_id;
+        self.next_reservation_id += 1;
+        self.reservations.insert(pending.domain_name.clone(), Reservation {
+            id: pending.reservation_id,
+            owner: pending.owner,
+            season_id: pending.season_id,
+            started_at: now,
+        });
+        Ok(pending)
+    }
+
+    // Removes the registration's reservation, unless it timed out and was released already
+    fn take_reservation(&mut self, pending: &PendingRegistration) -> bool {
+        let held = self.reservations
+            .get(&pending.domain_name)
+            .is_some_and(|reservation| reservation.id == pending.reservation_id);
+        if held {
+            self.reservations.remove(&pending.domain_name);
+        }
+        held
+    }
+
+    pub fn is_address_in_season(&self, season_id: u64, address: &str) -> bool {
+        self.season_addresses
+            .get(&season_id)
+            .is_some_and(|addresses| addresses.contains(address))
+    }
+
+    pub fn add_address_to_season(&mut self, caller: Principal, season_id: u64, address: String) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+
+        match self.seasons.get(&season_id).map(|season| &season.status) {
+            Some(SeasonStatus::Active) => {
+                self.season_addresses.entry(season_id).or_default().insert(address);
+                Ok(())
+            }
+            Some(SeasonStatus::Completed) | Some(SeasonStatus::Deactivated) => Err(RegistryError::SeasonNotActive),
+            None => Err(RegistryError::SeasonNotFound),
+        }
+    }
+
+    /// Sets the custom MCP endpoint of a domain or subdomain.
+    pub fn store_custom_endpoint(&mut self, name: &str, endpoint: Option<String>, now: u64) -> Result<(), RegistryError> {
+        if split_subdomain(name).is_some() {
+            return self.set_subdomain_endpoint(name, endpoint, now);
+        }
+        let record = self.domains.get_mut(name).ok_or(RegistryError::DomainNotFound)?;
+        record.custom_mcp_endpoint = endpoint;
+        record.last_modified = now;
+        Ok(())
+    }
+
+    /// Points the name at an identity or a canister. None points it back at its owner.
+    pub fn set_domain_target(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        target: Option<Principal>,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.authorize(domain_name, caller, Permission::SetEndpoint)?;
+        let record = self.domains.get_mut(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        record.target = target;
+        record.last_modified = now;
+        Ok(())
+    }
+
+    pub fn touch(&mut self, domain_name: &str, now: u64) {
+        if let Some(domain) = self.domains.get_mut(domain_name) {
+            domain.last_modified = now;
+        }
+    }
+
+    // Admin settings
+
+    // Sensitive settings below go through governance proposals once the threshold is above
+    // one; the `apply_*` halves are what an approved proposal runs.
+
+    pub fn add_admin(&mut self, caller: Principal, new_admin: Principal) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.require_single_signer()?;
+        self.apply_add_admin(new_admin)
+    }
+
+    fn apply_add_admin(&mut self, new_admin: Principal) -> Result<(), RegistryError> {
+        self.admins.insert(new_admin);
+        Ok(())
+    }
+
+    pub fn remove_admin(&mut self, caller: Principal, admin: Principal) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.require_single_signer()?;
+        self.apply_remove_admin(admin)
+    }
+
+    fn apply_remove_admin(&mut self, admin: Principal) -> Result<(), RegistryError> {
+        if self.admins.len() <= 1 {
+            return Err(RegistryError::LastAdmin);
+        }
+        if self.admins.contains(&admin) && self.admins.len() as u64 <= self.governance_threshold {
+            return Err(RegistryError::invalid_input("Lower the approval threshold before removing this admin"));
+        }
+        self.admins.remove(&admin);
+        Ok(())
+    }
+
+    pub fn approve_user_for_short_names(&mut self, caller: Principal, user: Principal) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        self.approved_short_users.insert(user);
+        Ok(())
+    }
+
+    pub fn revoke_short_name_approval(&mut self, caller: Principal, user: Principal) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        self.approved_short_users.remove(&user);
+        Ok(())
+    }
+
+    pub fn set_short_name_mode(&mut self, caller: Principal, mode: RegistrationMode) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        self.short_name_mode = mode;
+        Ok(())
+    }
+
+    pub fn set_base_fee(&mut self, caller: Principal, fee: u64) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Treasurer)?;
+        self.require_single_signer()?;
+        self.base_fee = fee;
+        Ok(())
+    }
+
+    pub fn set_ledger_canister(&mut self, caller: Principal, ledger: Option<Principal>) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Treasurer)?;
+        self.require_single_signer()?;
+        self.ledger_canister = ledger;
+        Ok(())
+    }
+
+    // Flows that hold funds in escrow only run against a real ledger
+    fn require_ledger(&self) -> Result<(), RegistryError> {
+        self.ledger_canister.map(|_| ()).ok_or(RegistryError::LedgerUnavailable)
+    }
+
+    pub fn set_domain_canister_wasm(&mut self, caller: Principal, wasm: Vec<u8>) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.require_single_signer()?;
+        self.domain_canister_wasm = wasm;
+        Ok(())
+    }
+
+    // Seasons
+
+    pub fn create_season(&mut self, caller: Principal, request: CreateSeasonRequest, now: u64) -> Result<u64, RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+        self.require_single_signer()?;
+        self.open_season(caller, request, now)
+    }
+
+    fn open_season(&mut self, created_by: Principal, request: CreateSeasonRequest, now: u64) -> Result<u64, RegistryError> {
+        if request.min_letters == 0 || request.min_letters > 64 {
+            return Err(RegistryError::invalid_input("Min letters must be between 1 and 64"));
+        }
+        if let Some(max) = request.max_letters {
+            if max < request.min_letters || max > 64 {
+                return Err(RegistryError::invalid_input("Max letters must be >= min letters and <= 64"));
+            }
+        }
+        if request.total_allowed == 0 {
+            return Err(RegistryError::invalid_input("Total allowed must be greater than 0"));
+        }
+        if request.price_icp == 0 {
+            return Err(RegistryError::invalid_input("Price must be greater than 0"));
+        }
+        if request.max_per_wallet == Some(0) {
+            return Err(RegistryError::invalid_input("Per-wallet cap must be greater than 0"));
+        }
+        // Only one season may be active at a time
+        if self.active_season().is_some() {
+            return Err(RegistryError::ActiveSeasonExists);
+        }
+
+        let season_id = self.next_season_id;
+        self.next_season_id += 1;
+        self.seasons.insert(season_id, RegistrationSeason {
+            season_id,
+            min_letters: request.min_letters,
+            max_letters: request.max_letters,
+            total_allowed: request.total_allowed,
+            registered_count: 0,
+            price_icp: request.price_icp,
+            created_by,
+            created_at: now,
+            status: SeasonStatus::Active,
+            max_per_wallet: request.max_per_wallet,
+        });
+
+        Ok(season_id)
+    }
+
+    pub fn deactivate_season(&mut self, caller: Principal, season_id: u64) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+        let season = self.seasons.get_mut(&season_id).ok_or(RegistryError::SeasonNotFound)?;
+        season.status = SeasonStatus::Deactivated;
+        Ok(())
+    }
+
+    /// Season `season_number`, or the latest season when it is 0.
+    pub fn season_by_number(&self, season_number: u64) -> Option<&RegistrationSeason> {
+        if season_number == 0 {
+            self.seasons.values().max_by_key(|season| season.season_id)
+        } else {
+            self.seasons.get(&season_number)
+        }
+    }
+
+    // Registration
+
+    fn check_name(&self, domain_name: &str, now: u64) -> Result<(), RegistryError> {
+        if !is_valid_domain_name(domain_name) {
+            return Err(RegistryError::InvalidName);
+        }
+        if self.is_reserved_name(domain_name) {
+            return Err(RegistryError::Reserved);
+        }
+        if !self.is_available(domain_name, now) {
+            return Err(RegistryError::Unavailable);
+        }
+        Ok(())
+    }
+
+    /// Checks that `wallet` may take one more domain, counting the registrations it has in
+    /// flight. With a season, the season's own per-wallet cap applies as well.
+    fn check_wallet_capacity(&self, wallet: Principal, season_id: Option<u64>) -> Result<(), RegistryError> {
+        let Some(limit) = self.wallet_limit(wallet) else {
+            return Ok(());
+        };
+        let owned = self.owner_index.get(&wallet);
+        let owned_count = owned.map_or(0, |names| names.len() as u64);
+        let in_flight: Vec<(&String, &Reservation)> = self.reservations
+            .iter()
+            .filter(|(_, reservation)| reservation.owner == wallet)
+            .collect();
+
+        if owned_count >= limit {
+            return Err(match self.wallet_domain(wallet) {
+                Some(domain) => RegistryError::WalletAlreadyOwns { domain },
+                None => RegistryError::WalletLimitReached { limit },
+            });
+        }
+        if owned_count + in_flight.len() as u64 >= limit {
+            let (name, _) = in_flight.iter().min_by_key(|(_, reservation)| reservation.id).unwrap();
+            return Err(RegistryError::RegistrationInProgress { domain: (*name).clone() });
+        }
+
+        let season_cap = season_id.and_then(|id| Some((id, self.seasons.get(&id)?.max_per_wallet?)));
+        if let Some((id, cap)) = season_cap {
+            let in_season = owned.into_iter()
+                .flatten()
+                .filter(|name| self.domains.get(*name).is_some_and(|domain| domain.registration_season_id == Some(id)))
+                .count()
+                + in_flight.iter().filter(|(_, reservation)| reservation.season_id == Some(id)).count();
+            if in_season as u64 >= cap {
+                return Err(RegistryError::WalletLimitReached { limit: cap });
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_max_domains_per_wallet(&mut self, caller: Principal, limit: u64) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.max_domains_per_wallet = limit;
+        Ok(())
+    }
+
+    /// Grants `wallet` its own limit, or with None returns it to the global one.
+    pub fn set_wallet_limit(&mut self, caller: Principal, wallet: Principal, limit: Option<u64>) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Support)?;
+        match limit {
+            Some(limit) => self.wallet_limit_overrides.insert(wallet, limit),
+            None => self.wallet_limit_overrides.remove(&wallet),
+        };
+        Ok(())
+    }
+
+    pub fn set_season_wallet_cap(&mut self, caller: Principal, season_id: u64, cap: Option<u64>) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+        let season = self.seasons.get_mut(&season_id).ok_or(RegistryError::SeasonNotFound)?;
+        season.max_per_wallet = cap;
+        Ok(())
+    }
+
+    /// Records a commitment to register a name without revealing it. Returns the time
+    /// from which it can be revealed.
+    pub fn commit_registration(&mut self, caller: Principal, commitment: Vec<u8>, now: u64) -> Result<u64, RegistryError> {
+        if commitment.len() != 32 {
+            return Err(RegistryError::InvalidInput { reason: "Commitment must be a 32-byte SHA-256 hash".to_string() });
+        }
+
+        let open = self.commitments.entry(caller).or_default();
+        open.retain(|_, created_at| now.saturating_sub(*created_at) <= MAX_COMMITMENT_AGE_NS);
+        if open.len() >= MAX_COMMITMENTS_PER_CALLER && !open.contains_key(&commitment) {
+            return Err(RegistryError::InvalidInput {
+                reason: format!("At most {} commitments can be open at once", MAX_COMMITMENTS_PER_CALLER),
+            });
+        }
+        open.insert(commitment, now);
+        Ok(now + MIN_COMMITMENT_AGE_NS)
+    }
+
+    fn check_commitment(&self, caller: Principal, commitment: &[u8], now: u64) -> Result<(), RegistryError> {
+        let created_at = *self.commitments
+            .get(&caller)
+            .and_then(|open| open.get(commitment))
+            .ok_or(RegistryError::CommitmentNotFound)?;
+        if now < created_at + MIN_COMMITMENT_AGE_NS {
+            return Err(RegistryError::CommitmentTooNew { reveal_after: created_at + MIN_COMMITMENT_AGE_NS });
+        }
+        if now - created_at > MAX_COMMITMENT_AGE_NS {
+            return Err(RegistryError::CommitmentExpired);
+        }
+        Ok(())
+    }
+
+    /// Starts the registration a caller committed to. Only the committer can reveal, and
+    /// the usual registration checks run now rather than at commit time. The commitment
+    /// is consumed once they pass.
+    pub fn begin_reveal(
+        &mut self,
+        caller: Principal,
+        request: &RevealRequest,
+        now: u64,
+    ) -> Result<PendingRegistration, RegistryError> {
+        let commitment = registration_commitment(&request.domain_name, caller, &request.salt);
+        self.check_commitment(caller, &commitment, now)?;
+
+        let registration = RegistrationRequest {
+            domain_name: request.domain_name.clone(),
+            administrator: request.administrator,
+            operator: request.operator,
+            payment_block: request.payment_block,
+        };
+        let pending = self.begin_registration(caller, &registration, now)?;
+
+        if let Some(open) = self.commitments.get_mut(&caller) {
+            open.remove(&commitment);
+            if open.is_empty() {
+                self.commitments.remove(&caller);
+            }
+        }
+        Ok(pending)
+    }
+
+    /// Validates a paid registration and reserves its season slot. Admins register for
+    /// free, outside any season, and may own several domains.
+    pub fn begin_registration(
+        &mut self,
+        caller: Principal,
+        request: &RegistrationRequest,
+        now: u64,
+    ) -> Result<PendingRegistration, RegistryError> {
+        if !is_valid_domain_name(&request.domain_name) {
+            return Err(RegistryError::InvalidName);
+        }
+        if self.is_reserved_name(&request.domain_name) {
+            return Err(RegistryError::Reserved);
+        }
+        if !self.can_register_short_domain(&request.domain_name, caller) {
+            return Err(RegistryError::ShortNameRequiresApproval);
+        }
+
+        self.release_stale_reservations(now);
+        // Above a threshold of one, free names need an approved gift instead
+        let is_admin_caller = self.is_admin(caller) && self.governance_threshold <= 1;
+        self.check_wallet_capacity(caller, None)?;
+        if !self.is_available(&request.domain_name, now) {
+            return Err(RegistryError::Unavailable);
+        }
+
+        let (season_id, fee_e8s) = if is_admin_caller {
+            (None, 0)
+        } else {
+            let (id, season) = self.find_applicable_season(&request.domain_name).ok_or(RegistryError::NoSeason)?;
+            (Some(id), season.price_icp * E8S_PER_ICP)
+        };
+
+        self.reserve(PendingRegistration {
+            reservation_id: 0,
+            domain_name: request.domain_name.clone(),
+            owner: caller,
+            administrator: request.administrator,
+            operator: request.operator,
+            season_id,
+            fee_e8s,
+            payment_block: Some(request.payment_block),
+            kind: RegistrationKind::Purchase,
+            was_gifted: is_admin_caller,
+        }, now)
+    }
+
+    pub fn begin_gift(
+        &mut self,
+        caller: Principal,
+        request: &AdminGiftRequest,
+        now: u64,
+    ) -> Result<PendingRegistration, RegistryError> {
+        self.require_role(caller, AdminRole::Support)?;
+        let approval = self.gift_approval(request)?;
+        self.release_stale_reservations(now);
+        self.check_name(&request.domain_name, now)?;
+        self.check_wallet_capacity(request.recipient, None)?;
+
+        // Gifts still consume a slot of the active season
+        let season_id = self.active_season().ok_or(RegistryError::NoSeason)?.season_id;
+
+        let pending = self.reserve(PendingRegistration {
+            reservation_id: 0,
+            domain_name: request.domain_name.clone(),
+            owner: request.recipient,
+            administrator: request.administrator,
+            operator: request.operator,
+            season_id: Some(season_id),
+            fee_e8s: 0,
+            payment_block: None,
+            kind: RegistrationKind::Gift,
+            was_gifted: true,
+        }, now)?;
+        if let Some(index) = approval {
+            self.approved_gifts.remove(index);
+        }
+        Ok(pending)
+    }
+
+    pub fn begin_address_creation(
+        &mut self,
+        caller: Principal,
+        request: &AdminCreateDomainRequest,
+        now: u64,
+    ) -> Result<PendingRegistration, RegistryError> {
+        self.require_role(caller, AdminRole::Support)?;
+        self.release_stale_reservations(now);
+        self.check_name(&request.domain_name, now)?;
+        self.check_wallet_capacity(request.recipient, None)?;
+
+        let season = self.active_season().ok_or(RegistryError::NoSeason)?;
+        let season_id = season.season_id;
+        if season.registered_count >= season.total_allowed {
+            return Err(RegistryError::SeasonFull);
+        }
+        if !self.is_address_in_season(season_id, &request.recipient_address) {
+            return Err(RegistryError::AddressNotAuthorized { address: request.recipient_address.clone() });
+        }
+
+        self.reserve(PendingRegistration {
+            reservation_id: 0,
+            domain_name: request.domain_name.clone(),
+            owner: request.recipient,
+            administrator: request.administrator,
+            operator: request.operator,
+            season_id: Some(season_id),
+            fee_e8s: 0,
+            payment_block: None,
+            kind: RegistrationKind::AddressCreation { address: request.recipient_address.clone() },
+            was_gifted: false,
+        }, now)
+    }
+
+    /// Stores the domain once its canister exists and returns the receipt. The name and
+    /// the owner's wallet are checked again, since transfers may have run meanwhile.
+    pub fn complete_registration(
+        &mut self,
+        pending: PendingRegistration,
+        canister_id: Principal,
+        now: u64,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        if !self.take_reservation(&pending) {
+            return Err(RegistryError::ReservationExpired);
+        }
+
+        let still_free = if !self.is_available(&pending.domain_name, now) {
+            Err(RegistryError::Unavailable)
+        } else {
+            self.check_wallet_capacity(pending.owner, pending.season_id)
+        };
+        if let Err(e) = still_free {
+            if let Some(id) = pending.season_id {
+                self.release_slot(id);
+            }
+            return Err(e);
+        }
+
+        if matches!(pending.kind, RegistrationKind::ReservedRelease) {
+            self.reserved_names.remove(&pending.domain_name.to_lowercase());
+        }
+
+        // The previous owner of an expired name no longer holds it
+        if let Some(previous) = self.domains.get(&pending.domain_name).map(|domain| domain.owner) {
+            self.unindex_owner(&pending.domain_name, previous);
+            self.drop_subdomains(&pending.domain_name);
+            self.permissions.remove(&pending.domain_name);
+        }
+
+        let record = DomainRecord {
+            owner: pending.owner,
+            administrator: pending.administrator,
+            operator: pending.operator,
+            canister_id,
+            registration_time: now,
+            expiration_time: now + REGISTRATION_PERIOD_NS,
+            last_modified: now,
+            last_payment_block: pending.payment_block.unwrap_or(0),
+            custom_mcp_endpoint: None,
+            was_gifted: pending.was_gifted,
+            registration_season_id: pending.season_id,
+            target: None,
+        };
+        let expiration_time = record.expiration_time;
+        self.domains.insert(pending.domain_name.clone(), record);
+        self.index_owner(&pending.domain_name, pending.owner);
+        self.index_name(&pending.domain_name);
+
+        if let Some(id) = pending.season_id {
+            self.complete_season_if_full(id);
+        }
+
+        let summary = match &pending.kind {
+            RegistrationKind::Purchase => {
+                let fee_info = if pending.season_id.is_none() {
+                    "Free (admin registration)".to_string()
+                } else {
+                    format!("Fee: {} ICP", pending.fee_e8s as f64 / E8S_PER_ICP as f64)
+                };
+                format!(
+                    "Domain {} registered successfully with canister {}. {}",
+                    pending.domain_name, canister_id, fee_info
+                )
+            }
+            RegistrationKind::Gift => format!(
+                "Domain {} gifted to {} with canister {} (FREE admin gift)",
+                pending.domain_name, pending.owner, canister_id
+            ),
+            RegistrationKind::AddressCreation { address } => format!(
+                "Domain {} created for address '{}' and assigned to {} with canister {}",
+                pending.domain_name, address, pending.owner, canister_id
+            ),
+            RegistrationKind::Auction { auction_id } => format!(
+                "Domain {} won by {} in auction {} for {} ICP with canister {}",
+                pending.domain_name, pending.owner, auction_id,
+                pending.fee_e8s as f64 / E8S_PER_ICP as f64, canister_id
+            ),
+            RegistrationKind::ReservedRelease => format!(
+                "Reserved domain {} released to {} with canister {}",
+                pending.domain_name, pending.owner, canister_id
+            ),
+        };
+
+        let sequence = self.next_registration_sequence;
+        self.next_registration_sequence += 1;
+
+        Ok(RegistrationReceipt {
+            domain: pending.domain_name,
+            canister_id,
+            owner: pending.owner,
+            season_id: pending.season_id,
+            fee_e8s: pending.fee_e8s,
+            payment_block: pending.payment_block
+                .filter(|_| matches!(pending.kind, RegistrationKind::Purchase | RegistrationKind::Auction { .. })),
+            expiration_time,
+            sequence,
+            summary: Some(summary),
+        })
+    }
+
+    /// Releases the name and season slot taken by `begin_*` when the registration cannot finish.
+    pub fn abort_registration(&mut self, pending: PendingRegistration) {
+        if !self.take_reservation(&pending) {
+            return;
+        }
+        if let Some(id) = pending.season_id {
+            self.release_slot(id);
+        }
+    }
+
+    /// Checks that the caller may renew and returns the fee the ledger must confirm, if any,
+    /// after claiming its payment block. Admins renew for free.
+    pub fn begin_renewal(&mut self, caller: Principal, domain_name: &str, payment_block: u64) -> Result<Option<u64>, RegistryError> {
+        self.authorize(domain_name, caller, Permission::Renew)?;
+        let fee_e8s = self.renewal_fee();
+        if self.is_admin(caller) || fee_e8s == 0 || self.ledger_canister.is_none() {
+            return Ok(None);
+        }
+        self.claim_payment_block(payment_block)?;
+        Ok(Some(fee_e8s))
+    }
+
+    /// Extends the domain by one registration period once `begin_renewal`'s payment is confirmed.
+    pub fn complete_renewal(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        payment_block: u64,
+        now: u64,
+    ) -> Result<String, RegistryError> {
+        self.authorize(domain_name, caller, Permission::Renew)?;
+        let fee_info = if self.is_admin(caller) {
+            "Free (admin renewal)".to_string()
+        } else {
+            format!("Fee: {} ICP", self.renewal_fee() as f64 / E8S_PER_ICP as f64)
+        };
+
+        let domain = self.domains.get_mut(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        domain.expiration_time += REGISTRATION_PERIOD_NS;
+        domain.last_payment_block = payment_block;
+        domain.last_modified = now;
+
+        Ok(format!("Domain {} renewed successfully. {}", domain_name, fee_info))
+    }
+
+    // Hands the domain to `new_owner` and withdraws the previous owner's listing, transfer
+    // proposal, grants and target. The new owner also becomes administrator and operator
+    fn move_domain(&mut self, domain_name: &str, new_owner: Principal, now: u64) {
+        let Some(domain) = self.domains.get_mut(domain_name) else {
+            return;
+        };
+        let old_owner = domain.owner;
+        domain.owner = new_owner;
+        domain.administrator = new_owner;
+        domain.operator = new_owner;
+        domain.target = None;
+        domain.last_modified = now;
+
+        self.unindex_owner(domain_name, old_owner);
+        self.index_owner(domain_name, new_owner);
+        self.listings.remove(domain_name);
+        self.pending_transfers.remove(domain_name);
+        self.permissions.remove(domain_name);
+    }
+}
+
+/// The runtime services the registration flows depend on.
+pub struct Environment<C, L, M> {
+    pub clock: C,
+    pub ledger: L,
+    pub management: M,
+}
+
+impl<C: Clock, L: Ledger, M: Management> Environment<C, L, M> {
+    /// Registers a name without a prior commitment. The name is public as soon as the
+    /// call is sent, so only admins may; everyone else commits and reveals.
+    pub async fn register_domain(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: RegistrationRequest,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        state.borrow().require_admin(caller)?;
+        let pending = state.borrow_mut().begin_registration(caller, &request, self.clock.now())?;
+        self.pay_and_provision(state, caller, request.payment_block, pending).await
+    }
+
+    pub async fn reveal_registration(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: RevealRequest,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let pending = state.borrow_mut().begin_reveal(caller, &request, self.clock.now())?;
+        self.pay_and_provision(state, caller, request.payment_block, pending).await
+    }
+
+    pub async fn renew_domain(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        domain_name: &str,
+        payment_block: u64,
+    ) -> Result<String, RegistryError> {
+        let fee_e8s = state.borrow_mut().begin_renewal(caller, domain_name, payment_block)?;
+        if let Some(fee_e8s) = fee_e8s {
+            if let Err(reason) = self.ledger.verify_payment(caller, payment_block, fee_e8s).await {
+                state.borrow_mut().used_payment_blocks.remove(&payment_block);
+                return Err(RegistryError::PaymentInvalid { reason });
+            }
+        }
+
+        let renewed = state.borrow_mut().complete_renewal(caller, domain_name, payment_block, self.clock.now());
+        if fee_e8s.is_some() && renewed.is_err() {
+            // The payment was not spent, so it may pay for another attempt
+            state.borrow_mut().used_payment_blocks.remove(&payment_block);
+        }
+        renewed
+    }
+
+    pub async fn gift_domain(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: AdminGiftRequest,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let pending = state.borrow_mut().begin_gift(caller, &request, self.clock.now())?;
+        self.provision(state, pending).await
+    }
+
+    pub async fn create_domain_with_address(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: AdminCreateDomainRequest,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let pending = state.borrow_mut().begin_address_creation(caller, &request, self.clock.now())?;
+        self.provision(state, pending).await
+    }
+
+    async fn pay_and_provision(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        payment_block: u64,
+        pending: PendingRegistration,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let checked = pending.fee_e8s > 0 && state.borrow().ledger_canister.is_some();
+        if checked {
+            let claimed = state.borrow_mut().claim_payment_block(payment_block);
+            if let Err(e) = claimed {
+                state.borrow_mut().abort_registration(pending);
+                return Err(e);
+            }
+            if let Err(reason) = self.ledger.verify_payment(caller, payment_block, pending.fee_e8s).await {
+                let mut registry = state.borrow_mut();
+                registry.used_payment_blocks.remove(&payment_block);
+                registry.abort_registration(pending);
+                return Err(RegistryError::PaymentInvalid { reason });
+            }
+        }
+
+        let result = self.provision(state, pending).await;
+        if checked && result.is_err() {
+            // The payment was not spent, so it may pay for another attempt
+            state.borrow_mut().used_payment_blocks.remove(&payment_block);
+        }
+        result
+    }
+
+    // Never holds a borrow of the state across the await
+    async fn provision(
+        &self,
+        state: &RefCell<Registry>,
+        pending: PendingRegistration,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let created = self.management
+            .create_domain_canister(&pending.domain_name, pending.owner, pending.administrator, pending.operator)
+            .await;
+
+        match created {
+            Ok(canister_id) => state.borrow_mut().complete_registration(pending, canister_id, self.clock.now()),
+            Err(reason) => {
+                state.borrow_mut().abort_registration(pending);
+                Err(RegistryError::CanisterCreationFailed { reason })
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod season_invariants;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::future::Future;
+    use std::pin::pin;
+    use std::task::{Context, Poll, Waker};
+
+    pub(super) const NOW: u64 = 1_700_000_000_000_000_000;
+
+    pub(super) struct FixedClock(pub(super) Cell<u64>);
+
+    impl Clock for FixedClock {
+        fn now(&self) -> u64 {
+            self.0.get()
+        }
+    }
+
+    // Holds the registry's balance: verified payments are paid in, and transfers pay out
+    // their amount plus the ledger fee
+    pub(super) struct MockLedger {
+        accept: bool,
+        pub(super) transfers: RefCell<Vec<(Principal, u64)>>,
+        pub(super) balance_e8s: Cell<u64>,
+    }
+
+    impl Ledger for MockLedger {
+        async fn verify_payment(&self, _payer: Principal, _block_index: u64, amount_e8s: u64) -> Result<(), String> {
+            if self.accept {
+                self.balance_e8s.set(self.balance_e8s.get() + amount_e8s);
+                Ok(())
+            } else {
+                Err("block not found".to_string())
+            }
+        }
+
+        async fn transfer(&self, to: Principal, amount_e8s: u64, _memo: u64, _created_at_time: u64) -> Result<u64, String> {
+            let balance_e8s = self.balance_e8s
+                .get()
+                .checked_sub(amount_e8s + crate::ledger::TRANSFER_FEE_E8S)
+                .ok_or_else(|| "insufficient funds".to_string())?;
+            self.balance_e8s.set(balance_e8s);
+            let mut transfers = self.transfers.borrow_mut();
+            transfers.push((to, amount_e8s));
+            Ok(transfers.len() as u64)
+        }
+    }
+
+    pub(super) struct MockManagement {
+        fail: bool,
+    }
+
+    impl Management for MockManagement {
+        async fn create_domain_canister(
+            &self,
+            _domain_name: &str,
+            _owner: Principal,
+            _administrator: Principal,
+            _operator: Principal,
+        ) -> Result<Principal, String> {
+            if self.fail {
+                Err("out of cycles".to_string())
+            } else {
+                Ok(Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 7, 1, 1]))
+            }
+        }
+    }
+
+    pub(super) fn environment(accept_payment: bool, fail_creation: bool) -> Environment<FixedClock, MockLedger, MockManagement> {
+        Environment {
+            clock: FixedClock(Cell::new(NOW)),
+            ledger: MockLedger { accept: accept_payment, transfers: RefCell::new(Vec::new()), balance_e8s: Cell::new(0) },
+            management: MockManagement { fail: fail_creation },
+        }
+    }
+
+    // The mocks never suspend, so a single poll completes the flow
+    pub(super) fn run<F: Future>(future: F) -> F::Output {
+        match pin!(future).poll(&mut Context::from_waker(Waker::noop())) {
+            Poll::Ready(output) => output,
+            Poll::Pending => panic!("mock services should complete immediately"),
+        }
+    }
+
+    pub(super) fn user(n: u8) -> Principal {
+        Principal::self_authenticating([n])
+    }
+
+    pub(super) fn admin() -> Principal {
+        user(0)
+    }
+
+    pub(super) fn ledger() -> Principal {
+        Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 2, 1, 1])
+    }
+
+    // Registry whose domains were registered at NOW, outside any season
+    pub(super) fn registry_with(domains: &[(&str, Principal)]) -> Registry {
+        let mut registry = Registry::new(admin());
+        registry.ledger_canister = Some(ledger());
+        for (name, owner) in domains {
+            registry.domains.insert(name.to_string(), DomainRecord {
+                owner: *owner,
+                administrator: *owner,
+                operator: *owner,
+                canister_id: Principal::management_canister(),
+                registration_time: NOW,
+                expiration_time: NOW + REGISTRATION_PERIOD_NS,
+                last_modified: NOW,
+                last_payment_block: 0,
+                custom_mcp_endpoint: None,
+                was_gifted: false,
+                registration_season_id: None,
+                target: None,
+            });
+        }
+        registry.rebuild_indexes();
+        registry
+    }
+
+    fn registry_with_season(total_allowed: u64, price_icp: u64) -> (RefCell<Registry>, u64) {
+        let mut registry = registry_with(&[]);
+        let request = CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed, price_icp, max_per_wallet: None };
+        let season = registry.create_season(admin(), request, NOW).unwrap();
+        (RefCell::new(registry), season)
+    }
+
+    // A distinct payment block for each name and payer
+    fn payment_block(domain_name: &str, owner: Principal) -> u64 {
+        domain_name
+            .bytes()
+            .chain(owner.as_slice().iter().copied())
+            .fold(0, |block: u64, byte| block.wrapping_mul(31).wrapping_add(byte.into()))
+    }
+
+    fn request(domain_name: &str, owner: Principal) -> RegistrationRequest {
+        RegistrationRequest {
+            domain_name: domain_name.to_string(),
+            administrator: owner,
+            operator: owner,
+            payment_block: payment_block(domain_name, owner),
+        }
+    }
+
+    // Commits to the request, lets the commitment age and reveals it, as paying callers must
+    fn register(
+        env: &Environment<FixedClock, MockLedger, MockManagement>,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: RegistrationRequest,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let now = env.clock.now();
+        let commitment = registration_commitment(&request.domain_name, caller, b"pepper");
+        state.borrow_mut().commit_registration(caller, commitment, now - MIN_COMMITMENT_AGE_NS).unwrap();
+        let reveal = RevealRequest {
+            domain_name: request.domain_name,
+            salt: b"pepper".to_vec(),
+            administrator: request.administrator,
+            operator: request.operator,
+            payment_block: request.payment_block,
+        };
+        run(env.reveal_registration(state, caller, reveal))
+    }
+
+    #[test]
+    fn cheapest_applicable_season_wins() {
+        let mut registry = Registry::new(admin());
+        for (id, (min, max, price, status)) in [
+            (3, Some(5), 20, SeasonStatus::Active),
+            (3, Some(5), 15, SeasonStatus::Active),
+            (3, Some(5), 5, SeasonStatus::Deactivated),
+            (1, Some(2), 1, SeasonStatus::Active),
+        ].into_iter().enumerate() {
+            registry.seasons.insert(id as u64, RegistrationSeason {
+                season_id: id as u64,
+                min_letters: min,
+                max_letters: max,
+                total_allowed: 10,
+                registered_count: 0,
+                price_icp: price,
+                created_by: admin(),
+                created_at: NOW,
+                status,
+                max_per_wallet: None,
+            });
+        }
+
+        assert_eq!(registry.find_applicable_season("test").map(|(id, _)| id), Some(1));
+        assert_eq!(registry.registration_fee("test"), Ok(15 * E8S_PER_ICP));
+
+        // A full season is skipped
+        registry.seasons.get_mut(&1).unwrap().registered_count = 10;
+        assert_eq!(registry.find_applicable_season("test").map(|(id, _)| id), Some(0));
+        assert!(registry.find_applicable_season("toolong").is_none());
+    }
+
+    #[test]
+    fn registration_creates_domain_and_receipt() {
+        let (state, season) = registry_with_season(2, 10);
+        let receipt = register(&environment(true, false), &state, user(1), request("alpha", user(1))).unwrap();
+
+        assert_eq!(receipt.season_id, Some(season));
+        assert_eq!(receipt.fee_e8s, 10 * E8S_PER_ICP);
+        assert_eq!(receipt.payment_block, Some(payment_block("alpha", user(1))));
+        assert_eq!(receipt.expiration_time, NOW + REGISTRATION_PERIOD_NS);
+
+        let registry = state.borrow();
+        assert_eq!(registry.domains["alpha"].owner, user(1));
+        assert_eq!(registry.wallet_domain(user(1)), Some("alpha".to_string()));
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+    }
+
+    #[test]
+    fn one_domain_per_wallet_except_admins() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        register(&env, &state, user(1), request("alpha", user(1))).unwrap();
+
+        let second = register(&env, &state, user(1), request("bravo", user(1)));
+        assert_eq!(second.err(), Some(RegistryError::WalletAlreadyOwns { domain: "alpha".to_string() }));
+
+        run(env.register_domain(&state, admin(), request("charlie", admin()))).unwrap();
+        let receipt = run(env.register_domain(&state, admin(), request("delta", admin()))).unwrap();
+        assert_eq!(receipt.fee_e8s, 0);
+        assert!(state.borrow().domains["delta"].was_gifted);
+    }
+
+    #[test]
+    fn failed_payment_or_canister_creation_releases_the_slot() {
+        let (state, season) = registry_with_season(1, 10);
+
+        let unpaid = register(&environment(false, false), &state, user(1), request("alpha", user(1)));
+        assert!(matches!(unpaid, Err(RegistryError::PaymentInvalid { .. })));
+
+        let failed = register(&environment(true, true), &state, user(1), request("alpha", user(1)));
+        assert!(matches!(failed, Err(RegistryError::CanisterCreationFailed { .. })));
+
+        let registry = state.borrow();
+        assert_eq!(registry.seasons[&season].registered_count, 0);
+        assert!(registry.domains.is_empty());
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert!(registry.used_payment_blocks.is_empty());
+    }
+
+    #[test]
+    fn payment_blocks_pay_for_one_registration() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        register(&env, &state, user(1), request("alpha", user(1))).unwrap();
+
+        let mut reused = request("bravo", user(2));
+        reused.payment_block = payment_block("alpha", user(1));
+        let reused = register(&env, &state, user(2), reused);
+        assert!(matches!(reused, Err(RegistryError::PaymentInvalid { .. })));
+        assert!(state.borrow().reservations.is_empty());
+    }
+
+    #[test]
+    fn renewals_are_paid_once_per_payment_block() {
+        let state = RefCell::new(registry_with(&[("alpha", user(1)), ("bravo", user(2)), ("charlie", admin())]));
+        let expiration = NOW + REGISTRATION_PERIOD_NS;
+
+        let unpaid = run(environment(false, false).renew_domain(&state, user(1), "alpha", 7));
+        assert!(matches!(unpaid, Err(RegistryError::PaymentInvalid { .. })));
+        assert_eq!(state.borrow().domains["alpha"].expiration_time, expiration);
+        assert!(state.borrow().used_payment_blocks.is_empty());
+
+        let env = environment(true, false);
+        run(env.renew_domain(&state, user(1), "alpha", 7)).unwrap();
+        let reused = run(env.renew_domain(&state, user(2), "bravo", 7));
+        assert!(matches!(reused, Err(RegistryError::PaymentInvalid { .. })));
+
+        // Admins renew without paying
+        run(environment(false, false).renew_domain(&state, admin(), "charlie", 7)).unwrap();
+        let registry = state.borrow();
+        assert_eq!(registry.domains["alpha"].expiration_time, expiration + REGISTRATION_PERIOD_NS);
+        assert_eq!(registry.domains["alpha"].last_payment_block, 7);
+        assert_eq!(registry.domains["bravo"].expiration_time, expiration);
+        assert_eq!(registry.domains["charlie"].expiration_time, expiration + REGISTRATION_PERIOD_NS);
+    }
+
+    #[test]
+    fn without_a_ledger_payments_are_unchecked_and_escrow_is_off() {
+        let (state, _) = registry_with_season(10, 10);
+        state.borrow_mut().ledger_canister = None;
+        register(&environment(false, false), &state, user(1), request("alpha", user(1))).unwrap();
+
+        let mut registry = state.borrow_mut();
+        let listing = ListDomainRequest { domain_name: "alpha".to_string(), price_e8s: E8S_PER_ICP, duration_ns: None };
+        assert_eq!(registry.list_domain(user(1), listing, NOW), Err(RegistryError::LedgerUnavailable));
+        let auction = CreateAuctionRequest {
+            domain_name: "bravo".to_string(),
+            mode: auction::AuctionMode::English,
+            reserve_price_e8s: E8S_PER_ICP,
+            min_increment_e8s: None,
+            start_time: None,
+            duration_ns: auction::MIN_AUCTION_DURATION_NS,
+        };
+        assert_eq!(registry.create_auction(admin(), auction, NOW), Err(RegistryError::LedgerUnavailable));
+
+        assert_eq!(registry.set_ledger_canister(user(1), Some(ledger())), Err(RegistryError::NotAdmin));
+        registry.set_ledger_canister(admin(), Some(ledger())).unwrap();
+        assert_eq!(registry.ledger_canister, Some(ledger()));
+    }
+
+    #[test]
+    fn gifts_take_a_slot_and_complete_the_season() {
+        let (state, season) = registry_with_season(1, 10);
+        let gift = || AdminGiftRequest {
+            domain_name: "present".to_string(),
+            recipient: user(2),
+            administrator: user(2),
+            operator: user(2),
+        };
+
+        let not_admin = run(environment(true, false).gift_domain(&state, user(1), gift()));
+        assert_eq!(not_admin.err(), Some(RegistryError::NotAdmin));
+
+        let receipt = run(environment(true, false).gift_domain(&state, admin(), gift())).unwrap();
+        assert_eq!(receipt.fee_e8s, 0);
+        assert_eq!(receipt.payment_block, None);
+
+        let registry = state.borrow();
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+        assert!(matches!(registry.seasons[&season].status, SeasonStatus::Completed));
+    }
+
+    #[test]
+    fn expired_names_become_available() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        register(&env, &state, user(1), request("alpha", user(1))).unwrap();
+
+        assert!(!state.borrow().is_available("alpha", NOW + 1));
+        env.clock.0.set(NOW + REGISTRATION_PERIOD_NS + 1);
+        assert!(state.borrow().is_available("alpha", env.clock.now()));
+
+        let receipt = register(&env, &state, user(2), request("alpha", user(2))).unwrap();
+        assert_eq!(receipt.owner, user(2));
+    }
+
+    #[test]
+    fn registrations_in_flight_hold_their_slot() {
+        let (state, season) = registry_with_season(1, 10);
+        let mut registry = state.borrow_mut();
+        let gift = AdminGiftRequest {
+            domain_name: "present".to_string(),
+            recipient: user(2),
+            administrator: user(2),
+            operator: user(2),
+        };
+
+        let pending = registry.begin_gift(admin(), &gift, NOW).unwrap();
+        assert_eq!(registry.begin_registration(user(1), &request("alpha", user(1)), NOW).err(), Some(RegistryError::NoSeason));
+        assert!(matches!(registry.seasons[&season].status, SeasonStatus::Active));
+
+        registry.complete_registration(pending, Principal::management_canister(), NOW).unwrap();
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+        assert!(matches!(registry.seasons[&season].status, SeasonStatus::Completed));
+    }
+
+    #[test]
+    fn registrations_in_flight_lock_the_name_and_wallet() {
+        let (state, season) = registry_with_season(10, 10);
+        let mut registry = state.borrow_mut();
+        let first = registry.begin_registration(user(1), &request("alpha", user(1)), NOW).unwrap();
+
+        let same_name = registry.begin_registration(user(2), &request("alpha", user(2)), NOW);
+        assert_eq!(same_name.err(), Some(RegistryError::RegistrationInProgress { domain: "alpha".to_string() }));
+        let same_wallet = registry.begin_registration(user(1), &request("bravo", user(1)), NOW);
+        assert_eq!(same_wallet.err(), Some(RegistryError::RegistrationInProgress { domain: "alpha".to_string() }));
+        let gift = AdminGiftRequest {
+            domain_name: "alpha".to_string(),
+            recipient: user(3),
+            administrator: user(3),
+            operator: user(3),
+        };
+        assert!(matches!(registry.begin_gift(admin(), &gift, NOW), Err(RegistryError::RegistrationInProgress { .. })));
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+
+        registry.abort_registration(first);
+        assert!(registry.reservations.is_empty());
+        assert_eq!(registry.seasons[&season].registered_count, 0);
+        registry.begin_registration(user(2), &request("alpha", user(2)), NOW).unwrap();
+    }
+
+    #[test]
+    fn stuck_reservations_time_out() {
+        let (state, season) = registry_with_season(10, 10);
+        let mut registry = state.borrow_mut();
+        let stuck = registry.begin_registration(user(1), &request("alpha", user(1)), NOW).unwrap();
+
+        let later = NOW + RESERVATION_TIMEOUT_NS;
+        let taken_over = registry.begin_registration(user(2), &request("alpha", user(2)), later).unwrap();
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+
+        let late = registry.complete_registration(stuck, Principal::management_canister(), later);
+        assert_eq!(late.err(), Some(RegistryError::ReservationExpired));
+        registry.complete_registration(taken_over, Principal::management_canister(), later).unwrap();
+
+        assert_eq!(registry.domains["alpha"].owner, user(2));
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert_eq!(registry.seasons[&season].registered_count, 1);
+    }
+
+    #[test]
+    fn re_registering_an_expired_name_releases_the_previous_owner() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        register(&env, &state, user(1), request("alpha", user(1))).unwrap();
+
+        env.clock.0.set(NOW + REGISTRATION_PERIOD_NS + 1);
+        register(&env, &state, user(2), request("alpha", user(2))).unwrap();
+
+        let registry = state.borrow();
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert_eq!(registry.wallet_domain(user(2)), Some("alpha".to_string()));
+    }
+
+    fn reveal(domain_name: &str, owner: Principal) -> RevealRequest {
+        RevealRequest {
+            domain_name: domain_name.to_string(),
+            salt: b"pepper".to_vec(),
+            administrator: owner,
+            operator: owner,
+            payment_block: 42,
+        }
+    }
+
+    #[test]
+    fn revealed_commitments_register_after_the_delay() {
+        let (state, season) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        let commitment = registration_commitment("alpha", user(1), b"pepper");
+        let reveal_after = state.borrow_mut().commit_registration(user(1), commitment, NOW).unwrap();
+        assert_eq!(reveal_after, NOW + MIN_COMMITMENT_AGE_NS);
+
+        let early = run(env.reveal_registration(&state, user(1), reveal("alpha", user(1))));
+        assert_eq!(early.err(), Some(RegistryError::CommitmentTooNew { reveal_after }));
+
+        env.clock.0.set(reveal_after);
+        let receipt = run(env.reveal_registration(&state, user(1), reveal("alpha", user(1)))).unwrap();
+        assert_eq!(receipt.owner, user(1));
+        assert_eq!(receipt.season_id, Some(season));
+        assert!(state.borrow().commitments.is_empty());
+    }
+
+    #[test]
+    fn only_the_committer_can_reveal_in_time() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        let commitment = registration_commitment("alpha", user(1), b"pepper");
+        state.borrow_mut().commit_registration(user(1), commitment.clone(), NOW).unwrap();
+        env.clock.0.set(NOW + MIN_COMMITMENT_AGE_NS);
+
+        // A watcher copying the request commits to a different owner
+        let sniped = run(env.reveal_registration(&state, user(2), reveal("alpha", user(2))));
+        assert_eq!(sniped.err(), Some(RegistryError::CommitmentNotFound));
+        let mut wrong_salt = reveal("alpha", user(1));
+        wrong_salt.salt = b"salt".to_vec();
+        let guessed = run(env.reveal_registration(&state, user(1), wrong_salt));
+        assert_eq!(guessed.err(), Some(RegistryError::CommitmentNotFound));
+
+        env.clock.0.set(NOW + MAX_COMMITMENT_AGE_NS + 1);
+        let late = run(env.reveal_registration(&state, user(1), reveal("alpha", user(1))));
+        assert_eq!(late.err(), Some(RegistryError::CommitmentExpired));
+        assert!(state.borrow().domains.is_empty());
+    }
+
+    #[test]
+    fn only_admins_register_without_a_commitment() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        let commitment = registration_commitment("alpha", user(1), b"pepper");
+        state.borrow_mut().commit_registration(user(1), commitment, NOW).unwrap();
+        env.clock.0.set(NOW + MIN_COMMITMENT_AGE_NS);
+
+        // A watcher who reads the name off the reveal cannot take it in the same round
+        let sniped = run(env.register_domain(&state, user(2), request("alpha", user(2))));
+        assert_eq!(sniped.err(), Some(RegistryError::NotAdmin));
+        let receipt = run(env.reveal_registration(&state, user(1), reveal("alpha", user(1)))).unwrap();
+        assert_eq!(receipt.owner, user(1));
+    }
+
+    #[test]
+    fn transfer_moves_ownership_and_wallet_mapping() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        register(&env, &state, user(1), request("alpha", user(1))).unwrap();
+        register(&env, &state, user(2), request("bravo", user(2))).unwrap();
+
+        let mut registry = state.borrow_mut();
+        assert_eq!(registry.propose_transfer(user(3), "alpha", user(3), None, NOW).err(), Some(RegistryError::Unauthorized));
+        registry.propose_transfer(user(1), "alpha", user(2), None, NOW).unwrap();
+        assert_eq!(
+            registry.accept_transfer(user(2), "alpha", NOW).err(),
+            Some(RegistryError::WalletAlreadyOwns { domain: "bravo".to_string() })
+        );
+
+        registry.propose_transfer(user(1), "alpha", user(3), None, NOW).unwrap();
+        registry.accept_transfer(user(3), "alpha", NOW + 5).unwrap();
+        assert_eq!(registry.domains["alpha"].owner, user(3));
+        assert_eq!(registry.domains["alpha"].last_modified, NOW + 5);
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert_eq!(registry.wallet_domain(user(3)), Some("alpha".to_string()));
+    }
+
+    #[test]
+    fn wallet_limits_come_from_the_global_cap_or_an_override() {
+        let mut registry = registry_with(&[("alpha", user(1)), ("bravo", user(1))]);
+        registry.domains.get_mut("bravo").unwrap().registration_time = NOW + 1;
+        assert_eq!(registry.wallet_domains(user(1)), vec!["alpha".to_string(), "bravo".to_string()]);
+        assert_eq!(registry.wallet_domain(user(1)), Some("alpha".to_string()));
+        assert_eq!(registry.wallet_limit(admin()), None);
+
+        assert_eq!(registry.set_max_domains_per_wallet(user(1), 3), Err(RegistryError::NotAdmin));
+        registry.set_max_domains_per_wallet(admin(), 3).unwrap();
+        registry.propose_transfer(user(1), "alpha", user(2), None, NOW).unwrap();
+        registry.propose_transfer(user(1), "bravo", user(2), None, NOW).unwrap();
+        registry.accept_transfer(user(2), "bravo", NOW).unwrap();
+        assert_eq!(registry.wallet_domains(user(1)), vec!["alpha".to_string()]);
+
+        registry.set_wallet_limit(admin(), user(2), Some(1)).unwrap();
+        let over = registry.accept_transfer(user(2), "alpha", NOW);
+        assert_eq!(over.err(), Some(RegistryError::WalletAlreadyOwns { domain: "bravo".to_string() }));
+        registry.set_wallet_limit(admin(), user(2), None).unwrap();
+        registry.accept_transfer(user(2), "alpha", NOW).unwrap();
+        assert_eq!(registry.wallet_domains(user(2)).len(), 2);
+    }
+
+    #[test]
+    fn season_caps_count_in_flight_registrations() {
+        let (state, season) = registry_with_season(10, 10);
+        let mut registry = state.borrow_mut();
+        registry.set_max_domains_per_wallet(admin(), 5).unwrap();
+        registry.set_season_wallet_cap(admin(), season, Some(2)).unwrap();
+
+        let first = registry.begin_registration(user(1), &request("alpha", user(1)), NOW).unwrap();
+        registry.complete_registration(first, Principal::management_canister(), NOW).unwrap();
+        let second = registry.begin_registration(user(1), &request("bravo", user(1)), NOW).unwrap();
+        let third = registry.begin_registration(user(1), &request("charlie", user(1)), NOW);
+        assert_eq!(third.err(), Some(RegistryError::WalletLimitReached { limit: 2 }));
+
+        // Outside the season only the global cap applies
+        registry.begin_registration(user(2), &request("delta", user(2)), NOW).unwrap();
+        registry.abort_registration(second);
+        registry.begin_registration(user(1), &request("charlie", user(1)), NOW).unwrap();
+    }
+
+    #[test]
+    fn names_point_at_their_owner_until_given_a_target() {
+        let mut registry = registry_with(&[("alpha", user(1))]);
+        let canister = Principal::from_slice(&[0, 0, 0, 0, 0, 0, 0, 9, 1, 1]);
+        assert_eq!(registry.domains["alpha"].target_address(), user(1));
+
+        assert_eq!(registry.set_domain_target(user(2), "alpha", Some(canister), NOW), Err(RegistryError::Unauthorized));
+        registry.set_domain_target(user(1), "alpha", Some(canister), NOW + 1).unwrap();
+        assert_eq!(registry.domains["alpha"].target_address(), canister);
+        assert_eq!(registry.domains["alpha"].last_modified, NOW + 1);
+
+        // A new owner starts out pointing the name at themselves
+        registry.propose_transfer(user(1), "alpha", user(2), None, NOW).unwrap();
+        registry.accept_transfer(user(2), "alpha", NOW).unwrap();
+        assert_eq!(registry.domains["alpha"].target_address(), user(2));
+    }
+
+    #[test]
+    fn admin_domains_are_all_indexed() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        for name in ["alpha", "bravo", "charlie"] {
+            run(env.register_domain(&state, admin(), request(name, admin()))).unwrap();
+        }
+
+        let mut registry = state.borrow_mut();
+        assert_eq!(registry.wallet_domains(admin()).len(), 3);
+        registry.propose_transfer(admin(), "bravo", user(1), None, NOW).unwrap();
+        registry.accept_transfer(user(1), "bravo", NOW).unwrap();
+        assert_eq!(registry.wallet_domains(admin()), vec!["alpha".to_string(), "charlie".to_string()]);
+        assert_eq!(registry.wallet_domains(user(1)), vec!["bravo".to_string()]);
+    }
+
+    #[test]
+    fn names_are_indexed_by_every_substring() {
+        let (state, _) = registry_with_season(10, 10);
+        let env = environment(true, false);
+        for name in ["alpha", "Alphabet", "bravo"] {
+            run(env.register_domain(&state, admin(), request(name, admin()))).unwrap();
+        }
+
+        let registry = state.borrow();
+        let names = |pattern: &str| registry.names_containing(pattern).into_iter().collect::<Vec<_>>();
+        assert_eq!(names("alpha"), vec!["Alphabet".to_string(), "alpha".to_string()]);
+        assert_eq!(names("bet"), vec!["Alphabet".to_string()]);
+        assert_eq!(names("av"), vec!["bravo".to_string()]);
+        assert!(names("charlie").is_empty());
+    }
+}
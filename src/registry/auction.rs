@@ -0,0 +1,811 @@
+// Premium name auctions. Bids are paid into the registry up front and refunded through
+// the payout queue once the auction settles.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::cell::RefCell;
+use std::collections::HashSet;
+
+use super::{is_valid_domain_name, AdminRole, Environment, PendingRegistration, RegistrationKind, Registry};
+use crate::error::RegistryError;
+use crate::ledger::TRANSFER_FEE_E8S;
+use crate::services::{Clock, Ledger, Management};
+use crate::RegistrationReceipt;
+
+pub const MIN_AUCTION_DURATION_NS: u64 = 60 * 60 * 1_000_000_000; // 1 hour
+pub const MAX_AUCTION_DURATION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+// Sealed bids are revealed during this window after bidding closes
+pub const BID_REVEAL_PERIOD_NS: u64 = 24 * 60 * 60 * 1_000_000_000; // 24 hours
+// A payout still in flight after this long lost its reply, to a trap, and is sent again
+pub const PAYOUT_RETRY_NS: u64 = 10 * 60 * 1_000_000_000; // 10 minutes
+// The ledger deduplicates transfers for 24 hours and refuses older creation times, so a
+// payout's creation time is renewed before it gets that old
+const MAX_PAYOUT_ATTEMPT_AGE_NS: u64 = 23 * 60 * 60 * 1_000_000_000; // 23 hours
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum AuctionMode {
+    Vickrey, // Sealed bids, revealed once bidding closes; the winner pays the second-highest bid
+    English, // Open ascending bids; the winner pays their bid
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, PartialEq, Debug)]
+pub enum AuctionStatus {
+    Open,
+    Settling,
+    Sold { winner: Principal, price_e8s: u64, canister_id: Principal },
+    Unsold,
+    Cancelled,
+    Failed { reason: String },
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Bid {
+    pub bidder: Principal,
+    pub amount_e8s: u64,  // Zero while a sealed bid is unrevealed
+    pub deposit_e8s: u64, // What the bidder paid in; everything above the price is refunded
+    pub payment_block: u64,
+    pub placed_at: u64,
+    pub commitment: Option<Vec<u8>>, // Set until a sealed bid is revealed
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Auction {
+    pub id: u64,
+    pub domain_name: String,
+    pub mode: AuctionMode,
+    pub reserve_price_e8s: u64,
+    pub min_increment_e8s: u64,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub created_by: Principal,
+    pub bids: Vec<Bid>,
+    pub status: AuctionStatus,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct CreateAuctionRequest {
+    pub domain_name: String,
+    pub mode: AuctionMode,
+    pub reserve_price_e8s: u64,
+    pub min_increment_e8s: Option<u64>, // English auctions only; defaults to 1% of the reserve
+    pub start_time: Option<u64>,        // Defaults to now
+    pub duration_ns: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct PlaceBidRequest {
+    pub auction_id: u64,
+    pub amount_e8s: u64,             // The bid, or for a sealed bid the deposit, which must cover it
+    pub payment_block: u64,          // Transfer of `amount_e8s` to the registry
+    pub commitment: Option<Vec<u8>>, // Sealed bids only: `bid_commitment` of the hidden bid
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct RevealBidRequest {
+    pub auction_id: u64,
+    pub amount_e8s: u64,
+    pub salt: Vec<u8>,
+}
+
+/// The hash a sealed bid commits to: SHA-256 over the auction id and the bid as big-endian
+/// u64s, the length-prefixed bidder principal and the salt.
+pub fn bid_commitment(auction_id: u64, amount_e8s: u64, bidder: Principal, salt: &[u8]) -> Vec<u8> {
+    let mut hasher = Sha256::new();
+    hasher.update(auction_id.to_be_bytes());
+    hasher.update(amount_e8s.to_be_bytes());
+    hasher.update([bidder.as_slice().len() as u8]);
+    hasher.update(bidder.as_slice());
+    hasher.update(salt);
+    hasher.finalize().to_vec()
+}
+
+/// Public view of an auction. Sealed auctions hide their highest bid until they close.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct AuctionInfo {
+    pub id: u64,
+    pub domain_name: String,
+    pub mode: AuctionMode,
+    pub reserve_price_e8s: u64,
+    pub min_increment_e8s: u64,
+    pub starts_at: u64,
+    pub ends_at: u64,
+    pub status: AuctionStatus,
+    pub bid_count: u64,
+    pub highest_bid_e8s: Option<u64>,
+}
+
+/// Funds the registry owes back, paid out by `Environment::process_payouts`. A payout
+/// stays queued until the ledger confirms its transfer.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Payout {
+    pub id: u64,
+    pub to: Principal,
+    pub amount_e8s: u64,
+    pub memo: String,
+    pub sent_at: Option<u64>, // Creation time of the transfer, reused by retries so the ledger deduplicates them
+    pub in_flight: bool,
+}
+
+impl Auction {
+    fn is_sealed(&self) -> bool {
+        self.mode == AuctionMode::Vickrey && matches!(self.status, AuctionStatus::Open)
+    }
+
+    /// When the auction is settled: when bidding closes, or for a sealed auction once its
+    /// bids have had time to be revealed.
+    pub fn settles_at(&self) -> u64 {
+        match self.mode {
+            AuctionMode::Vickrey => self.ends_at + BID_REVEAL_PERIOD_NS,
+            AuctionMode::English => self.ends_at,
+        }
+    }
+
+    pub fn info(&self) -> AuctionInfo {
+        AuctionInfo {
+            id: self.id,
+            domain_name: self.domain_name.clone(),
+            mode: self.mode,
+            reserve_price_e8s: self.reserve_price_e8s,
+            min_increment_e8s: self.min_increment_e8s,
+            starts_at: self.starts_at,
+            ends_at: self.ends_at,
+            status: self.status.clone(),
+            bid_count: self.bids.len() as u64,
+            highest_bid_e8s: if self.is_sealed() { None } else { self.highest_bid().map(|bid| bid.amount_e8s) },
+        }
+    }
+
+    // Earlier bids win ties
+    fn highest_bid(&self) -> Option<&Bid> {
+        self.highest_bid_among(&HashSet::new())
+    }
+
+    // Bids that count: sealed ones only once revealed
+    fn standing_bids(&self) -> impl Iterator<Item = &Bid> {
+        self.bids.iter().filter(|bid| bid.commitment.is_none())
+    }
+
+    fn highest_bid_among(&self, passed_over: &HashSet<Principal>) -> Option<&Bid> {
+        self.standing_bids()
+            .filter(|bid| !passed_over.contains(&bid.bidder))
+            .reduce(|best, bid| if bid.amount_e8s > best.amount_e8s { bid } else { best })
+    }
+
+    fn minimum_bid(&self) -> u64 {
+        match (self.mode, self.highest_bid()) {
+            (AuctionMode::English, Some(highest)) => highest.amount_e8s + self.min_increment_e8s,
+            _ => self.reserve_price_e8s,
+        }
+    }
+
+    fn check_bid(&self, amount_e8s: u64, now: u64) -> Result<(), RegistryError> {
+        if !matches!(self.status, AuctionStatus::Open) || now < self.starts_at || now >= self.ends_at {
+            return Err(RegistryError::AuctionClosed);
+        }
+        let minimum_e8s = self.minimum_bid();
+        if amount_e8s < minimum_e8s {
+            return Err(RegistryError::BidTooLow { minimum_e8s });
+        }
+        Ok(())
+    }
+
+    // The winning bid and the price it pays, if any bid met the reserve. Bidders who were
+    // passed over neither win nor set the price
+    fn outcome(&self, passed_over: &HashSet<Principal>) -> Option<(&Bid, u64)> {
+        let winner = self.highest_bid_among(passed_over)?;
+        let price = match self.mode {
+            AuctionMode::English => winner.amount_e8s,
+            AuctionMode::Vickrey => self
+                .standing_bids()
+                .filter(|bid| bid.bidder != winner.bidder && !passed_over.contains(&bid.bidder))
+                .map(|bid| bid.amount_e8s)
+                .max()
+                .unwrap_or(0)
+                .max(self.reserve_price_e8s),
+        };
+        Some((winner, price))
+    }
+}
+
+impl Registry {
+    pub fn is_under_auction(&self, domain_name: &str) -> bool {
+        self.auctions
+            .values()
+            .any(|auction| auction.domain_name == domain_name && matches!(auction.status, AuctionStatus::Open))
+    }
+
+    /// Puts a name up for auction. Reserved and registered names may not be auctioned.
+    pub fn create_auction(&mut self, caller: Principal, request: CreateAuctionRequest, now: u64) -> Result<u64, RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+        self.require_ledger()?;
+        if !is_valid_domain_name(&request.domain_name) {
+            return Err(RegistryError::InvalidName);
+        }
+        if self.is_reserved_name(&request.domain_name) {
+            return Err(RegistryError::Reserved);
+        }
+        if !self.is_available(&request.domain_name, now) || self.reservations.contains_key(&request.domain_name) {
+            return Err(RegistryError::Unavailable);
+        }
+        if request.reserve_price_e8s == 0 {
+            return Err(RegistryError::InvalidInput { reason: "Reserve price must be positive".to_string() });
+        }
+        if !(MIN_AUCTION_DURATION_NS..=MAX_AUCTION_DURATION_NS).contains(&request.duration_ns) {
+            return Err(RegistryError::InvalidInput {
+                reason: "Auctions must last between 1 hour and 30 days".to_string(),
+            });
+        }
+
+        let id = self.next_auction_id;
+        self.next_auction_id += 1;
+        let starts_at = request.start_time.unwrap_or(now).max(now);
+        self.auctions.insert(id, Auction {
+            id,
+            domain_name: request.domain_name,
+            mode: request.mode,
+            reserve_price_e8s: request.reserve_price_e8s,
+            min_increment_e8s: request.min_increment_e8s.unwrap_or(request.reserve_price_e8s / 100).max(1),
+            starts_at,
+            ends_at: starts_at + request.duration_ns,
+            created_by: caller,
+            bids: Vec::new(),
+            status: AuctionStatus::Open,
+        });
+        Ok(id)
+    }
+
+    /// Withdraws an open auction and refunds every bid.
+    pub fn cancel_auction(&mut self, caller: Principal, auction_id: u64) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::SeasonManager)?;
+        let auction = self.auctions.get_mut(&auction_id).ok_or(RegistryError::AuctionNotFound)?;
+        if !matches!(auction.status, AuctionStatus::Open) {
+            return Err(RegistryError::AuctionClosed);
+        }
+        auction.status = AuctionStatus::Cancelled;
+        self.refund_bids(auction_id, None);
+        Ok(())
+    }
+
+    /// Checks a bid before its payment is verified and claims its payment block. Sealed
+    /// auctions take a commitment to the bid and a deposit of at least the reserve, which
+    /// bidders may oversize to hide their bid.
+    pub fn begin_bid(&mut self, caller: Principal, request: &PlaceBidRequest, now: u64) -> Result<(), RegistryError> {
+        self.require_ledger()?;
+        let auction = self.auctions.get(&request.auction_id).ok_or(RegistryError::AuctionNotFound)?;
+        match (auction.mode, &request.commitment) {
+            (AuctionMode::Vickrey, Some(commitment)) if commitment.len() == 32 => {}
+            (AuctionMode::Vickrey, _) => {
+                return Err(RegistryError::InvalidInput {
+                    reason: "Sealed bids need a 32-byte SHA-256 commitment".to_string(),
+                });
+            }
+            (AuctionMode::English, Some(_)) => {
+                return Err(RegistryError::InvalidInput { reason: "English auctions take open bids".to_string() });
+            }
+            (AuctionMode::English, None) => {}
+        }
+        auction.check_bid(request.amount_e8s, now)?;
+        self.check_wallet_capacity(caller, None)?;
+        if !self.used_payment_blocks.insert(request.payment_block) {
+            return Err(RegistryError::PaymentInvalid { reason: "Payment block was already used".to_string() });
+        }
+        Ok(())
+    }
+
+    /// Releases the payment block of a bid whose payment could not be verified.
+    pub fn abort_bid(&mut self, request: &PlaceBidRequest) {
+        self.used_payment_blocks.remove(&request.payment_block);
+    }
+
+    /// Records a paid bid. A bid overtaken or closed out while its payment was being
+    /// verified is refunded.
+    pub fn record_bid(&mut self, caller: Principal, request: &PlaceBidRequest, now: u64) -> Result<(), RegistryError> {
+        let auction = self.auctions.get_mut(&request.auction_id).ok_or(RegistryError::AuctionNotFound)?;
+        if let Err(e) = auction.check_bid(request.amount_e8s, now) {
+            let memo = format!("Refund of rejected bid on {}", auction.domain_name);
+            self.queue_payout(caller, request.amount_e8s, memo);
+            return Err(e);
+        }
+        let sealed = request.commitment.is_some();
+        auction.bids.push(Bid {
+            bidder: caller,
+            amount_e8s: if sealed { 0 } else { request.amount_e8s },
+            deposit_e8s: request.amount_e8s,
+            payment_block: request.payment_block,
+            placed_at: now,
+            commitment: request.commitment.clone(),
+        });
+        Ok(())
+    }
+
+    /// Reveals a sealed bid once bidding has closed. Bids that are not revealed before
+    /// the auction settles do not count, and their deposit is refunded.
+    pub fn reveal_bid(&mut self, caller: Principal, request: RevealBidRequest, now: u64) -> Result<(), RegistryError> {
+        let auction = self.auctions.get_mut(&request.auction_id).ok_or(RegistryError::AuctionNotFound)?;
+        if auction.mode != AuctionMode::Vickrey || !matches!(auction.status, AuctionStatus::Open) {
+            return Err(RegistryError::AuctionClosed);
+        }
+        if now < auction.ends_at {
+            return Err(RegistryError::CommitmentTooNew { reveal_after: auction.ends_at });
+        }
+        if now >= auction.settles_at() {
+            return Err(RegistryError::CommitmentExpired);
+        }
+
+        let commitment = bid_commitment(request.auction_id, request.amount_e8s, caller, &request.salt);
+        let reserve_price_e8s = auction.reserve_price_e8s;
+        let bid = auction.bids
+            .iter_mut()
+            .find(|bid| bid.bidder == caller && bid.commitment.as_ref() == Some(&commitment))
+            .ok_or(RegistryError::CommitmentNotFound)?;
+        if request.amount_e8s < reserve_price_e8s {
+            return Err(RegistryError::BidTooLow { minimum_e8s: reserve_price_e8s });
+        }
+        if request.amount_e8s > bid.deposit_e8s {
+            return Err(RegistryError::InvalidInput { reason: "Bid is larger than its deposit".to_string() });
+        }
+        bid.amount_e8s = request.amount_e8s;
+        bid.commitment = None;
+        Ok(())
+    }
+
+    /// Bids visible to `caller`: all of them, except in a sealed auction that is still open.
+    pub fn auction_bids(&self, caller: Principal, auction_id: u64) -> Vec<Bid> {
+        let Some(auction) = self.auctions.get(&auction_id) else {
+            return Vec::new();
+        };
+        let sealed = auction.is_sealed() && !self.is_admin(caller);
+        auction.bids.iter().filter(|bid| !sealed || bid.bidder == caller).cloned().collect()
+    }
+
+    pub fn active_auctions(&self) -> Vec<AuctionInfo> {
+        let mut active: Vec<AuctionInfo> = self.auctions
+            .values()
+            .filter(|auction| matches!(auction.status, AuctionStatus::Open))
+            .map(Auction::info)
+            .collect();
+        active.sort_by_key(|auction| (auction.ends_at, auction.id));
+        active
+    }
+
+    /// When the next open auction is due to settle, for scheduling settlement.
+    pub fn next_auction_end(&self) -> Option<u64> {
+        self.auctions
+            .values()
+            .filter(|auction| matches!(auction.status, AuctionStatus::Open))
+            .map(Auction::settles_at)
+            .min()
+    }
+
+    /// Moves every auction that has ended into settlement. Auctions without a winning bid
+    /// close as Unsold; the others return the registration of the name to their winner.
+    /// A winner who can no longer take the name, for example because their wallet is full,
+    /// is passed over for the next bidder, at the price the remaining bids set.
+    pub fn start_due_settlements(&mut self, now: u64) -> Vec<(u64, PendingRegistration)> {
+        let mut due: Vec<u64> = self.auctions
+            .values()
+            .filter(|auction| matches!(auction.status, AuctionStatus::Open) && auction.settles_at() <= now)
+            .map(|auction| auction.id)
+            .collect();
+        due.sort();
+
+        let mut registrations = Vec::new();
+        for id in due {
+            let mut passed_over = HashSet::new();
+            let mut last_error = None;
+            loop {
+                let auction = &self.auctions[&id];
+                let Some((winner, price_e8s)) = auction.outcome(&passed_over) else {
+                    match last_error {
+                        Some(e) => self.fail_auction(id, e),
+                        None => self.auctions.get_mut(&id).unwrap().status = AuctionStatus::Unsold,
+                    }
+                    break;
+                };
+
+                let bidder = winner.bidder;
+                let pending = PendingRegistration {
+                    reservation_id: 0,
+                    domain_name: auction.domain_name.clone(),
+                    owner: bidder,
+                    administrator: bidder,
+                    operator: bidder,
+                    season_id: None,
+                    fee_e8s: price_e8s,
+                    payment_block: Some(winner.payment_block),
+                    kind: RegistrationKind::Auction { auction_id: id },
+                    was_gifted: false,
+                };
+                match self.reserve(pending, now) {
+                    Ok(pending) => {
+                        self.auctions.get_mut(&id).unwrap().status = AuctionStatus::Settling;
+                        registrations.push((id, pending));
+                        break;
+                    }
+                    Err(e) => {
+                        passed_over.insert(bidder);
+                        last_error = Some(e);
+                    }
+                }
+            }
+        }
+        registrations
+    }
+
+    /// Closes a settling auction once its winner's registration finished or failed, and
+    /// queues the refunds it owes.
+    pub fn finish_settlement(&mut self, auction_id: u64, result: &Result<RegistrationReceipt, RegistryError>) {
+        let Some(auction) = self.auctions.get_mut(&auction_id) else {
+            return;
+        };
+        if !matches!(auction.status, AuctionStatus::Settling) {
+            return;
+        }
+        match result {
+            Ok(receipt) => {
+                auction.status = AuctionStatus::Sold {
+                    winner: receipt.owner,
+                    price_e8s: receipt.fee_e8s,
+                    canister_id: receipt.canister_id,
+                };
+                let winning_bid = receipt.payment_block.map(|block| (block, receipt.fee_e8s));
+                self.refund_bids(auction_id, winning_bid);
+            }
+            Err(e) => self.fail_auction(auction_id, e.clone()),
+        }
+    }
+
+    fn fail_auction(&mut self, auction_id: u64, error: RegistryError) {
+        if let Some(auction) = self.auctions.get_mut(&auction_id) {
+            auction.status = AuctionStatus::Failed { reason: error.to_string() };
+        }
+        self.refund_bids(auction_id, None);
+    }
+
+    // Refunds every deposit but the winning one, given by its payment block and price,
+    // which gets back what it paid above the price
+    fn refund_bids(&mut self, auction_id: u64, winning_bid: Option<(u64, u64)>) {
+        let auction = &self.auctions[&auction_id];
+        let mut refunds = Vec::new();
+        for bid in &auction.bids {
+            let amount_e8s = match winning_bid {
+                Some((block, price_e8s)) if block == bid.payment_block => bid.deposit_e8s - price_e8s,
+                _ => bid.deposit_e8s,
+            };
+            if amount_e8s > 0 {
+                let memo = format!("Refund of bid on {}", auction.domain_name);
+                refunds.push((bid.bidder, amount_e8s, memo));
+            }
+        }
+        for (to, amount_e8s, memo) in refunds {
+            self.queue_payout(to, amount_e8s, memo);
+        }
+    }
+
+    // Auctions interrupted by an upgrade go back to Open and are settled again
+    pub(super) fn reopen_settling_auctions(&mut self) {
+        for auction in self.auctions.values_mut() {
+            if matches!(auction.status, AuctionStatus::Settling) {
+                auction.status = AuctionStatus::Open;
+            }
+        }
+    }
+
+    pub(super) fn queue_payout(&mut self, to: Principal, amount_e8s: u64, memo: String) {
+        let id = self.next_payout_id;
+        self.next_payout_id += 1;
+        self.payouts.push(Payout { id, to, amount_e8s, memo, sent_at: None, in_flight: false });
+    }
+
+    /// Marks the payouts that are due to be sent as in flight and returns them. A payout
+    /// keeps its creation time across retries, so that a transfer whose reply was lost is
+    /// deduplicated by the ledger rather than paid twice.
+    pub(super) fn start_payouts(&mut self, now: u64) -> Vec<Payout> {
+        self.payouts
+            .iter_mut()
+            .filter(|payout| !payout.in_flight || payout.sent_at.is_some_and(|sent_at| now.saturating_sub(sent_at) >= PAYOUT_RETRY_NS))
+            .map(|payout| {
+                if payout.sent_at.is_none_or(|sent_at| now.saturating_sub(sent_at) >= MAX_PAYOUT_ATTEMPT_AGE_NS) {
+                    payout.sent_at = Some(now);
+                }
+                payout.in_flight = true;
+                payout.clone()
+            })
+            .collect()
+    }
+
+    /// Removes a payout once its transfer went through, or queues it again.
+    pub(super) fn finish_payout(&mut self, payout_id: u64, sent: bool) {
+        if sent {
+            self.payouts.retain(|payout| payout.id != payout_id);
+        } else if let Some(payout) = self.payouts.iter_mut().find(|payout| payout.id == payout_id) {
+            payout.in_flight = false;
+        }
+    }
+
+    // Transfers suspended by an upgrade never complete; they go out again under their
+    // original creation time
+    pub(super) fn release_in_flight_payouts(&mut self) {
+        for payout in &mut self.payouts {
+            payout.in_flight = false;
+        }
+    }
+}
+
+impl<C: Clock, L: Ledger, M: Management> Environment<C, L, M> {
+    pub async fn place_bid(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: PlaceBidRequest,
+    ) -> Result<(), RegistryError> {
+        state.borrow_mut().begin_bid(caller, &request, self.clock.now())?;
+
+        if let Err(reason) = self.ledger.verify_payment(caller, request.payment_block, request.amount_e8s).await {
+            state.borrow_mut().abort_bid(&request);
+            return Err(RegistryError::PaymentInvalid { reason });
+        }
+
+        let recorded = state.borrow_mut().record_bid(caller, &request, self.clock.now());
+        if recorded.is_err() {
+            self.process_payouts(state).await;
+        }
+        recorded
+    }
+
+    /// Settles every auction that has ended and pays out the refunds. Returns the
+    /// receipts of the names that were sold.
+    pub async fn settle_auctions(&self, state: &RefCell<Registry>) -> Vec<RegistrationReceipt> {
+        let due = state.borrow_mut().start_due_settlements(self.clock.now());
+
+        let mut receipts = Vec::new();
+        for (auction_id, pending) in due {
+            let result = self.provision(state, pending).await;
+            state.borrow_mut().finish_settlement(auction_id, &result);
+            receipts.extend(result.ok());
+        }
+
+        self.process_payouts(state).await;
+        receipts
+    }
+
+    /// Sends the queued payouts. Each stays in the persisted queue until its transfer
+    /// succeeds; failed ones are sent again on the next attempt. The ledger fee comes out
+    /// of each payout, so the registry's account pays out exactly what it holds for others.
+    pub async fn process_payouts(&self, state: &RefCell<Registry>) {
+        let now = self.clock.now();
+        let due = state.borrow_mut().start_payouts(now);
+        for payout in due {
+            let amount_e8s = payout.amount_e8s.saturating_sub(TRANSFER_FEE_E8S);
+            if amount_e8s == 0 {
+                // Too small to cover the ledger fee
+                state.borrow_mut().finish_payout(payout.id, true);
+                continue;
+            }
+            let created_at_time = payout.sent_at.unwrap_or(now);
+            let sent = self.ledger.transfer(payout.to, amount_e8s, payout.id, created_at_time).await.is_ok();
+            state.borrow_mut().finish_payout(payout.id, sent);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ledger::TRANSFER_FEE_E8S as FEE;
+    use crate::registry::tests::{admin, environment, registry_with, run, user, FixedClock, MockLedger, MockManagement, NOW};
+
+    const ICP: u64 = crate::registry::E8S_PER_ICP;
+
+    fn auction_of(state: &RefCell<Registry>, mode: AuctionMode) -> u64 {
+        let request = CreateAuctionRequest {
+            domain_name: "ai".to_string(),
+            mode,
+            reserve_price_e8s: 10 * ICP,
+            min_increment_e8s: Some(ICP),
+            start_time: None,
+            duration_ns: MIN_AUCTION_DURATION_NS,
+        };
+        state.borrow_mut().create_auction(admin(), request, NOW).unwrap()
+    }
+
+    fn bid(auction_id: u64, amount_icp: u64, payment_block: u64) -> PlaceBidRequest {
+        PlaceBidRequest { auction_id, amount_e8s: amount_icp * ICP, payment_block, commitment: None }
+    }
+
+    fn sealed_bid(auction_id: u64, bidder: Principal, amount_icp: u64, deposit_icp: u64, payment_block: u64) -> PlaceBidRequest {
+        PlaceBidRequest {
+            auction_id,
+            amount_e8s: deposit_icp * ICP,
+            payment_block,
+            commitment: Some(bid_commitment(auction_id, amount_icp * ICP, bidder, b"pepper")),
+        }
+    }
+
+    fn reveal(auction_id: u64, amount_icp: u64) -> RevealBidRequest {
+        RevealBidRequest { auction_id, amount_e8s: amount_icp * ICP, salt: b"pepper".to_vec() }
+    }
+
+    // Seals each bid behind an exact deposit, then reveals them all once bidding closes
+    fn seal_and_reveal(env: &Environment<FixedClock, MockLedger, MockManagement>, state: &RefCell<Registry>, id: u64, bids: &[(u8, u64)]) {
+        for (block, &(bidder, amount_icp)) in bids.iter().enumerate() {
+            run(env.place_bid(state, user(bidder), sealed_bid(id, user(bidder), amount_icp, amount_icp, block as u64))).unwrap();
+        }
+        for &(bidder, amount_icp) in bids {
+            state.borrow_mut().reveal_bid(user(bidder), reveal(id, amount_icp), NOW + MIN_AUCTION_DURATION_NS).unwrap();
+        }
+    }
+
+    #[test]
+    fn vickrey_winner_pays_the_second_price() {
+        let state = RefCell::new(registry_with(&[]));
+        let env = environment(true, false);
+        let id = auction_of(&state, AuctionMode::Vickrey);
+
+        // The first bidder hides a bid of 30 behind a deposit of 40
+        run(env.place_bid(&state, user(1), sealed_bid(id, user(1), 30, 40, 1))).unwrap();
+        run(env.place_bid(&state, user(2), sealed_bid(id, user(2), 20, 20, 2))).unwrap();
+        run(env.place_bid(&state, user(3), sealed_bid(id, user(3), 12, 12, 3))).unwrap();
+        assert_eq!(state.borrow().auction_bids(user(2), id).len(), 1);
+        assert_eq!(state.borrow().auction_bids(admin(), id)[0].amount_e8s, 0);
+        assert_eq!(state.borrow().active_auctions()[0].highest_bid_e8s, None);
+        assert!(!state.borrow().is_available("ai", NOW));
+
+        env.clock.0.set(NOW + MIN_AUCTION_DURATION_NS);
+        for (bidder, amount_icp) in [(1, 30), (2, 20), (3, 12)] {
+            state.borrow_mut().reveal_bid(user(bidder), reveal(id, amount_icp), env.clock.now()).unwrap();
+        }
+        assert!(run(env.settle_auctions(&state)).is_empty());
+
+        env.clock.0.set(NOW + MIN_AUCTION_DURATION_NS + BID_REVEAL_PERIOD_NS);
+        let receipts = run(env.settle_auctions(&state));
+        assert_eq!(receipts.len(), 1);
+        assert_eq!(receipts[0].owner, user(1));
+        assert_eq!(receipts[0].fee_e8s, 20 * ICP);
+
+        let registry = state.borrow();
+        assert_eq!(registry.domains["ai"].owner, user(1));
+        assert_eq!(registry.wallet_domain(user(1)), Some("ai".to_string()));
+        assert_eq!(registry.auction_bids(user(2), id).len(), 3);
+        assert!(registry.payouts.is_empty());
+        assert_eq!(
+            *env.ledger.transfers.borrow(),
+            vec![(user(1), 20 * ICP - FEE), (user(2), 20 * ICP - FEE), (user(3), 12 * ICP - FEE)]
+        );
+    }
+
+    #[test]
+    fn sealed_bids_count_only_once_revealed() {
+        let state = RefCell::new(registry_with(&[]));
+        let env = environment(true, false);
+        let id = auction_of(&state, AuctionMode::Vickrey);
+
+        let open = run(env.place_bid(&state, user(1), bid(id, 30, 1)));
+        assert!(matches!(open, Err(RegistryError::InvalidInput { .. })));
+        run(env.place_bid(&state, user(1), sealed_bid(id, user(1), 30, 30, 2))).unwrap();
+        run(env.place_bid(&state, user(2), sealed_bid(id, user(2), 20, 25, 3))).unwrap();
+        run(env.place_bid(&state, user(3), sealed_bid(id, user(3), 50, 40, 4))).unwrap();
+
+        let ends_at = NOW + MIN_AUCTION_DURATION_NS;
+        let early = state.borrow_mut().reveal_bid(user(2), reveal(id, 20), NOW);
+        assert_eq!(early, Err(RegistryError::CommitmentTooNew { reveal_after: ends_at }));
+        let wrong = state.borrow_mut().reveal_bid(user(2), reveal(id, 21), ends_at);
+        assert_eq!(wrong, Err(RegistryError::CommitmentNotFound));
+        let uncovered = state.borrow_mut().reveal_bid(user(3), reveal(id, 50), ends_at);
+        assert!(matches!(uncovered, Err(RegistryError::InvalidInput { .. })));
+        state.borrow_mut().reveal_bid(user(2), reveal(id, 20), ends_at).unwrap();
+
+        // The highest bidder never reveals: their bid does not count and is refunded
+        let settles_at = ends_at + BID_REVEAL_PERIOD_NS;
+        let late = state.borrow_mut().reveal_bid(user(1), reveal(id, 30), settles_at);
+        assert_eq!(late, Err(RegistryError::CommitmentExpired));
+        env.clock.0.set(settles_at);
+        let receipts = run(env.settle_auctions(&state));
+        assert_eq!((receipts[0].owner, receipts[0].fee_e8s), (user(2), 10 * ICP));
+        assert_eq!(
+            *env.ledger.transfers.borrow(),
+            vec![(user(1), 30 * ICP - FEE), (user(2), 15 * ICP - FEE), (user(3), 40 * ICP - FEE)]
+        );
+    }
+
+    #[test]
+    fn english_bids_must_outbid_and_unmet_reserves_go_unsold() {
+        let state = RefCell::new(registry_with(&[]));
+        let env = environment(true, false);
+        let id = auction_of(&state, AuctionMode::English);
+
+        let low = run(env.place_bid(&state, user(1), bid(id, 9, 1)));
+        assert_eq!(low.err(), Some(RegistryError::BidTooLow { minimum_e8s: 10 * ICP }));
+        run(env.place_bid(&state, user(1), bid(id, 10, 2))).unwrap();
+        let reused = run(env.place_bid(&state, user(2), bid(id, 15, 2)));
+        assert!(matches!(reused, Err(RegistryError::PaymentInvalid { .. })));
+        let close = run(env.place_bid(&state, user(2), bid(id, 10, 3)));
+        assert_eq!(close.err(), Some(RegistryError::BidTooLow { minimum_e8s: 11 * ICP }));
+        run(env.place_bid(&state, user(2), bid(id, 11, 4))).unwrap();
+        assert_eq!(state.borrow().active_auctions()[0].highest_bid_e8s, Some(11 * ICP));
+
+        env.clock.0.set(NOW + MIN_AUCTION_DURATION_NS);
+        let late = run(env.place_bid(&state, user(3), bid(id, 50, 5)));
+        assert_eq!(late.err(), Some(RegistryError::AuctionClosed));
+        run(env.settle_auctions(&state));
+        assert_eq!(state.borrow().domains["ai"].owner, user(2));
+        assert_eq!(*env.ledger.transfers.borrow(), vec![(user(1), 10 * ICP - FEE)]);
+
+        // Nobody meets the reserve of a second auction
+        state.borrow_mut().domains.clear();
+        let unsold = auction_of(&state, AuctionMode::English);
+        env.clock.0.set(NOW + 2 * MIN_AUCTION_DURATION_NS);
+        assert!(run(env.settle_auctions(&state)).is_empty());
+        assert_eq!(state.borrow().auctions[&unsold].status, AuctionStatus::Unsold);
+    }
+
+    #[test]
+    fn winners_who_cannot_take_the_name_are_passed_over() {
+        let state = RefCell::new(registry_with(&[]));
+        let env = environment(true, false);
+        let id = auction_of(&state, AuctionMode::Vickrey);
+        seal_and_reveal(&env, &state, id, &[(1, 30), (2, 20), (3, 12)]);
+        state.borrow_mut().set_wallet_limit(admin(), user(1), Some(0)).unwrap();
+
+        env.clock.0.set(NOW + MIN_AUCTION_DURATION_NS + BID_REVEAL_PERIOD_NS);
+        let receipts = run(env.settle_auctions(&state));
+        assert_eq!((receipts[0].owner, receipts[0].fee_e8s), (user(2), 12 * ICP));
+        assert_eq!(
+            *env.ledger.transfers.borrow(),
+            vec![(user(1), 30 * ICP - FEE), (user(2), 8 * ICP - FEE), (user(3), 12 * ICP - FEE)]
+        );
+    }
+
+    #[test]
+    fn reserved_names_cannot_be_auctioned() {
+        let mut registry = registry_with(&[]);
+        let request = CreateAuctionRequest {
+            domain_name: "ICP".to_string(),
+            mode: AuctionMode::English,
+            reserve_price_e8s: ICP,
+            min_increment_e8s: None,
+            start_time: None,
+            duration_ns: MIN_AUCTION_DURATION_NS,
+        };
+        assert_eq!(registry.create_auction(admin(), request, NOW), Err(RegistryError::Reserved));
+    }
+
+    #[test]
+    fn failed_settlement_refunds_everyone() {
+        let state = RefCell::new(registry_with(&[]));
+        let env = environment(true, true);
+        let id = auction_of(&state, AuctionMode::Vickrey);
+        seal_and_reveal(&env, &state, id, &[(1, 30)]);
+
+        env.clock.0.set(NOW + MIN_AUCTION_DURATION_NS + BID_REVEAL_PERIOD_NS);
+        assert!(run(env.settle_auctions(&state)).is_empty());
+        assert!(matches!(state.borrow().auctions[&id].status, AuctionStatus::Failed { .. }));
+        assert!(state.borrow().reservations.is_empty());
+        assert!(state.borrow().is_available("ai", env.clock.now()));
+        assert_eq!(*env.ledger.transfers.borrow(), vec![(user(1), 30 * ICP - FEE)]);
+    }
+
+    #[test]
+    fn payouts_leave_the_queue_only_once_sent() {
+        let state = RefCell::new(registry_with(&[]));
+        state.borrow_mut().queue_payout(user(1), 10 * ICP, "Refund".to_string());
+
+        // A transfer whose reply is lost, as when the call traps, stays queued in flight
+        let lost = state.borrow_mut().start_payouts(NOW);
+        assert_eq!(lost[0].sent_at, Some(NOW));
+        assert!(state.borrow().payouts[0].in_flight);
+        assert!(state.borrow_mut().start_payouts(NOW + 1).is_empty());
+
+        // and goes out again under the same creation time, which the ledger deduplicates
+        let retried = state.borrow_mut().start_payouts(NOW + PAYOUT_RETRY_NS);
+        assert_eq!(retried[0].sent_at, Some(NOW));
+        state.borrow_mut().finish_payout(retried[0].id, false);
+        assert!(!state.borrow().payouts[0].in_flight);
+
+        let env = environment(true, false);
+        env.ledger.balance_e8s.set(10 * ICP);
+        run(env.process_payouts(&state));
+        assert!(state.borrow().payouts.is_empty());
+        assert_eq!(*env.ledger.transfers.borrow(), vec![(user(1), 10 * ICP - FEE)]);
+    }
+}
@@ -0,0 +1,404 @@
+// M-of-N approval of sensitive admin actions. With a threshold of one, admins call the
+// settings directly; above one, those calls fail and changes go through proposals.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use super::{AdminRole, Registry};
+use crate::error::RegistryError;
+use crate::{AdminGiftRequest, CreateSeasonRequest};
+
+pub const DEFAULT_PROPOSAL_DURATION_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000; // 7 days
+pub const MAX_PROPOSAL_PAGE_SIZE: u64 = 100;
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub enum AdminAction {
+    AddAdmin(Principal),
+    RemoveAdmin(Principal),
+    SetBaseFee(u64),
+    SetDomainCanisterWasm(Vec<u8>),
+    CreateSeason(CreateSeasonRequest),
+    SetApprovalThreshold(u64),
+    SetLedgerCanister(Option<Principal>),
+    SetMarketFee(u64),
+    WithdrawMarketFees(Principal),
+    GiftDomain(AdminGiftRequest), // Approves one `admin_gift_domain` call with this request
+    AssignRole(Principal, AdminRole),
+}
+
+/// Size and SHA-256 of a wasm module, which is too large to show itself.
+pub fn describe_wasm(wasm: &[u8]) -> String {
+    let hash: String = Sha256::digest(wasm).iter().map(|byte| format!("{:02x}", byte)).collect();
+    format!("{} bytes, sha256 {}", wasm.len(), hash)
+}
+
+impl AdminAction {
+    pub fn summary(&self) -> String {
+        match self {
+            AdminAction::AddAdmin(admin) => format!("Add admin {}", admin),
+            AdminAction::RemoveAdmin(admin) => format!("Remove admin {}", admin),
+            AdminAction::SetBaseFee(fee) => format!("Set base fee to {} e8s", fee),
+            AdminAction::SetDomainCanisterWasm(wasm) => format!("Set domain canister wasm ({})", describe_wasm(wasm)),
+            AdminAction::CreateSeason(request) => format!(
+                "Create season of {}..{} letter names, {} names at {} ICP",
+                request.min_letters,
+                request.max_letters.map(|max| max.to_string()).unwrap_or_default(),
+                request.total_allowed,
+                request.price_icp
+            ),
+            AdminAction::SetApprovalThreshold(threshold) => format!("Require {} approvals", threshold),
+            AdminAction::SetLedgerCanister(Some(ledger)) => format!("Take payments on ledger {}", ledger),
+            AdminAction::SetLedgerCanister(None) => "Disconnect the ledger".to_string(),
+            AdminAction::SetMarketFee(fee_bps) => format!("Set market fee to {} bps", fee_bps),
+            AdminAction::WithdrawMarketFees(to) => format!("Withdraw market fees to {}", to),
+            AdminAction::GiftDomain(request) => format!("Gift {} to {}", request.domain_name, request.recipient),
+            AdminAction::AssignRole(principal, role) => format!("Assign {:?} to {}", role, principal),
+        }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum ProposalStatus {
+    Open,
+    Executed,
+    Failed { reason: String },
+    Rejected,
+    Expired,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Vote {
+    pub voter: Principal,
+    pub approve: bool,
+    pub voted_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone)]
+pub struct Proposal {
+    pub id: u64,
+    pub action: AdminAction, // Wasm bytes are dropped once the proposal closes
+    pub summary: String,
+    pub proposer: Principal,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub votes: Vec<Vote>,
+    pub status: ProposalStatus,
+    pub closed_at: Option<u64>,
+}
+
+/// A proposal without its action payload.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ProposalInfo {
+    pub id: u64,
+    pub summary: String,
+    pub proposer: Principal,
+    pub created_at: u64,
+    pub expires_at: u64,
+    pub votes: Vec<Vote>,
+    pub approvals: u64,
+    pub threshold: u64,
+    pub status: ProposalStatus,
+    pub closed_at: Option<u64>,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ProposalPage {
+    pub proposals: Vec<ProposalInfo>,
+    pub total: u64,
+    pub next_offset: Option<u64>, // None when there are no more results
+}
+
+impl Registry {
+    /// Sensitive settings may only be changed directly while one approval is enough.
+    pub(super) fn require_single_signer(&self) -> Result<(), RegistryError> {
+        if self.governance_threshold > 1 {
+            return Err(RegistryError::ProposalRequired { threshold: self.governance_threshold });
+        }
+        Ok(())
+    }
+
+    /// Opens a proposal, counting the proposer's approval. It runs at once if that is enough.
+    pub fn propose_admin_action(
+        &mut self,
+        caller: Principal,
+        action: AdminAction,
+        duration_ns: Option<u64>,
+        now: u64,
+    ) -> Result<ProposalInfo, RegistryError> {
+        self.require_admin(caller)?;
+        let duration_ns = duration_ns.unwrap_or(DEFAULT_PROPOSAL_DURATION_NS);
+        if duration_ns == 0 || duration_ns > 4 * DEFAULT_PROPOSAL_DURATION_NS {
+            return Err(RegistryError::invalid_input("Proposals must stay open for at most 28 days"));
+        }
+
+        let id = self.next_proposal_id;
+        self.next_proposal_id += 1;
+        self.proposals.insert(id, Proposal {
+            id,
+            summary: action.summary(),
+            action,
+            proposer: caller,
+            created_at: now,
+            expires_at: now.saturating_add(duration_ns),
+            votes: vec![Vote { voter: caller, approve: true, voted_at: now }],
+            status: ProposalStatus::Open,
+            closed_at: None,
+        });
+        self.settle_proposal(id, now);
+        Ok(self.proposal_info(&self.proposals[&id]))
+    }
+
+    /// Records an admin's vote. Reaching the threshold runs the action; a proposal that can
+    /// no longer reach it is rejected.
+    pub fn vote_on_proposal(
+        &mut self,
+        caller: Principal,
+        proposal_id: u64,
+        approve: bool,
+        now: u64,
+    ) -> Result<ProposalInfo, RegistryError> {
+        self.require_admin(caller)?;
+        self.expire_proposals(now);
+        let proposal = self.proposals.get_mut(&proposal_id).ok_or(RegistryError::ProposalNotFound)?;
+        if proposal.status != ProposalStatus::Open {
+            return Err(RegistryError::ProposalClosed);
+        }
+        if proposal.votes.iter().any(|vote| vote.voter == caller) {
+            return Err(RegistryError::AlreadyVoted);
+        }
+        proposal.votes.push(Vote { voter: caller, approve, voted_at: now });
+
+        self.settle_proposal(proposal_id, now);
+        Ok(self.proposal_info(&self.proposals[&proposal_id]))
+    }
+
+    // Only votes of principals that are still admins count
+    fn tally(&self, proposal: &Proposal) -> (u64, u64) {
+        let counted = proposal.votes.iter().filter(|vote| self.is_admin(vote.voter));
+        let approvals = counted.clone().filter(|vote| vote.approve).count() as u64;
+        (approvals, counted.count() as u64 - approvals)
+    }
+
+    fn settle_proposal(&mut self, proposal_id: u64, now: u64) {
+        let Some(proposal) = self.proposals.get(&proposal_id) else {
+            return;
+        };
+        let (approvals, rejections) = self.tally(proposal);
+        let status = if approvals >= self.governance_threshold {
+            let action = proposal.action.clone();
+            let proposer = proposal.proposer;
+            match self.execute_action(action, proposer, now) {
+                Ok(()) => ProposalStatus::Executed,
+                Err(e) => ProposalStatus::Failed { reason: e.to_string() },
+            }
+        } else if (self.admins.len() as u64).saturating_sub(rejections) < self.governance_threshold {
+            ProposalStatus::Rejected
+        } else {
+            return;
+        };
+        self.close_proposal(proposal_id, status, now);
+    }
+
+    fn execute_action(&mut self, action: AdminAction, proposer: Principal, now: u64) -> Result<(), RegistryError> {
+        match action {
+            AdminAction::AddAdmin(admin) => self.apply_add_admin(admin),
+            AdminAction::RemoveAdmin(admin) => self.apply_remove_admin(admin),
+            AdminAction::SetBaseFee(fee) => {
+                self.base_fee = fee;
+                Ok(())
+            }
+            AdminAction::SetDomainCanisterWasm(wasm) => {
+                self.domain_canister_wasm = wasm;
+                Ok(())
+            }
+            AdminAction::CreateSeason(request) => self.open_season(proposer, request, now).map(|_| ()),
+            AdminAction::SetApprovalThreshold(threshold) => self.apply_threshold(threshold),
+            AdminAction::SetLedgerCanister(ledger) => {
+                self.ledger_canister = ledger;
+                Ok(())
+            }
+            AdminAction::SetMarketFee(fee_bps) => self.apply_market_fee(fee_bps),
+            AdminAction::WithdrawMarketFees(to) => self.apply_fee_withdrawal(to).map(|_| ()),
+            AdminAction::GiftDomain(request) => {
+                self.approved_gifts.push(request);
+                Ok(())
+            }
+            AdminAction::AssignRole(principal, role) => self.apply_assign_role(principal, role),
+        }
+    }
+
+    fn close_proposal(&mut self, proposal_id: u64, status: ProposalStatus, now: u64) {
+        if let Some(proposal) = self.proposals.get_mut(&proposal_id) {
+            proposal.status = status;
+            proposal.closed_at = Some(now);
+            if let AdminAction::SetDomainCanisterWasm(wasm) = &mut proposal.action {
+                *wasm = Vec::new();
+            }
+        }
+    }
+
+    /// Above a threshold of one, gifts need an executed `GiftDomain` proposal for the same
+    /// request. Returns its position among the approved gifts, to be used up once the gift
+    /// is reserved.
+    pub(super) fn gift_approval(&self, request: &AdminGiftRequest) -> Result<Option<usize>, RegistryError> {
+        if self.governance_threshold <= 1 {
+            return Ok(None);
+        }
+        match self.approved_gifts.iter().position(|approved| approved == request) {
+            Some(index) => Ok(Some(index)),
+            None => Err(RegistryError::ProposalRequired { threshold: self.governance_threshold }),
+        }
+    }
+
+    /// Sets the number of approvals sensitive actions need. Only direct while it is one.
+    pub fn set_approval_threshold(&mut self, caller: Principal, threshold: u64) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.require_single_signer()?;
+        self.apply_threshold(threshold)
+    }
+
+    fn apply_threshold(&mut self, threshold: u64) -> Result<(), RegistryError> {
+        if threshold == 0 || threshold > self.admins.len() as u64 {
+            return Err(RegistryError::invalid_input("Threshold must be between 1 and the number of admins"));
+        }
+        self.governance_threshold = threshold;
+        Ok(())
+    }
+
+    pub fn expire_proposals(&mut self, now: u64) {
+        let expired: Vec<u64> = self.proposals
+            .values()
+            .filter(|proposal| proposal.status == ProposalStatus::Open && proposal.expires_at <= now)
+            .map(|proposal| proposal.id)
+            .collect();
+        for id in expired {
+            self.close_proposal(id, ProposalStatus::Expired, now);
+        }
+    }
+
+    pub fn proposal_info(&self, proposal: &Proposal) -> ProposalInfo {
+        ProposalInfo {
+            id: proposal.id,
+            summary: proposal.summary.clone(),
+            proposer: proposal.proposer,
+            created_at: proposal.created_at,
+            expires_at: proposal.expires_at,
+            votes: proposal.votes.clone(),
+            approvals: self.tally(proposal).0,
+            threshold: self.governance_threshold,
+            status: proposal.status.clone(),
+            closed_at: proposal.closed_at,
+        }
+    }
+
+    /// Proposals newest first, optionally only the open ones.
+    pub fn proposals_page(&self, open_only: bool, offset: u64, limit: u64) -> ProposalPage {
+        let matching: Vec<&Proposal> = self.proposals
+            .values()
+            .rev()
+            .filter(|proposal| !open_only || proposal.status == ProposalStatus::Open)
+            .collect();
+        let total = matching.len() as u64;
+        let limit = limit.min(MAX_PROPOSAL_PAGE_SIZE);
+        let proposals: Vec<ProposalInfo> = matching
+            .into_iter()
+            .skip(offset as usize)
+            .take(limit as usize)
+            .map(|proposal| self.proposal_info(proposal))
+            .collect();
+        let end = offset.saturating_add(proposals.len() as u64);
+        ProposalPage { proposals, total, next_offset: (end < total).then_some(end) }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{admin, registry_with, user, NOW};
+    use crate::registry::E8S_PER_ICP;
+
+    fn three_admins_needing_two() -> Registry {
+        let mut registry = registry_with(&[]);
+        registry.add_admin(admin(), user(1)).unwrap();
+        registry.add_admin(admin(), user(2)).unwrap();
+        registry.set_approval_threshold(admin(), 2).unwrap();
+        registry
+    }
+
+    #[test]
+    fn sensitive_calls_need_enough_approvals() {
+        let mut registry = three_admins_needing_two();
+        assert_eq!(registry.set_base_fee(admin(), 1), Err(RegistryError::ProposalRequired { threshold: 2 }));
+        assert_eq!(registry.add_admin(admin(), user(9)), Err(RegistryError::ProposalRequired { threshold: 2 }));
+
+        let proposal = registry.propose_admin_action(admin(), AdminAction::SetBaseFee(7), None, NOW).unwrap();
+        assert_eq!(proposal.status, ProposalStatus::Open);
+        assert_eq!(proposal.approvals, 1);
+        assert_eq!(registry.vote_on_proposal(admin(), proposal.id, true, NOW).err(), Some(RegistryError::AlreadyVoted));
+        assert_eq!(registry.vote_on_proposal(user(5), proposal.id, true, NOW).err(), Some(RegistryError::NotAdmin));
+
+        let executed = registry.vote_on_proposal(user(1), proposal.id, true, NOW + 1).unwrap();
+        assert_eq!(executed.status, ProposalStatus::Executed);
+        assert_eq!(registry.base_fee, 7);
+        assert_eq!(registry.vote_on_proposal(user(2), proposal.id, true, NOW).err(), Some(RegistryError::ProposalClosed));
+    }
+
+    #[test]
+    fn proposals_are_rejected_expired_and_kept_in_history() {
+        let mut registry = three_admins_needing_two();
+        let wasm = registry.propose_admin_action(admin(), AdminAction::SetDomainCanisterWasm(vec![1; 64]), None, NOW).unwrap();
+        registry.vote_on_proposal(user(1), wasm.id, false, NOW).unwrap();
+        let rejected = registry.vote_on_proposal(user(2), wasm.id, false, NOW).unwrap();
+        assert_eq!(rejected.status, ProposalStatus::Rejected);
+        assert!(registry.domain_canister_wasm.is_empty());
+        assert!(matches!(&registry.proposals[&wasm.id].action, AdminAction::SetDomainCanisterWasm(bytes) if bytes.is_empty()));
+
+        let stale = registry.propose_admin_action(admin(), AdminAction::RemoveAdmin(user(2)), Some(10), NOW).unwrap();
+        let late = registry.vote_on_proposal(user(1), stale.id, true, NOW + 10);
+        assert_eq!(late.err(), Some(RegistryError::ProposalClosed));
+        assert!(registry.is_admin(user(2)));
+
+        let page = registry.proposals_page(false, 0, 1);
+        assert_eq!((page.total, page.next_offset), (2, Some(1)));
+        assert_eq!(page.proposals[0].status, ProposalStatus::Expired);
+        assert_eq!(page.proposals[0].votes.len(), 1);
+        assert!(registry.proposals_page(true, 0, 10).proposals.is_empty());
+    }
+
+    #[test]
+    fn fees_gifts_and_role_grants_need_enough_approvals() {
+        let mut registry = three_admins_needing_two();
+        registry.collected_fees_e8s = 5 * E8S_PER_ICP;
+        let required = Err(RegistryError::ProposalRequired { threshold: 2 });
+        assert_eq!(registry.set_market_fee(admin(), 100), required);
+        assert_eq!(registry.withdraw_market_fees(admin(), user(9)), Err(RegistryError::ProposalRequired { threshold: 2 }));
+        assert_eq!(registry.assign_role(admin(), user(9), AdminRole::Treasurer), required);
+
+        let withdrawal = registry.propose_admin_action(admin(), AdminAction::WithdrawMarketFees(user(9)), None, NOW).unwrap();
+        assert!(registry.payouts.is_empty());
+        registry.vote_on_proposal(user(1), withdrawal.id, true, NOW).unwrap();
+        assert_eq!(registry.collected_fees_e8s, 0);
+        assert_eq!((registry.payouts[0].to, registry.payouts[0].amount_e8s), (user(9), 5 * E8S_PER_ICP));
+
+        let grant = registry.propose_admin_action(admin(), AdminAction::AssignRole(user(9), AdminRole::Treasurer), None, NOW).unwrap();
+        registry.vote_on_proposal(user(2), grant.id, true, NOW).unwrap();
+        assert!(registry.has_role(user(9), AdminRole::Treasurer));
+
+        // An approved gift is good for one gift of that exact request
+        let season = CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed: 10, price_icp: 1, max_per_wallet: None };
+        registry.open_season(admin(), season, NOW).unwrap();
+        let gift = AdminGiftRequest {
+            domain_name: "present".to_string(),
+            recipient: user(3),
+            administrator: user(3),
+            operator: user(3),
+        };
+        assert_eq!(registry.begin_gift(admin(), &gift, NOW).err(), Some(RegistryError::ProposalRequired { threshold: 2 }));
+        let approval = registry.propose_admin_action(admin(), AdminAction::GiftDomain(gift.clone()), None, NOW).unwrap();
+        registry.vote_on_proposal(user(1), approval.id, true, NOW).unwrap();
+        registry.begin_gift(admin(), &gift, NOW).unwrap();
+        assert!(registry.approved_gifts.is_empty());
+        let another = AdminGiftRequest { domain_name: "another".to_string(), ..gift };
+        assert_eq!(registry.begin_gift(admin(), &another, NOW).err(), Some(RegistryError::ProposalRequired { threshold: 2 }));
+    }
+}
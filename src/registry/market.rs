@@ -0,0 +1,569 @@
+// Secondary market. Buyers pay into registry escrow; a sale moves the domain and queues
+// the seller's proceeds, less the protocol fee, in one step.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use super::{AdminRole, Environment, Registry, RESERVATION_TIMEOUT_NS};
+use crate::error::RegistryError;
+use crate::services::{Clock, Ledger, Management};
+
+pub const DEFAULT_MARKET_FEE_BPS: u64 = 250; // 2.5%
+pub const MAX_MARKET_FEE_BPS: u64 = 2_000; // 20%
+pub const DEFAULT_LISTING_DURATION_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+pub const MAX_SALES_PAGE_SIZE: u64 = 1_000;
+
+// The protocol's cut of a sale, computed in u128 so that large prices cannot overflow.
+// Never more than the price, since the fee is capped below 100%
+fn market_fee_e8s(price_e8s: u64, fee_bps: u64) -> u64 {
+    (u128::from(price_e8s) * u128::from(fee_bps) / 10_000) as u64
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Listing {
+    pub domain_name: String,
+    pub seller: Principal,
+    pub price_e8s: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+    // Buyer whose payment is being verified, and since when
+    pub pending_buyer: Option<(Principal, u64)>,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Offer {
+    pub id: u64,
+    pub domain_name: String,
+    pub buyer: Principal,
+    pub amount_e8s: u64,
+    pub payment_block: u64,
+    pub created_at: u64,
+    pub expires_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, PartialEq, Debug)]
+pub enum SaleKind {
+    Listing,
+    Offer,
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct Sale {
+    pub domain_name: String,
+    pub seller: Principal,
+    pub buyer: Principal,
+    pub price_e8s: u64,
+    pub fee_e8s: u64,
+    pub kind: SaleKind,
+    pub sold_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct SalePage {
+    pub sales: Vec<Sale>,
+    pub total: u64,
+    pub next_offset: Option<u64>, // None when there are no more results
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct ListDomainRequest {
+    pub domain_name: String,
+    pub price_e8s: u64,
+    pub duration_ns: Option<u64>, // Defaults to 30 days
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct BuyDomainRequest {
+    pub domain_name: String,
+    pub price_e8s: u64, // Must match the listing, so that a price change cannot surprise the buyer
+    pub payment_block: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct MakeOfferRequest {
+    pub domain_name: String,
+    pub amount_e8s: u64,
+    pub payment_block: u64,
+    pub duration_ns: u64,
+}
+
+impl Listing {
+    pub(super) fn is_locked(&self, now: u64) -> bool {
+        self.pending_buyer
+            .is_some_and(|(_, since)| now.saturating_sub(since) < RESERVATION_TIMEOUT_NS)
+    }
+}
+
+impl Registry {
+    fn require_owner(&self, domain_name: &str, caller: Principal, now: u64) -> Result<(), RegistryError> {
+        let domain = self.domains.get(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        if domain.expiration_time < now {
+            return Err(RegistryError::DomainNotFound);
+        }
+        if domain.owner != caller {
+            return Err(RegistryError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    fn check_buyer(&self, domain_name: &str, buyer: Principal) -> Result<(), RegistryError> {
+        if self.domains.get(domain_name).is_some_and(|domain| domain.owner == buyer) {
+            return Err(RegistryError::InvalidInput { reason: "You already own this domain".to_string() });
+        }
+        self.check_wallet_capacity(buyer, None)
+    }
+
+    pub(super) fn claim_payment_block(&mut self, payment_block: u64) -> Result<(), RegistryError> {
+        if !self.used_payment_blocks.insert(payment_block) {
+            return Err(RegistryError::PaymentInvalid { reason: "Payment block was already used".to_string() });
+        }
+        Ok(())
+    }
+
+    /// Offers the caller's domain for sale at a fixed price, replacing any earlier listing.
+    pub fn list_domain(&mut self, caller: Principal, request: ListDomainRequest, now: u64) -> Result<(), RegistryError> {
+        self.require_ledger()?;
+        self.require_owner(&request.domain_name, caller, now)?;
+        if request.price_e8s == 0 {
+            return Err(RegistryError::InvalidInput { reason: "Price must be positive".to_string() });
+        }
+        if self.listings.get(&request.domain_name).is_some_and(|listing| listing.is_locked(now)) {
+            return Err(RegistryError::SaleInProgress);
+        }
+
+        let duration = request.duration_ns.unwrap_or(DEFAULT_LISTING_DURATION_NS);
+        self.listings.insert(request.domain_name.clone(), Listing {
+            domain_name: request.domain_name,
+            seller: caller,
+            price_e8s: request.price_e8s,
+            created_at: now,
+            expires_at: now.saturating_add(duration),
+            pending_buyer: None,
+        });
+        Ok(())
+    }
+
+    pub fn cancel_listing(&mut self, caller: Principal, domain_name: &str, now: u64) -> Result<(), RegistryError> {
+        let listing = self.listings.get(domain_name).ok_or(RegistryError::ListingNotFound)?;
+        if listing.seller != caller && !self.is_admin(caller) {
+            return Err(RegistryError::Unauthorized);
+        }
+        if listing.is_locked(now) {
+            return Err(RegistryError::SaleInProgress);
+        }
+        self.listings.remove(domain_name);
+        Ok(())
+    }
+
+    /// Open listings whose seller still owns the domain, cheapest first.
+    pub fn active_listings(&self, now: u64) -> Vec<Listing> {
+        let mut listings: Vec<Listing> = self.listings
+            .values()
+            .filter(|listing| listing.expires_at > now && self.is_current_listing(listing, now))
+            .cloned()
+            .collect();
+        listings.sort_by(|a, b| a.price_e8s.cmp(&b.price_e8s).then_with(|| a.domain_name.cmp(&b.domain_name)));
+        listings
+    }
+
+    fn is_current_listing(&self, listing: &Listing, now: u64) -> bool {
+        self.domains
+            .get(&listing.domain_name)
+            .is_some_and(|domain| domain.owner == listing.seller && domain.expiration_time >= now)
+    }
+
+    /// Checks a purchase before its payment is verified and locks the listing for the buyer.
+    pub fn begin_purchase(&mut self, caller: Principal, request: &BuyDomainRequest, now: u64) -> Result<(), RegistryError> {
+        self.require_ledger()?;
+        let listing = self.listings.get(&request.domain_name).ok_or(RegistryError::ListingNotFound)?;
+        if listing.expires_at <= now || !self.is_current_listing(listing, now) {
+            return Err(RegistryError::ListingNotFound);
+        }
+        if listing.price_e8s != request.price_e8s {
+            return Err(RegistryError::ListingPriceChanged { price_e8s: listing.price_e8s });
+        }
+        if listing.is_locked(now) {
+            return Err(RegistryError::SaleInProgress);
+        }
+        self.check_buyer(&request.domain_name, caller)?;
+        self.claim_payment_block(request.payment_block)?;
+
+        if let Some(listing) = self.listings.get_mut(&request.domain_name) {
+            listing.pending_buyer = Some((caller, now));
+        }
+        Ok(())
+    }
+
+    /// Unlocks the listing and releases the payment block after a failed payment.
+    pub fn abort_purchase(&mut self, caller: Principal, request: &BuyDomainRequest) {
+        self.used_payment_blocks.remove(&request.payment_block);
+        if let Some(listing) = self.listings.get_mut(&request.domain_name) {
+            if listing.pending_buyer.is_some_and(|(buyer, _)| buyer == caller) {
+                listing.pending_buyer = None;
+            }
+        }
+    }
+
+    /// Completes a paid purchase. If the listing changed meanwhile the buyer is refunded.
+    pub fn complete_purchase(&mut self, caller: Principal, request: &BuyDomainRequest, now: u64) -> Result<Sale, RegistryError> {
+        let still_valid = match self.listings.get(&request.domain_name) {
+            Some(listing) if listing.pending_buyer.is_some_and(|(buyer, _)| buyer == caller) => {
+                if !self.is_current_listing(listing, now) {
+                    Err(RegistryError::ListingNotFound)
+                } else {
+                    self.check_buyer(&request.domain_name, caller)
+                }
+            }
+            _ => Err(RegistryError::ListingNotFound),
+        };
+        if let Err(e) = still_valid {
+            self.abort_purchase(caller, request);
+            // The block was spent on this purchase; it must not back another one
+            self.used_payment_blocks.insert(request.payment_block);
+            self.queue_payout(caller, request.price_e8s, format!("Refund of purchase of {}", request.domain_name));
+            return Err(e);
+        }
+
+        let listing = self.listings.remove(&request.domain_name).ok_or(RegistryError::ListingNotFound)?;
+        Ok(self.execute_sale(&request.domain_name, listing.seller, caller, request.price_e8s, SaleKind::Listing, now))
+    }
+
+    /// Escrows an offer on a domain, listed or not. Checked before its payment is verified.
+    pub fn begin_offer(&mut self, caller: Principal, request: &MakeOfferRequest, now: u64) -> Result<(), RegistryError> {
+        self.require_ledger()?;
+        let domain = self.domains.get(&request.domain_name).ok_or(RegistryError::DomainNotFound)?;
+        if domain.expiration_time < now {
+            return Err(RegistryError::DomainNotFound);
+        }
+        if request.amount_e8s == 0 {
+            return Err(RegistryError::InvalidInput { reason: "Offer must be positive".to_string() });
+        }
+        if request.duration_ns == 0 || request.duration_ns > DEFAULT_LISTING_DURATION_NS {
+            return Err(RegistryError::InvalidInput { reason: "Offers can last at most 30 days".to_string() });
+        }
+        self.check_buyer(&request.domain_name, caller)?;
+        self.claim_payment_block(request.payment_block)
+    }
+
+    pub fn abort_offer(&mut self, request: &MakeOfferRequest) {
+        self.used_payment_blocks.remove(&request.payment_block);
+    }
+
+    pub fn record_offer(&mut self, caller: Principal, request: &MakeOfferRequest, now: u64) -> u64 {
+        let id = self.next_offer_id;
+        self.next_offer_id += 1;
+        self.offers.insert(id, Offer {
+            id,
+            domain_name: request.domain_name.clone(),
+            buyer: caller,
+            amount_e8s: request.amount_e8s,
+            payment_block: request.payment_block,
+            created_at: now,
+            expires_at: now + request.duration_ns,
+        });
+        id
+    }
+
+    /// Withdraws an offer and refunds it. Admins may withdraw any offer.
+    pub fn cancel_offer(&mut self, caller: Principal, offer_id: u64) -> Result<(), RegistryError> {
+        let offer = self.offers.get(&offer_id).ok_or(RegistryError::OfferNotFound)?;
+        if offer.buyer != caller && !self.is_admin(caller) {
+            return Err(RegistryError::Unauthorized);
+        }
+        self.refund_offer(offer_id);
+        Ok(())
+    }
+
+    /// Sells the domain to the author of an offer. Only the owner can accept.
+    pub fn accept_offer(&mut self, caller: Principal, offer_id: u64, now: u64) -> Result<Sale, RegistryError> {
+        let offer = self.offers.get(&offer_id).ok_or(RegistryError::OfferNotFound)?;
+        if offer.expires_at <= now {
+            return Err(RegistryError::OfferNotFound);
+        }
+        let domain_name = offer.domain_name.clone();
+        let (buyer, amount_e8s) = (offer.buyer, offer.amount_e8s);
+        self.require_owner(&domain_name, caller, now)?;
+        if self.listings.get(&domain_name).is_some_and(|listing| listing.is_locked(now)) {
+            return Err(RegistryError::SaleInProgress);
+        }
+        self.check_buyer(&domain_name, buyer)?;
+
+        self.offers.remove(&offer_id);
+        self.listings.remove(&domain_name);
+        Ok(self.execute_sale(&domain_name, caller, buyer, amount_e8s, SaleKind::Offer, now))
+    }
+
+    /// Offers on a domain, highest first.
+    pub fn domain_offers(&self, domain_name: &str, now: u64) -> Vec<Offer> {
+        let mut offers: Vec<Offer> = self.offers
+            .values()
+            .filter(|offer| offer.domain_name == domain_name && offer.expires_at > now)
+            .cloned()
+            .collect();
+        offers.sort_by(|a, b| b.amount_e8s.cmp(&a.amount_e8s).then_with(|| a.id.cmp(&b.id)));
+        offers
+    }
+
+    /// Refunds expired offers and drops expired listings.
+    pub fn expire_market_entries(&mut self, now: u64) {
+        let expired: Vec<u64> = self.offers
+            .values()
+            .filter(|offer| offer.expires_at <= now)
+            .map(|offer| offer.id)
+            .collect();
+        for id in expired {
+            self.refund_offer(id);
+        }
+        self.listings.retain(|_, listing| listing.expires_at > now || listing.is_locked(now));
+    }
+
+    fn refund_offer(&mut self, offer_id: u64) {
+        if let Some(offer) = self.offers.remove(&offer_id) {
+            self.queue_payout(offer.buyer, offer.amount_e8s, format!("Refund of offer on {}", offer.domain_name));
+        }
+    }
+
+    // Moves the domain, pays the seller and refunds the other offers on it
+    fn execute_sale(&mut self, domain_name: &str, seller: Principal, buyer: Principal, price_e8s: u64, kind: SaleKind, now: u64) -> Sale {
+        self.move_domain(domain_name, buyer, now);
+
+        let fee_e8s = market_fee_e8s(price_e8s, self.market_fee_bps);
+        self.collected_fees_e8s = self.collected_fees_e8s.saturating_add(fee_e8s);
+        if price_e8s > fee_e8s {
+            self.queue_payout(seller, price_e8s - fee_e8s, format!("Proceeds of sale of {}", domain_name));
+        }
+
+        let outbid: Vec<u64> = self.offers
+            .values()
+            .filter(|offer| offer.domain_name == domain_name)
+            .map(|offer| offer.id)
+            .collect();
+        for id in outbid {
+            self.refund_offer(id);
+        }
+
+        let sale = Sale { domain_name: domain_name.to_string(), seller, buyer, price_e8s, fee_e8s, kind, sold_at: now };
+        self.sales.push(sale.clone());
+        sale
+    }
+
+    /// Sales, newest first, optionally for a single domain.
+    pub fn sales_page(&self, domain_name: Option<&str>, offset: u64, limit: u64) -> SalePage {
+        let limit = limit.clamp(1, MAX_SALES_PAGE_SIZE);
+        let matching: Vec<&Sale> = self.sales
+            .iter()
+            .rev()
+            .filter(|sale| domain_name.is_none_or(|name| sale.domain_name == name))
+            .collect();
+
+        let total = matching.len() as u64;
+        let sales: Vec<Sale> = matching.into_iter().skip(offset as usize).take(limit as usize).cloned().collect();
+        let next = offset + sales.len() as u64;
+        SalePage {
+            sales,
+            total,
+            next_offset: (next < total).then_some(next),
+        }
+    }
+
+    pub fn set_market_fee(&mut self, caller: Principal, fee_bps: u64) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Treasurer)?;
+        self.require_single_signer()?;
+        self.apply_market_fee(fee_bps)
+    }
+
+    pub(super) fn apply_market_fee(&mut self, fee_bps: u64) -> Result<(), RegistryError> {
+        if fee_bps > MAX_MARKET_FEE_BPS {
+            return Err(RegistryError::InvalidInput {
+                reason: format!("Market fee cannot exceed {} basis points", MAX_MARKET_FEE_BPS),
+            });
+        }
+        self.market_fee_bps = fee_bps;
+        Ok(())
+    }
+
+    /// Queues a payout of every collected protocol fee to `to`. Only direct while one
+    /// approval is enough.
+    pub fn withdraw_market_fees(&mut self, caller: Principal, to: Principal) -> Result<u64, RegistryError> {
+        self.require_role(caller, AdminRole::Treasurer)?;
+        self.require_single_signer()?;
+        self.apply_fee_withdrawal(to)
+    }
+
+    pub(super) fn apply_fee_withdrawal(&mut self, to: Principal) -> Result<u64, RegistryError> {
+        let amount_e8s = std::mem::take(&mut self.collected_fees_e8s);
+        if amount_e8s > 0 {
+            self.queue_payout(to, amount_e8s, "Market fee withdrawal".to_string());
+        }
+        Ok(amount_e8s)
+    }
+}
+
+impl<C: Clock, L: Ledger, M: Management> Environment<C, L, M> {
+    pub async fn buy_domain(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: BuyDomainRequest,
+    ) -> Result<Sale, RegistryError> {
+        state.borrow_mut().begin_purchase(caller, &request, self.clock.now())?;
+
+        if let Err(reason) = self.ledger.verify_payment(caller, request.payment_block, request.price_e8s).await {
+            state.borrow_mut().abort_purchase(caller, &request);
+            return Err(RegistryError::PaymentInvalid { reason });
+        }
+
+        let sale = state.borrow_mut().complete_purchase(caller, &request, self.clock.now());
+        self.process_payouts(state).await;
+        sale
+    }
+
+    pub async fn make_offer(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: MakeOfferRequest,
+    ) -> Result<u64, RegistryError> {
+        state.borrow_mut().begin_offer(caller, &request, self.clock.now())?;
+
+        if let Err(reason) = self.ledger.verify_payment(caller, request.payment_block, request.amount_e8s).await {
+            state.borrow_mut().abort_offer(&request);
+            return Err(RegistryError::PaymentInvalid { reason });
+        }
+
+        Ok(state.borrow_mut().record_offer(caller, &request, self.clock.now()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{admin, environment, registry_with, run, user, NOW};
+    use crate::ledger::TRANSFER_FEE_E8S as FEE;
+    use crate::registry::{Payout, E8S_PER_ICP as ICP};
+
+    fn registry_with_domain(owner: Principal) -> RefCell<Registry> {
+        RefCell::new(registry_with(&[("alpha", owner)]))
+    }
+
+    fn listing(price_icp: u64) -> ListDomainRequest {
+        ListDomainRequest { domain_name: "alpha".to_string(), price_e8s: price_icp * ICP, duration_ns: None }
+    }
+
+    fn purchase(price_icp: u64, payment_block: u64) -> BuyDomainRequest {
+        BuyDomainRequest { domain_name: "alpha".to_string(), price_e8s: price_icp * ICP, payment_block }
+    }
+
+    fn offer(amount_icp: u64, payment_block: u64) -> MakeOfferRequest {
+        MakeOfferRequest {
+            domain_name: "alpha".to_string(),
+            amount_e8s: amount_icp * ICP,
+            payment_block,
+            duration_ns: 60 * 60 * 1_000_000_000,
+        }
+    }
+
+    #[test]
+    fn market_fees_do_not_overflow_on_large_prices() {
+        assert_eq!(market_fee_e8s(100 * ICP, DEFAULT_MARKET_FEE_BPS), 25 * ICP / 10);
+        assert_eq!(market_fee_e8s(u64::MAX, MAX_MARKET_FEE_BPS), u64::MAX / 5);
+        assert_eq!(market_fee_e8s(u64::MAX, 0), 0);
+    }
+
+    #[test]
+    fn buying_a_listing_moves_the_domain_and_pays_the_seller() {
+        let state = registry_with_domain(user(1));
+        let env = environment(true, false);
+        state.borrow_mut().list_domain(user(1), listing(100), NOW).unwrap();
+        assert_eq!(state.borrow_mut().list_domain(user(2), listing(1), NOW).err(), Some(RegistryError::Unauthorized));
+        run(env.make_offer(&state, user(3), offer(50, 7))).unwrap();
+
+        let stale = run(env.buy_domain(&state, user(2), purchase(90, 1)));
+        assert_eq!(stale.err(), Some(RegistryError::ListingPriceChanged { price_e8s: 100 * ICP }));
+        let sale = run(env.buy_domain(&state, user(2), purchase(100, 1))).unwrap();
+        assert_eq!((sale.seller, sale.buyer, sale.fee_e8s), (user(1), user(2), 25 * ICP / 10));
+
+        let registry = state.borrow();
+        assert_eq!(registry.domains["alpha"].owner, user(2));
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert_eq!(registry.wallet_domain(user(2)), Some("alpha".to_string()));
+        assert!(registry.listings.is_empty() && registry.offers.is_empty());
+        assert_eq!(registry.collected_fees_e8s, 25 * ICP / 10);
+        assert_eq!(registry.sales_page(Some("alpha"), 0, 10).total, 1);
+        assert_eq!(
+            *env.ledger.transfers.borrow(),
+            vec![(user(1), 975 * ICP / 10 - FEE), (user(3), 50 * ICP - FEE)]
+        );
+    }
+
+    #[test]
+    fn concurrent_buyers_are_locked_out_and_stale_listings_refund() {
+        let state = registry_with_domain(user(1));
+        let env = environment(true, false);
+        state.borrow_mut().list_domain(user(1), listing(10), NOW).unwrap();
+
+        state.borrow_mut().begin_purchase(user(2), &purchase(10, 1), NOW).unwrap();
+        let second = state.borrow_mut().begin_purchase(user(3), &purchase(10, 2), NOW);
+        assert_eq!(second.err(), Some(RegistryError::SaleInProgress));
+
+        // The domain changes hands while the buyer's payment is verified
+        state.borrow_mut().move_domain("alpha", user(4), NOW);
+        let late = state.borrow_mut().complete_purchase(user(2), &purchase(10, 1), NOW);
+        assert_eq!(late.err(), Some(RegistryError::ListingNotFound));
+        assert_eq!(state.borrow().payouts, vec![Payout {
+            id: 1,
+            to: user(2),
+            amount_e8s: 10 * ICP,
+            memo: "Refund of purchase of alpha".to_string(),
+            sent_at: None,
+            in_flight: false,
+        }]);
+        assert!(state.borrow().used_payment_blocks.contains(&1));
+        assert!(run(env.buy_domain(&state, user(3), purchase(10, 2))).is_err());
+    }
+
+    #[test]
+    fn owners_accept_offers_and_expired_ones_are_refunded() {
+        let state = registry_with_domain(user(1));
+        let env = environment(true, false);
+        let low = run(env.make_offer(&state, user(2), offer(5, 1))).unwrap();
+        let high = run(env.make_offer(&state, user(3), offer(8, 2))).unwrap();
+        assert_eq!(state.borrow().domain_offers("alpha", NOW)[0].id, high);
+
+        assert_eq!(state.borrow_mut().accept_offer(user(2), high, NOW).err(), Some(RegistryError::Unauthorized));
+        state.borrow_mut().accept_offer(user(1), high, NOW).unwrap();
+        assert_eq!(state.borrow().domains["alpha"].owner, user(3));
+        assert!(!state.borrow().offers.contains_key(&low));
+
+        let again = run(env.make_offer(&state, user(5), offer(9, 3))).unwrap();
+        state.borrow_mut().expire_market_entries(NOW + 60 * 60 * 1_000_000_000);
+        assert!(!state.borrow().offers.contains_key(&again));
+        // The seller's proceeds, then the refunds of the outbid and the expired offer
+        let payees: Vec<Principal> = state.borrow().payouts.iter().map(|payout| payout.to).collect();
+        assert_eq!(payees, vec![user(1), user(2), user(5)]);
+    }
+
+    #[test]
+    fn escrow_holds_exactly_what_is_owed() {
+        let state = registry_with_domain(user(1));
+        let env = environment(true, false);
+        let owed = || {
+            let registry = state.borrow();
+            let offers: u64 = registry.offers.values().map(|offer| offer.amount_e8s).sum();
+            let payouts: u64 = registry.payouts.iter().map(|payout| payout.amount_e8s).sum();
+            offers + payouts + registry.collected_fees_e8s
+        };
+
+        state.borrow_mut().list_domain(user(1), listing(100), NOW).unwrap();
+        run(env.make_offer(&state, user(3), offer(50, 7))).unwrap();
+        assert_eq!(env.ledger.balance_e8s.get(), owed());
+        run(env.buy_domain(&state, user(2), purchase(100, 1))).unwrap();
+        assert_eq!(env.ledger.balance_e8s.get(), owed());
+
+        // Withdrawing every fee leaves nothing behind and nothing queued
+        assert_eq!(state.borrow_mut().withdraw_market_fees(admin(), user(9)), Ok(25 * ICP / 10));
+        run(env.process_payouts(&state));
+        assert!(state.borrow().payouts.is_empty());
+        assert_eq!((env.ledger.balance_e8s.get(), owed()), (0, 0));
+    }
+}
@@ -0,0 +1,256 @@
+// Per-domain permission matrix. The owner may do everything; the administrator, the
+// operator and any delegates may do what the owner granted them.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+use super::Registry;
+use crate::error::RegistryError;
+use crate::DomainRecord;
+
+pub const MAX_DELEGATES: usize = 20;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Permission {
+    Transfer,
+    Renew,
+    SetEndpoint,
+    EditMetadata,
+    UploadFiles, // Knowledge file, Candid file and MCP manifest
+    ManageSubdomains,
+}
+
+pub const ALL_PERMISSIONS: [Permission; 6] = [
+    Permission::Transfer,
+    Permission::Renew,
+    Permission::SetEndpoint,
+    Permission::EditMetadata,
+    Permission::UploadFiles,
+    Permission::ManageSubdomains,
+];
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum Grantee {
+    Administrator,
+    Operator,
+    Delegate(Principal),
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PermissionMatrix {
+    pub administrator: BTreeSet<Permission>,
+    pub operator: BTreeSet<Permission>,
+    pub delegates: BTreeMap<Principal, BTreeSet<Permission>>,
+}
+
+impl Default for PermissionMatrix {
+    // Administrators can do everything but give the domain away; operators manage the endpoint
+    fn default() -> Self {
+        PermissionMatrix {
+            administrator: BTreeSet::from([
+                Permission::Renew,
+                Permission::SetEndpoint,
+                Permission::EditMetadata,
+                Permission::UploadFiles,
+                Permission::ManageSubdomains,
+            ]),
+            operator: BTreeSet::from([Permission::SetEndpoint]),
+            delegates: BTreeMap::new(),
+        }
+    }
+}
+
+impl PermissionMatrix {
+    /// Everything `principal` may do on `domain`, or None when it has no role there.
+    fn granted_to(&self, domain: &DomainRecord, principal: Principal) -> Option<BTreeSet<Permission>> {
+        let mut granted: Option<BTreeSet<Permission>> = None;
+        let roles = [
+            (principal == domain.administrator).then_some(&self.administrator),
+            (principal == domain.operator).then_some(&self.operator),
+            self.delegates.get(&principal),
+        ];
+        for permissions in roles.into_iter().flatten() {
+            granted.get_or_insert_with(BTreeSet::new).extend(permissions.iter().copied());
+        }
+        granted
+    }
+}
+
+impl Registry {
+    pub fn domain_permissions(&self, domain_name: &str) -> PermissionMatrix {
+        self.permissions.get(domain_name).cloned().unwrap_or_default()
+    }
+
+    /// What `principal` may do on the domain. The owner holds every permission.
+    pub fn permissions_of(&self, domain_name: &str, principal: Principal) -> Vec<Permission> {
+        let Some(domain) = self.domains.get(domain_name) else {
+            return Vec::new();
+        };
+        if principal == domain.owner {
+            return ALL_PERMISSIONS.to_vec();
+        }
+        let matrix = self.domain_permissions(domain_name);
+        matrix.granted_to(domain, principal).unwrap_or_default().into_iter().collect()
+    }
+
+    /// Loads a domain on which the caller holds `permission`. Callers without any role on the
+    /// domain get `Unauthorized`; those whose role lacks the permission get `PermissionDenied`.
+    pub fn authorize(
+        &self,
+        domain_name: &str,
+        caller: Principal,
+        permission: Permission,
+    ) -> Result<&DomainRecord, RegistryError> {
+        let domain = self.domains.get(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        if caller == domain.owner {
+            return Ok(domain);
+        }
+        let granted = match self.permissions.get(domain_name) {
+            Some(matrix) => matrix.granted_to(domain, caller),
+            None => PermissionMatrix::default().granted_to(domain, caller),
+        };
+        match granted {
+            Some(permissions) if permissions.contains(&permission) => Ok(domain),
+            Some(_) => Err(RegistryError::PermissionDenied { permission }),
+            None => Err(RegistryError::Unauthorized),
+        }
+    }
+
+    fn require_domain_owner(&self, domain_name: &str, caller: Principal) -> Result<(), RegistryError> {
+        let domain = self.domains.get(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        if domain.owner != caller {
+            return Err(RegistryError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    /// Owner-only. Adds `permissions` to what the grantee already holds.
+    pub fn grant_permissions(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        grantee: Grantee,
+        permissions: Vec<Permission>,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.require_domain_owner(domain_name, caller)?;
+        let mut matrix = self.domain_permissions(domain_name);
+        match grantee {
+            Grantee::Administrator => matrix.administrator.extend(permissions),
+            Grantee::Operator => matrix.operator.extend(permissions),
+            Grantee::Delegate(delegate) => {
+                if delegate == Principal::anonymous() {
+                    return Err(RegistryError::invalid_input("Cannot delegate to the anonymous principal"));
+                }
+                if !matrix.delegates.contains_key(&delegate) && matrix.delegates.len() >= MAX_DELEGATES {
+                    return Err(RegistryError::invalid_input(format!("A domain can have at most {} delegates", MAX_DELEGATES)));
+                }
+                matrix.delegates.entry(delegate).or_default().extend(permissions);
+            }
+        }
+        self.permissions.insert(domain_name.to_string(), matrix);
+        self.touch(domain_name, now);
+        Ok(())
+    }
+
+    /// Owner-only. A delegate left without permissions is removed.
+    pub fn revoke_permissions(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        grantee: Grantee,
+        permissions: Vec<Permission>,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.require_domain_owner(domain_name, caller)?;
+        let mut matrix = self.domain_permissions(domain_name);
+        let held = match &grantee {
+            Grantee::Administrator => Some(&mut matrix.administrator),
+            Grantee::Operator => Some(&mut matrix.operator),
+            Grantee::Delegate(delegate) => matrix.delegates.get_mut(delegate),
+        };
+        if let Some(held) = held {
+            held.retain(|permission| !permissions.contains(permission));
+        }
+        matrix.delegates.retain(|_, held| !held.is_empty());
+        self.permissions.insert(domain_name.to_string(), matrix);
+        self.touch(domain_name, now);
+        Ok(())
+    }
+
+    /// Owner-only. The new administrator takes over the administrator's permissions.
+    pub fn set_domain_administrator(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        administrator: Principal,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.require_domain_owner(domain_name, caller)?;
+        if let Some(domain) = self.domains.get_mut(domain_name) {
+            domain.administrator = administrator;
+            domain.last_modified = now;
+        }
+        Ok(())
+    }
+
+    /// Owner-only. The new operator takes over the operator's permissions.
+    pub fn set_domain_operator(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        operator: Principal,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.require_domain_owner(domain_name, caller)?;
+        if let Some(domain) = self.domains.get_mut(domain_name) {
+            domain.operator = operator;
+            domain.last_modified = now;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{registry_with, user, NOW};
+
+    #[test]
+    fn roles_hold_only_what_the_owner_granted() {
+        let mut registry = registry_with(&[("alpha", user(1))]);
+        registry.set_domain_administrator(user(1), "alpha", user(2), NOW).unwrap();
+        registry.set_domain_operator(user(1), "alpha", user(3), NOW).unwrap();
+        assert_eq!(registry.set_domain_operator(user(2), "alpha", user(2), NOW), Err(RegistryError::Unauthorized));
+
+        // By default the administrator cannot give the domain away
+        let denied = registry.propose_transfer(user(2), "alpha", user(2), None, NOW);
+        assert_eq!(denied.err(), Some(RegistryError::PermissionDenied { permission: Permission::Transfer }));
+        registry.complete_renewal(user(2), "alpha", 1, NOW).unwrap();
+        assert_eq!(registry.complete_renewal(user(3), "alpha", 1, NOW).err(), Some(RegistryError::PermissionDenied { permission: Permission::Renew }));
+        assert_eq!(registry.complete_renewal(user(4), "alpha", 1, NOW).err(), Some(RegistryError::Unauthorized));
+
+        registry.grant_permissions(user(1), "alpha", Grantee::Operator, vec![Permission::Renew], NOW).unwrap();
+        registry.grant_permissions(user(1), "alpha", Grantee::Delegate(user(4)), vec![Permission::EditMetadata], NOW).unwrap();
+        registry.complete_renewal(user(3), "alpha", 1, NOW).unwrap();
+        assert_eq!(registry.permissions_of("alpha", user(4)), vec![Permission::EditMetadata]);
+        assert_eq!(registry.permissions_of("alpha", user(1)).len(), ALL_PERMISSIONS.len());
+
+        registry.revoke_permissions(user(1), "alpha", Grantee::Delegate(user(4)), vec![Permission::EditMetadata], NOW).unwrap();
+        assert!(registry.domain_permissions("alpha").delegates.is_empty());
+        registry.revoke_permissions(user(1), "alpha", Grantee::Administrator, vec![Permission::Renew], NOW).unwrap();
+        assert!(registry.complete_renewal(user(2), "alpha", 1, NOW).is_err());
+    }
+
+    #[test]
+    fn grants_do_not_survive_a_change_of_owner() {
+        let mut registry = registry_with(&[("alpha", user(1))]);
+        registry.grant_permissions(user(1), "alpha", Grantee::Delegate(user(2)), vec![Permission::Transfer], NOW).unwrap();
+        registry.propose_transfer(user(2), "alpha", user(3), None, NOW).unwrap();
+        registry.accept_transfer(user(3), "alpha", NOW).unwrap();
+
+        assert_eq!(registry.domains["alpha"].owner, user(3));
+        assert_eq!(registry.propose_transfer(user(2), "alpha", user(2), None, NOW).err(), Some(RegistryError::Unauthorized));
+        assert_eq!(registry.domain_permissions("alpha"), PermissionMatrix::default());
+    }
+}
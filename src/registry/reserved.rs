@@ -0,0 +1,253 @@
+// Reserved names. Exact names and `*` patterns such as `*-official` keep names out of
+// registration until a moderator releases them to a recipient.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use super::{is_valid_domain_name, AdminRole, Environment, PendingRegistration, Registry, RegistrationKind};
+use crate::error::RegistryError;
+use crate::services::{Clock, Ledger, Management};
+use crate::RegistrationReceipt;
+
+pub const MAX_RESERVED_IMPORT: usize = 10_000;
+pub const MAX_RESERVED_PATTERNS: usize = 100;
+pub const MAX_RESERVED_PAGE_SIZE: u64 = 1_000;
+// Patterns with fewer fixed characters would reserve whole swathes of the namespace
+const MIN_PATTERN_LITERALS: usize = 3;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ReservedImport {
+    pub added: u64,
+    pub already_reserved: u64,
+    pub invalid: Vec<String>, // Entries that are not valid domain names, as given
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct ReservedNamePage {
+    pub names: Vec<String>,
+    pub total: u64,
+    pub next_offset: Option<u64>, // None when there are no more results
+}
+
+/// Lowercased pattern, if it is a `*` wildcard over valid name characters.
+fn normalize_reserved_pattern(pattern: &str) -> Result<String, RegistryError> {
+    let pattern = pattern.trim().to_lowercase();
+    if !pattern.contains('*') {
+        return Err(RegistryError::invalid_input("A pattern needs at least one '*'; reserve exact names by name"));
+    }
+    if pattern.len() > 64 || !pattern.chars().all(|c| c == '*' || c == '-' || c.is_alphanumeric()) {
+        return Err(RegistryError::invalid_input("A pattern may only contain letters, digits, '-' and '*'"));
+    }
+    if pattern.chars().filter(|c| *c != '*').count() < MIN_PATTERN_LITERALS {
+        return Err(RegistryError::invalid_input(format!(
+            "A pattern needs at least {} characters besides '*'",
+            MIN_PATTERN_LITERALS
+        )));
+    }
+    Ok(pattern)
+}
+
+/// Glob match where `*` stands for any run of characters, including none.
+fn matches_pattern(pattern: &str, name: &str) -> bool {
+    let mut parts = pattern.split('*');
+    let first = parts.next().unwrap_or_default();
+    let Some(mut rest) = name.strip_prefix(first) else {
+        return false;
+    };
+    let mut parts: Vec<&str> = parts.collect();
+    let last = parts.pop().unwrap_or_default();
+    for part in parts {
+        match rest.find(part) {
+            Some(at) => rest = &rest[at + part.len()..],
+            None => return false,
+        }
+    }
+    rest.ends_with(last)
+}
+
+impl Registry {
+    pub fn is_reserved_name(&self, name: &str) -> bool {
+        let name = name.to_lowercase();
+        self.reserved_names.contains(&name)
+            || self.reserved_patterns.iter().any(|pattern| matches_pattern(pattern, &name))
+    }
+
+    pub fn add_reserved_name(&mut self, caller: Principal, name: String) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        let name = name.trim().to_lowercase();
+        if !is_valid_domain_name(&name) {
+            return Err(RegistryError::InvalidName);
+        }
+        self.reserved_names.insert(name);
+        Ok(())
+    }
+
+    /// Reserves every valid name in `names`, reporting the ones that are not.
+    pub fn add_reserved_names(&mut self, caller: Principal, names: Vec<String>) -> Result<ReservedImport, RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        if names.len() > MAX_RESERVED_IMPORT {
+            return Err(RegistryError::invalid_input(format!(
+                "At most {} names can be imported per call",
+                MAX_RESERVED_IMPORT
+            )));
+        }
+        let mut import = ReservedImport { added: 0, already_reserved: 0, invalid: Vec::new() };
+        for entry in names {
+            let name = entry.trim().to_lowercase();
+            if !is_valid_domain_name(&name) {
+                import.invalid.push(entry);
+            } else if self.reserved_names.insert(name) {
+                import.added += 1;
+            } else {
+                import.already_reserved += 1;
+            }
+        }
+        Ok(import)
+    }
+
+    pub fn remove_reserved_name(&mut self, caller: Principal, name: &str) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        if !self.reserved_names.remove(&name.trim().to_lowercase()) {
+            return Err(RegistryError::invalid_input(format!("{} is not a reserved name", name)));
+        }
+        Ok(())
+    }
+
+    pub fn add_reserved_pattern(&mut self, caller: Principal, pattern: &str) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        let pattern = normalize_reserved_pattern(pattern)?;
+        if !self.reserved_patterns.contains(&pattern) && self.reserved_patterns.len() >= MAX_RESERVED_PATTERNS {
+            return Err(RegistryError::invalid_input(format!(
+                "At most {} patterns can be reserved",
+                MAX_RESERVED_PATTERNS
+            )));
+        }
+        self.reserved_patterns.insert(pattern);
+        Ok(())
+    }
+
+    pub fn remove_reserved_pattern(&mut self, caller: Principal, pattern: &str) -> Result<(), RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        if !self.reserved_patterns.remove(&pattern.trim().to_lowercase()) {
+            return Err(RegistryError::invalid_input(format!("{} is not a reserved pattern", pattern)));
+        }
+        Ok(())
+    }
+
+    /// Exact reserved names in alphabetical order.
+    pub fn reserved_names_page(&self, offset: u64, limit: u64) -> ReservedNamePage {
+        let mut sorted: Vec<&String> = self.reserved_names.iter().collect();
+        sorted.sort();
+        let total = sorted.len() as u64;
+        let limit = limit.min(MAX_RESERVED_PAGE_SIZE);
+        let names: Vec<String> = sorted.into_iter().skip(offset as usize).take(limit as usize).cloned().collect();
+        let end = offset.saturating_add(names.len() as u64);
+        ReservedNamePage { names, total, next_offset: (end < total).then_some(end) }
+    }
+
+    /// Validates the release of a reserved name. The name stays reserved until the
+    /// registration completes, and it is registered outside any season.
+    pub fn begin_reserved_release(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        recipient: Principal,
+        now: u64,
+    ) -> Result<PendingRegistration, RegistryError> {
+        self.require_role(caller, AdminRole::Moderator)?;
+        if !is_valid_domain_name(domain_name) {
+            return Err(RegistryError::InvalidName);
+        }
+        if !self.is_reserved_name(domain_name) {
+            return Err(RegistryError::invalid_input(format!("{} is not reserved", domain_name)));
+        }
+        if recipient == Principal::anonymous() {
+            return Err(RegistryError::invalid_input("Cannot release a name to the anonymous principal"));
+        }
+        self.release_stale_reservations(now);
+        if !self.is_available(domain_name, now) {
+            return Err(RegistryError::Unavailable);
+        }
+        self.check_wallet_capacity(recipient, None)?;
+
+        self.reserve(PendingRegistration {
+            reservation_id: 0,
+            domain_name: domain_name.to_string(),
+            owner: recipient,
+            administrator: recipient,
+            operator: recipient,
+            season_id: None,
+            fee_e8s: 0,
+            payment_block: None,
+            kind: RegistrationKind::ReservedRelease,
+            was_gifted: true,
+        }, now)
+    }
+}
+
+impl<C: Clock, L: Ledger, M: Management> Environment<C, L, M> {
+    /// Registers a reserved name straight to `recipient`, free of charge.
+    pub async fn release_reserved_name(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        domain_name: String,
+        recipient: Principal,
+    ) -> Result<RegistrationReceipt, RegistryError> {
+        let pending = state.borrow_mut().begin_reserved_release(caller, &domain_name, recipient, self.clock.now())?;
+        self.provision(state, pending).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{admin, environment, registry_with, run, user, NOW};
+
+    #[test]
+    fn patterns_and_bulk_imports_reserve_names() {
+        let mut registry = registry_with(&[]);
+        assert_eq!(registry.add_reserved_pattern(admin(), "*-x"), Err(RegistryError::invalid_input(
+            "A pattern needs at least 3 characters besides '*'"
+        )));
+        registry.add_reserved_pattern(admin(), "*-Official").unwrap();
+        registry.add_reserved_pattern(admin(), "icp*bank").unwrap();
+        assert!(registry.is_reserved_name("acme-official"));
+        assert!(registry.is_reserved_name("ICPbank"));
+        assert!(registry.is_reserved_name("icp-central-bank"));
+        assert!(!registry.is_reserved_name("official-acme"));
+        assert!(!registry.can_register("acme-official", user(1), NOW));
+
+        let names = vec!["Brand".to_string(), "brand".to_string(), "-bad".to_string(), "api".to_string()];
+        let import = registry.add_reserved_names(admin(), names).unwrap();
+        assert_eq!(import, ReservedImport { added: 1, already_reserved: 2, invalid: vec!["-bad".to_string()] });
+        assert!(registry.is_reserved_name("BRAND"));
+        assert_eq!(registry.add_reserved_name(user(1), "other".to_string()), Err(RegistryError::NotAdmin));
+
+        registry.remove_reserved_name(admin(), "brand").unwrap();
+        registry.remove_reserved_pattern(admin(), "*-official").unwrap();
+        assert!(!registry.is_reserved_name("brand") && !registry.is_reserved_name("acme-official"));
+        let page = registry.reserved_names_page(0, 3);
+        assert_eq!(page.names, vec!["admin", "api", "canister"]);
+        assert_eq!(page.next_offset, Some(3));
+    }
+
+    #[test]
+    fn released_names_go_straight_to_the_recipient() {
+        let state = RefCell::new(registry_with(&[]));
+        state.borrow_mut().add_reserved_pattern(admin(), "*-official").unwrap();
+        let env = environment(true, false);
+
+        let unreserved = run(env.release_reserved_name(&state, admin(), "plain".to_string(), user(1)));
+        assert!(matches!(unreserved, Err(RegistryError::InvalidInput { .. })));
+        let receipt = run(env.release_reserved_name(&state, admin(), "api".to_string(), user(1))).unwrap();
+        assert_eq!((receipt.owner, receipt.season_id, receipt.fee_e8s), (user(1), None, 0));
+        run(env.release_reserved_name(&state, admin(), "acme-official".to_string(), user(2))).unwrap();
+
+        let registry = state.borrow();
+        assert!(registry.domains["api"].was_gifted);
+        assert!(!registry.reserved_names.contains("api"));
+        assert_eq!(registry.domains["acme-official"].owner, user(2));
+        assert!(registry.reservations.is_empty());
+    }
+}
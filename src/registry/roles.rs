@@ -0,0 +1,146 @@
+// Tiered admin roles. SuperAdmins are the principals in `admins` and may do everything;
+// the other roles each cover one area of administration.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+
+use super::Registry;
+use crate::error::RegistryError;
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum AdminRole {
+    SuperAdmin,
+    SeasonManager, // Seasons, their address lists and wallet caps, auctions
+    Moderator,     // Reserved names, short-name approvals, endpoint hosts and approvals
+    Treasurer,     // Registration and market fees, withdrawals and payouts
+    Support,       // Gifts, address-based creation, per-wallet limits
+}
+
+impl Registry {
+    pub fn has_role(&self, principal: Principal, role: AdminRole) -> bool {
+        self.is_admin(principal)
+            || self.admin_roles.get(&principal).is_some_and(|roles| roles.contains(&role))
+    }
+
+    /// Callers without any admin role get `NotAdmin`; those with other roles get `MissingRole`.
+    pub fn require_role(&self, caller: Principal, role: AdminRole) -> Result<(), RegistryError> {
+        if self.has_role(caller, role) {
+            Ok(())
+        } else if self.admin_roles.contains_key(&caller) {
+            Err(RegistryError::MissingRole { role })
+        } else {
+            Err(RegistryError::NotAdmin)
+        }
+    }
+
+    pub fn roles_of(&self, principal: Principal) -> Vec<AdminRole> {
+        let mut roles: BTreeSet<AdminRole> = self.admin_roles.get(&principal).cloned().unwrap_or_default();
+        if self.is_admin(principal) {
+            roles.insert(AdminRole::SuperAdmin);
+        }
+        roles.into_iter().collect()
+    }
+
+    /// Every principal holding a role, by principal.
+    pub fn role_assignments(&self) -> Vec<(Principal, Vec<AdminRole>)> {
+        let mut principals: BTreeSet<Principal> = self.admin_roles.keys().copied().collect();
+        principals.extend(self.admins.iter().copied());
+        principals.into_iter().map(|principal| (principal, self.roles_of(principal))).collect()
+    }
+
+    /// SuperAdmin-only, and only direct while one approval is enough. Assigning SuperAdmin
+    /// is the same as `add_admin`.
+    pub fn assign_role(&mut self, caller: Principal, principal: Principal, role: AdminRole) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.require_single_signer()?;
+        self.apply_assign_role(principal, role)
+    }
+
+    pub(super) fn apply_assign_role(&mut self, principal: Principal, role: AdminRole) -> Result<(), RegistryError> {
+        if role == AdminRole::SuperAdmin {
+            return self.apply_add_admin(principal);
+        }
+        if principal == Principal::anonymous() {
+            return Err(RegistryError::invalid_input("Cannot assign a role to the anonymous principal"));
+        }
+        self.admin_roles.entry(principal).or_default().insert(role);
+        Ok(())
+    }
+
+    /// SuperAdmin-only. Revoking SuperAdmin is the same as `remove_admin`.
+    pub fn revoke_role(&mut self, caller: Principal, principal: Principal, role: AdminRole) -> Result<(), RegistryError> {
+        if role == AdminRole::SuperAdmin {
+            return self.remove_admin(caller, principal);
+        }
+        self.require_admin(caller)?;
+        if let Some(roles) = self.admin_roles.get_mut(&principal) {
+            roles.remove(&role);
+            if roles.is_empty() {
+                self.admin_roles.remove(&principal);
+            }
+        }
+        Ok(())
+    }
+
+    /// Any admin role may read the audit log; everyone may once it is public.
+    pub fn check_audit_log_reader(&self, caller: Principal) -> Result<(), RegistryError> {
+        if self.audit_log_public || self.is_admin(caller) || self.admin_roles.contains_key(&caller) {
+            Ok(())
+        } else {
+            Err(RegistryError::NotAdmin)
+        }
+    }
+
+    pub fn set_audit_log_public(&mut self, caller: Principal, public: bool) -> Result<(), RegistryError> {
+        self.require_admin(caller)?;
+        self.audit_log_public = public;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{admin, registry_with, user};
+    use crate::CreateSeasonRequest;
+
+    fn season() -> CreateSeasonRequest {
+        CreateSeasonRequest { min_letters: 3, max_letters: None, total_allowed: 10, price_icp: 5, max_per_wallet: None }
+    }
+
+    #[test]
+    fn roles_grant_only_their_own_area() {
+        let mut registry = registry_with(&[]);
+        registry.assign_role(admin(), user(1), AdminRole::SeasonManager).unwrap();
+        registry.assign_role(admin(), user(2), AdminRole::Moderator).unwrap();
+        let escalation = registry.assign_role(user(1), user(3), AdminRole::Treasurer);
+        assert_eq!(escalation, Err(RegistryError::MissingRole { role: AdminRole::SuperAdmin }));
+
+        registry.create_season(user(1), season(), 0).unwrap();
+        assert_eq!(registry.set_base_fee(user(1), 1), Err(RegistryError::MissingRole { role: AdminRole::Treasurer }));
+        registry.add_reserved_name(user(2), "brand".to_string()).unwrap();
+        let wasm = registry.set_domain_canister_wasm(user(2), vec![1]);
+        assert_eq!(wasm, Err(RegistryError::MissingRole { role: AdminRole::SuperAdmin }));
+        assert_eq!(registry.set_base_fee(user(3), 1), Err(RegistryError::NotAdmin));
+        assert!(!registry.is_admin(user(1)));
+
+        registry.revoke_role(admin(), user(1), AdminRole::SeasonManager).unwrap();
+        assert_eq!(registry.deactivate_season(user(1), 1), Err(RegistryError::NotAdmin));
+        let assignments = registry.role_assignments();
+        assert_eq!(assignments.len(), 2);
+        assert!(assignments.contains(&(admin(), vec![AdminRole::SuperAdmin])));
+        assert!(assignments.contains(&(user(2), vec![AdminRole::Moderator])));
+    }
+
+    #[test]
+    fn super_admin_is_the_admin_set() {
+        let mut registry = registry_with(&[]);
+        registry.assign_role(admin(), user(1), AdminRole::SuperAdmin).unwrap();
+        assert!(registry.is_admin(user(1)));
+        assert_eq!(registry.roles_of(user(1)), vec![AdminRole::SuperAdmin]);
+        assert!(registry.has_role(user(1), AdminRole::Treasurer));
+
+        registry.revoke_role(user(1), admin(), AdminRole::SuperAdmin).unwrap();
+        assert!(!registry.is_admin(admin()));
+    }
+}
@@ -0,0 +1,242 @@
+// Model-based checks of season accounting under interleaved registrations. Each begin_*
+// call stands for a canister call suspended at its first await; Finish resumes one of
+// them, in any order, with the canister either created or not. Wait lets time pass so
+// that stuck reservations time out while their calls are still pending.
+use super::*;
+use proptest::prelude::*;
+use proptest::sample::Index;
+
+const NOW: u64 = 1_700_000_000_000_000_000;
+const NAMES: &[&str] = &["alpha", "bravo", "charlie", "delta", "echo", "foxtrot", "golf", "hotel"];
+const USERS: u8 = 6;
+
+#[derive(Clone, Debug)]
+enum Op {
+    CreateSeason { min_letters: u64, total_allowed: u64, price_icp: u64 },
+    DeactivateSeason { season: Index },
+    AuthorizeAddress { user: u8 },
+    BeginPurchase { user: u8, name: Index },
+    BeginGift { user: u8, name: Index },
+    BeginAddressCreation { user: u8, name: Index },
+    Finish { pending: Index, canister_created: bool },
+    Transfer { name: Index, to: u8 },
+    Wait { minutes: u64 },
+}
+
+fn op() -> impl Strategy<Value = Op> {
+    let user = 0..USERS;
+    prop_oneof![
+        1 => (4..=7u64, 1..=4u64, 1..=3u64).prop_map(|(min_letters, total_allowed, price_icp)| {
+            Op::CreateSeason { min_letters, total_allowed, price_icp }
+        }),
+        1 => any::<Index>().prop_map(|season| Op::DeactivateSeason { season }),
+        1 => user.clone().prop_map(|user| Op::AuthorizeAddress { user }),
+        4 => (user.clone(), any::<Index>()).prop_map(|(user, name)| Op::BeginPurchase { user, name }),
+        2 => (user.clone(), any::<Index>()).prop_map(|(user, name)| Op::BeginGift { user, name }),
+        2 => (user.clone(), any::<Index>()).prop_map(|(user, name)| Op::BeginAddressCreation { user, name }),
+        6 => (any::<Index>(), prop::bool::weighted(0.8))
+            .prop_map(|(pending, canister_created)| Op::Finish { pending, canister_created }),
+        1 => (any::<Index>(), user).prop_map(|(name, to)| Op::Transfer { name, to }),
+        1 => (1..=20u64).prop_map(|minutes| Op::Wait { minutes }),
+    ]
+}
+
+// user(0) is the admin
+fn user(n: u8) -> Principal {
+    Principal::self_authenticating([n])
+}
+
+fn address(n: u8) -> String {
+    format!("address-{}", n)
+}
+
+struct Harness {
+    registry: Registry,
+    now: u64,
+    pending: Vec<PendingRegistration>,
+    // registered_count of each season when it was first seen completed
+    completed_at: HashMap<u64, u64>,
+}
+
+impl Harness {
+    fn new() -> Self {
+        Harness {
+            registry: Registry::new(user(0)),
+            now: NOW,
+            pending: Vec::new(),
+            completed_at: HashMap::new(),
+        }
+    }
+
+    fn apply(&mut self, op: Op) {
+        let admin = user(0);
+        let registry = &mut self.registry;
+        let now = self.now;
+        let begun = match op {
+            Op::CreateSeason { min_letters, total_allowed, price_icp } => {
+                let request = CreateSeasonRequest { min_letters, max_letters: None, total_allowed, price_icp, max_per_wallet: None };
+                let _ = registry.create_season(admin, request, now);
+                None
+            }
+            Op::DeactivateSeason { season } => {
+                let mut ids: Vec<u64> = registry.seasons.keys().copied().collect();
+                ids.sort();
+                if !ids.is_empty() {
+                    let _ = registry.deactivate_season(admin, *season.get(&ids));
+                }
+                None
+            }
+            Op::AuthorizeAddress { user } => {
+                if let Some(id) = registry.active_season().map(|season| season.season_id) {
+                    let _ = registry.add_address_to_season(admin, id, address(user));
+                }
+                None
+            }
+            Op::BeginPurchase { user: n, name } => {
+                let request = RegistrationRequest {
+                    domain_name: name.get(NAMES).to_string(),
+                    administrator: user(n),
+                    operator: user(n),
+                    payment_block: 1,
+                };
+                registry.begin_registration(user(n), &request, now).ok()
+            }
+            Op::BeginGift { user: n, name } => {
+                let request = AdminGiftRequest {
+                    domain_name: name.get(NAMES).to_string(),
+                    recipient: user(n),
+                    administrator: user(n),
+                    operator: user(n),
+                };
+                registry.begin_gift(admin, &request, now).ok()
+            }
+            Op::BeginAddressCreation { user: n, name } => {
+                let request = AdminCreateDomainRequest {
+                    domain_name: name.get(NAMES).to_string(),
+                    recipient: user(n),
+                    administrator: user(n),
+                    operator: user(n),
+                    recipient_address: address(n),
+                };
+                registry.begin_address_creation(admin, &request, now).ok()
+            }
+            Op::Finish { pending, canister_created } => {
+                if !self.pending.is_empty() {
+                    let pending = self.pending.remove(pending.index(self.pending.len()));
+                    if canister_created {
+                        let _ = registry.complete_registration(pending, Principal::management_canister(), now);
+                    } else {
+                        registry.abort_registration(pending);
+                    }
+                }
+                None
+            }
+            Op::Transfer { name, to } => {
+                let name = name.get(NAMES);
+                if let Some(owner) = registry.domains.get(*name).map(|domain| domain.owner) {
+                    let _ = registry
+                        .propose_transfer(owner, name, user(to), None, now)
+                        .and_then(|_| registry.accept_transfer(user(to), name, now));
+                }
+                None
+            }
+            Op::Wait { minutes } => {
+                self.now += minutes * 60 * 1_000_000_000;
+                None
+            }
+        };
+        self.pending.extend(begun);
+    }
+
+    fn check_invariants(&mut self) -> Result<(), TestCaseError> {
+        let registry = &self.registry;
+
+        // Pending calls whose reservation timed out no longer hold anything
+        let holding: Vec<&PendingRegistration> = self.pending
+            .iter()
+            .filter(|pending| {
+                registry.reservations
+                    .get(&pending.domain_name)
+                    .is_some_and(|reservation| reservation.id == pending.reservation_id)
+            })
+            .collect();
+        prop_assert_eq!(holding.len(), registry.reservations.len(), "reservation without a pending call");
+        for pending in &holding {
+            let Some(limit) = registry.wallet_limit(pending.owner) else { continue };
+            let owned = registry.wallet_domains(pending.owner).len();
+            let in_flight = holding.iter().filter(|other| other.owner == pending.owner).count();
+            prop_assert!(
+                (owned + in_flight) as u64 <= limit,
+                "{} owns {} and registers {} with a limit of {}", pending.owner, owned, in_flight, limit
+            );
+        }
+
+        for (id, season) in &registry.seasons {
+            let tagged = registry.domains
+                .values()
+                .filter(|domain| domain.registration_season_id == Some(*id))
+                .count() as u64;
+            let in_flight = holding.iter().filter(|pending| pending.season_id == Some(*id)).count() as u64;
+
+            prop_assert_eq!(
+                season.registered_count, tagged + in_flight,
+                "season {} counts {} but has {} domains and {} registrations in flight",
+                id, season.registered_count, tagged, in_flight
+            );
+            prop_assert!(
+                season.registered_count <= season.total_allowed,
+                "season {} counts {} of {} allowed", id, season.registered_count, season.total_allowed
+            );
+
+            match self.completed_at.get(id) {
+                Some(&count) => {
+                    prop_assert!(!matches!(season.status, SeasonStatus::Active), "season {} reopened", id);
+                    prop_assert_eq!(season.registered_count, count, "completed season {} changed", id);
+                }
+                None if matches!(season.status, SeasonStatus::Completed) => {
+                    self.completed_at.insert(*id, season.registered_count);
+                }
+                None => {}
+            }
+        }
+
+        for (wallet, names) in &registry.owner_index {
+            prop_assert!(!names.is_empty(), "{} is indexed without domains", wallet);
+            for name in names {
+                let owner = registry.domains.get(name).map(|domain| domain.owner);
+                prop_assert_eq!(owner, Some(*wallet), "{} is indexed to {} but does not own it", wallet, name);
+            }
+        }
+        for (name, domain) in &registry.domains {
+            prop_assert!(
+                registry.owner_index.get(&domain.owner).is_some_and(|names| names.contains(name)),
+                "{} owns {} but is not indexed to it", domain.owner, name
+            );
+            if let Some(limit) = registry.wallet_limit(domain.owner) {
+                let owned = registry.wallet_domains(domain.owner).len() as u64;
+                prop_assert!(owned <= limit, "{} owns {} domains with a limit of {}", domain.owner, owned, limit);
+            }
+        }
+
+        Ok(())
+    }
+}
+
+proptest! {
+    #[test]
+    fn season_accounting_holds_under_interleaving(ops in prop::collection::vec(op(), 1..80)) {
+        let mut harness = Harness::new();
+        for op in ops {
+            harness.apply(op);
+            harness.check_invariants()?;
+        }
+
+        // Once every call has finished nothing is left in flight
+        while let Some(pending) = harness.pending.pop() {
+            let now = harness.now;
+            harness.registry.complete_registration(pending, Principal::management_canister(), now).ok();
+            harness.check_invariants()?;
+        }
+        prop_assert!(harness.registry.reservations.is_empty());
+    }
+}
@@ -0,0 +1,399 @@
+// Subdomains such as `alice.mycompany`. Each has its own owner, operator, target and MCP
+// endpoint, and lives only as long as the registration of its parent.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+
+use super::{is_valid_domain_name, Environment, Permission, Registry, RESERVATION_TIMEOUT_NS};
+use crate::error::RegistryError;
+use crate::services::{Clock, Ledger, Management};
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum MintPolicy {
+    OwnerOnly,
+    Allowlist(Vec<Principal>),
+    Anyone,
+}
+
+/// Set by the parent's owner. Whoever may manage the parent's subdomains always mints for free.
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SubdomainPolicy {
+    pub mint: MintPolicy,
+    pub fee_e8s: u64, // Paid to the parent's owner by everyone else
+    pub revocable: bool, // Applies to subdomains minted while it is set
+}
+
+impl Default for SubdomainPolicy {
+    fn default() -> Self {
+        SubdomainPolicy { mint: MintPolicy::OwnerOnly, fee_e8s: 0, revocable: true }
+    }
+}
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug)]
+pub struct SubdomainRecord {
+    pub parent: String,
+    pub owner: Principal,
+    pub operator: Principal,
+    pub target: Option<Principal>, // Defaults to the parent's canister
+    pub custom_mcp_endpoint: Option<String>,
+    pub created_at: u64,
+    pub last_modified: u64,
+    pub revocable: bool,
+    // Registration time of the parent it was minted under. A re-registered parent starts clean
+    pub parent_registered_at: u64,
+}
+
+#[derive(CandidType, Serialize, Deserialize)]
+pub struct MintSubdomainRequest {
+    pub parent: String,
+    pub label: String,
+    pub owner: Principal,
+    pub operator: Option<Principal>,
+    pub target: Option<Principal>,
+    pub fee_e8s: u64, // The fee the caller expects to pay
+    pub payment_block: Option<u64>,
+}
+
+/// Splits `label.parent` into its two parts, if the name is a valid subdomain.
+pub fn split_subdomain(name: &str) -> Option<(&str, &str)> {
+    let (label, parent) = name.split_once('.')?;
+    (is_valid_domain_name(label) && is_valid_domain_name(parent)).then_some((label, parent))
+}
+
+impl Registry {
+    /// Policy for subdomains of `parent`, or the default when none was set.
+    pub fn subdomain_policy(&self, parent: &str) -> SubdomainPolicy {
+        self.subdomain_policies.get(parent).cloned().unwrap_or_default()
+    }
+
+    pub fn set_subdomain_policy(
+        &mut self,
+        caller: Principal,
+        parent: &str,
+        policy: SubdomainPolicy,
+    ) -> Result<(), RegistryError> {
+        self.authorize(parent, caller, Permission::ManageSubdomains)?;
+        self.subdomain_policies.insert(parent.to_string(), policy);
+        Ok(())
+    }
+
+    /// A subdomain whose parent is still under the registration it was minted with.
+    pub fn live_subdomain(&self, name: &str, now: u64) -> Option<&SubdomainRecord> {
+        let subdomain = self.subdomains.get(name)?;
+        let parent = self.domains.get(&subdomain.parent)?;
+        (parent.expiration_time > now && parent.registration_time == subdomain.parent_registered_at)
+            .then_some(subdomain)
+    }
+
+    /// Live subdomains of `parent`, by name.
+    pub fn subdomains_of(&self, parent: &str, now: u64) -> Vec<(String, SubdomainRecord)> {
+        let mut subdomains: Vec<(String, SubdomainRecord)> = self.subdomains
+            .iter()
+            .filter(|(name, subdomain)| subdomain.parent == parent && self.live_subdomain(name, now).is_some())
+            .map(|(name, subdomain)| (name.clone(), subdomain.clone()))
+            .collect();
+        subdomains.sort_by(|a, b| a.0.cmp(&b.0));
+        subdomains
+    }
+
+    /// Checks a mint and returns the fee it costs. A paid mint also locks the name and
+    /// claims its payment block until `complete_subdomain_mint` or `abort_subdomain_mint`.
+    pub fn begin_subdomain_mint(
+        &mut self,
+        caller: Principal,
+        request: &MintSubdomainRequest,
+        now: u64,
+    ) -> Result<u64, RegistryError> {
+        let name = format!("{}.{}", request.label, request.parent);
+        if split_subdomain(&name).is_none() {
+            return Err(RegistryError::InvalidName);
+        }
+        if request.owner == Principal::anonymous() {
+            return Err(RegistryError::invalid_input("Subdomains cannot belong to the anonymous principal"));
+        }
+        let parent = self.domains.get(&request.parent).ok_or(RegistryError::DomainNotFound)?;
+        if parent.expiration_time <= now {
+            return Err(RegistryError::DomainNotFound);
+        }
+        if self.live_subdomain(&name, now).is_some() || self.is_mint_locked(&name, now) {
+            return Err(RegistryError::Unavailable);
+        }
+
+        let policy = self.subdomain_policy(&request.parent);
+        let is_editor = self.authorize(&request.parent, caller, Permission::ManageSubdomains).is_ok();
+        let may_mint = is_editor || match &policy.mint {
+            MintPolicy::OwnerOnly => false,
+            MintPolicy::Allowlist(minters) => minters.contains(&caller),
+            MintPolicy::Anyone => true,
+        };
+        if !may_mint {
+            return Err(RegistryError::Unauthorized);
+        }
+
+        let fee_e8s = if is_editor { 0 } else { policy.fee_e8s };
+        if fee_e8s != request.fee_e8s {
+            return Err(RegistryError::SubdomainFeeChanged { fee_e8s });
+        }
+        if fee_e8s > 0 {
+            self.require_ledger()?;
+            let payment_block = request.payment_block
+                .ok_or_else(|| RegistryError::invalid_input("This subdomain requires a payment block"))?;
+            self.claim_payment_block(payment_block)?;
+            self.pending_mints.insert(name, (caller, now));
+        }
+        Ok(fee_e8s)
+    }
+
+    /// Unlocks the name and releases the payment block after a failed payment.
+    pub fn abort_subdomain_mint(&mut self, request: &MintSubdomainRequest) {
+        if let Some(payment_block) = request.payment_block {
+            self.used_payment_blocks.remove(&payment_block);
+        }
+        self.pending_mints.remove(&format!("{}.{}", request.label, request.parent));
+    }
+
+    /// Stores the subdomain and pays the parent's owner. A paid mint that lost its lock or
+    /// its parent meanwhile is refunded.
+    pub fn complete_subdomain_mint(
+        &mut self,
+        caller: Principal,
+        request: MintSubdomainRequest,
+        fee_e8s: u64,
+        now: u64,
+    ) -> Result<String, RegistryError> {
+        let name = format!("{}.{}", request.label, request.parent);
+        if fee_e8s > 0 {
+            let locked = self.pending_mints.remove(&name).is_some_and(|(minter, _)| minter == caller);
+            let parent_active = self.domains.get(&request.parent).is_some_and(|parent| parent.expiration_time > now);
+            if !locked || !parent_active || self.live_subdomain(&name, now).is_some() {
+                self.queue_payout(caller, fee_e8s, format!("Refund of subdomain {}", name));
+                return Err(if parent_active { RegistryError::Unavailable } else { RegistryError::DomainNotFound });
+            }
+        }
+
+        let (parent_owner, parent_registered_at) = self.domains
+            .get(&request.parent)
+            .map(|parent| (parent.owner, parent.registration_time))
+            .ok_or(RegistryError::DomainNotFound)?;
+        let revocable = self.subdomain_policy(&request.parent).revocable;
+        if fee_e8s > 0 {
+            self.queue_payout(parent_owner, fee_e8s, format!("Subdomain fee for {}", name));
+        }
+        self.subdomains.insert(name.clone(), SubdomainRecord {
+            parent: request.parent.clone(),
+            owner: request.owner,
+            operator: request.operator.unwrap_or(request.owner),
+            target: request.target,
+            custom_mcp_endpoint: None,
+            created_at: now,
+            last_modified: now,
+            revocable,
+            parent_registered_at,
+        });
+        Ok(name)
+    }
+
+    fn is_mint_locked(&self, name: &str, now: u64) -> bool {
+        self.pending_mints
+            .get(name)
+            .is_some_and(|(_, started_at)| now < started_at.saturating_add(RESERVATION_TIMEOUT_NS))
+    }
+
+    /// Checks that the caller may edit a live subdomain: its owner, or also its operator
+    /// when `operator_allowed` is set.
+    pub fn check_subdomain_editor(
+        &self,
+        name: &str,
+        caller: Principal,
+        operator_allowed: bool,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        let subdomain = self.live_subdomain(name, now).ok_or(RegistryError::SubdomainNotFound)?;
+        if caller != subdomain.owner && !(operator_allowed && caller == subdomain.operator) {
+            return Err(RegistryError::Unauthorized);
+        }
+        Ok(())
+    }
+
+    fn editable_subdomain(
+        &mut self,
+        name: &str,
+        caller: Principal,
+        operator_allowed: bool,
+        now: u64,
+    ) -> Result<&mut SubdomainRecord, RegistryError> {
+        self.check_subdomain_editor(name, caller, operator_allowed, now)?;
+        let subdomain = self.subdomains.get_mut(name).ok_or(RegistryError::SubdomainNotFound)?;
+        subdomain.last_modified = now;
+        Ok(subdomain)
+    }
+
+    pub fn set_subdomain_target(
+        &mut self,
+        caller: Principal,
+        name: &str,
+        target: Option<Principal>,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.editable_subdomain(name, caller, true, now)?.target = target;
+        Ok(())
+    }
+
+    pub fn set_subdomain_operator(
+        &mut self,
+        caller: Principal,
+        name: &str,
+        operator: Principal,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        self.editable_subdomain(name, caller, false, now)?.operator = operator;
+        Ok(())
+    }
+
+    pub fn transfer_subdomain(
+        &mut self,
+        caller: Principal,
+        name: &str,
+        new_owner: Principal,
+        now: u64,
+    ) -> Result<(), RegistryError> {
+        if new_owner == Principal::anonymous() {
+            return Err(RegistryError::invalid_input("Subdomains cannot belong to the anonymous principal"));
+        }
+        let subdomain = self.editable_subdomain(name, caller, false, now)?;
+        subdomain.owner = new_owner;
+        subdomain.operator = new_owner;
+        Ok(())
+    }
+
+    /// Deletes a subdomain. Its owner may always release it; editors of the parent may
+    /// revoke it only if it was minted as revocable.
+    pub fn remove_subdomain(&mut self, caller: Principal, name: &str, now: u64) -> Result<(), RegistryError> {
+        let subdomain = self.live_subdomain(name, now).ok_or(RegistryError::SubdomainNotFound)?;
+        if caller != subdomain.owner {
+            self.authorize(&subdomain.parent, caller, Permission::ManageSubdomains)?;
+            if !subdomain.revocable {
+                return Err(RegistryError::SubdomainNotRevocable);
+            }
+        }
+        self.subdomains.remove(name);
+        Ok(())
+    }
+
+    pub fn set_subdomain_endpoint(&mut self, name: &str, endpoint: Option<String>, now: u64) -> Result<(), RegistryError> {
+        let subdomain = self.subdomains.get_mut(name).ok_or(RegistryError::SubdomainNotFound)?;
+        subdomain.custom_mcp_endpoint = endpoint;
+        subdomain.last_modified = now;
+        Ok(())
+    }
+
+    /// Forgets everything minted under `parent`, for when the name changes hands through
+    /// a new registration.
+    pub(super) fn drop_subdomains(&mut self, parent: &str) {
+        self.subdomains.retain(|_, subdomain| subdomain.parent != parent);
+        self.subdomain_policies.remove(parent);
+    }
+
+    /// Deletes subdomains whose parent expired or was registered again.
+    pub fn expire_subdomains(&mut self, now: u64) {
+        let dead: Vec<String> = self.subdomains
+            .keys()
+            .filter(|name| self.live_subdomain(name, now).is_none())
+            .cloned()
+            .collect();
+        for name in dead {
+            self.subdomains.remove(&name);
+        }
+        self.pending_mints.retain(|_, (_, started_at)| now < started_at.saturating_add(RESERVATION_TIMEOUT_NS));
+    }
+}
+
+impl<C: Clock, L: Ledger, M: Management> Environment<C, L, M> {
+    /// Mints a subdomain, collecting the parent's fee first when there is one.
+    pub async fn mint_subdomain(
+        &self,
+        state: &RefCell<Registry>,
+        caller: Principal,
+        request: MintSubdomainRequest,
+    ) -> Result<String, RegistryError> {
+        let fee_e8s = state.borrow_mut().begin_subdomain_mint(caller, &request, self.clock.now())?;
+
+        if let (true, Some(payment_block)) = (fee_e8s > 0, request.payment_block) {
+            if let Err(reason) = self.ledger.verify_payment(caller, payment_block, fee_e8s).await {
+                state.borrow_mut().abort_subdomain_mint(&request);
+                return Err(RegistryError::PaymentInvalid { reason });
+            }
+        }
+
+        let minted = state.borrow_mut().complete_subdomain_mint(caller, request, fee_e8s, self.clock.now());
+        if fee_e8s > 0 {
+            self.process_payouts(state).await;
+        }
+        minted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{environment, registry_with, run, user, NOW};
+    use crate::ledger::TRANSFER_FEE_E8S as FEE;
+    use crate::registry::REGISTRATION_PERIOD_NS;
+
+    fn mint(parent: &str, label: &str, owner: Principal, fee_e8s: u64) -> MintSubdomainRequest {
+        MintSubdomainRequest {
+            parent: parent.to_string(),
+            label: label.to_string(),
+            owner,
+            operator: None,
+            target: None,
+            fee_e8s,
+            payment_block: (fee_e8s > 0).then_some(7),
+        }
+    }
+
+    #[test]
+    fn minting_follows_the_parent_policy_and_pays_its_owner() {
+        let state = RefCell::new(registry_with(&[("company", user(1))]));
+        let env = environment(true, false);
+
+        let closed = run(env.mint_subdomain(&state, user(2), mint("company", "alice", user(2), 0)));
+        assert_eq!(closed.err(), Some(RegistryError::Unauthorized));
+        let name = run(env.mint_subdomain(&state, user(1), mint("company", "staff", user(3), 0))).unwrap();
+        assert_eq!(name, "staff.company");
+
+        let policy = SubdomainPolicy { mint: MintPolicy::Anyone, fee_e8s: 50_000, revocable: false };
+        state.borrow_mut().set_subdomain_policy(user(1), "company", policy).unwrap();
+        let stale = run(env.mint_subdomain(&state, user(2), mint("company", "alice", user(2), 0)));
+        assert_eq!(stale.err(), Some(RegistryError::SubdomainFeeChanged { fee_e8s: 50_000 }));
+        run(env.mint_subdomain(&state, user(2), mint("company", "alice", user(2), 50_000))).unwrap();
+        assert_eq!(env.ledger.transfers.borrow().as_slice(), &[(user(1), 50_000 - FEE)]);
+
+        let taken = run(env.mint_subdomain(&state, user(4), mint("company", "alice", user(4), 50_000)));
+        assert_eq!(taken.err(), Some(RegistryError::Unavailable));
+
+        let mut registry = state.borrow_mut();
+        assert!(!registry.subdomains["alice.company"].revocable);
+        assert_eq!(registry.remove_subdomain(user(1), "alice.company", NOW), Err(RegistryError::SubdomainNotRevocable));
+        registry.remove_subdomain(user(1), "staff.company", NOW).unwrap();
+        registry.remove_subdomain(user(2), "alice.company", NOW).unwrap();
+        assert!(registry.subdomains.is_empty());
+    }
+
+    #[test]
+    fn subdomains_disappear_with_their_parent() {
+        let mut registry = registry_with(&[("company", user(1))]);
+        registry.begin_subdomain_mint(user(1), &mint("company", "alice", user(2), 0), NOW).unwrap();
+        registry.complete_subdomain_mint(user(1), mint("company", "alice", user(2), 0), 0, NOW).unwrap();
+
+        registry.set_subdomain_target(user(2), "alice.company", Some(user(5)), NOW).unwrap();
+        assert_eq!(registry.set_subdomain_operator(user(3), "alice.company", user(3), NOW), Err(RegistryError::Unauthorized));
+        assert_eq!(registry.subdomains_of("company", NOW).len(), 1);
+
+        let expired = NOW + REGISTRATION_PERIOD_NS;
+        assert!(registry.live_subdomain("alice.company", expired).is_none());
+        assert!(registry.subdomains_of("company", expired).is_empty());
+        registry.expire_subdomains(expired);
+        assert!(registry.subdomains.is_empty());
+    }
+}
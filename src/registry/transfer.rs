@@ -0,0 +1,145 @@
+// Two-step ownership transfers: the owner proposes, the recipient accepts before a deadline.
+use candid::{CandidType, Principal};
+use serde::{Deserialize, Serialize};
+
+use super::{Permission, Registry};
+use crate::error::RegistryError;
+
+pub const DEFAULT_TRANSFER_WINDOW_NS: u64 = 7 * 24 * 60 * 60 * 1_000_000_000; // 7 days
+pub const MAX_TRANSFER_WINDOW_NS: u64 = 30 * 24 * 60 * 60 * 1_000_000_000; // 30 days
+
+#[derive(CandidType, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct PendingTransfer {
+    pub domain_name: String,
+    pub from: Principal,
+    pub to: Principal,
+    pub proposed_by: Principal,
+    pub proposed_at: u64,
+    pub expires_at: u64,
+}
+
+impl Registry {
+    /// Proposes `new_owner` for a domain, replacing any earlier proposal for it.
+    pub fn propose_transfer(
+        &mut self,
+        caller: Principal,
+        domain_name: &str,
+        new_owner: Principal,
+        window_ns: Option<u64>,
+        now: u64,
+    ) -> Result<PendingTransfer, RegistryError> {
+        let owner = self.authorize(domain_name, caller, Permission::Transfer)?.owner;
+        if new_owner == owner {
+            return Err(RegistryError::InvalidInput { reason: "The domain already belongs to this principal".to_string() });
+        }
+        if new_owner == Principal::anonymous() {
+            return Err(RegistryError::InvalidInput { reason: "Cannot transfer to the anonymous principal".to_string() });
+        }
+        let window_ns = window_ns.unwrap_or(DEFAULT_TRANSFER_WINDOW_NS);
+        if window_ns == 0 || window_ns > MAX_TRANSFER_WINDOW_NS {
+            return Err(RegistryError::InvalidInput { reason: "Transfers must be accepted within 30 days".to_string() });
+        }
+
+        let transfer = PendingTransfer {
+            domain_name: domain_name.to_string(),
+            from: owner,
+            to: new_owner,
+            proposed_by: caller,
+            proposed_at: now,
+            expires_at: now + window_ns,
+        };
+        self.pending_transfers.insert(domain_name.to_string(), transfer.clone());
+        Ok(transfer)
+    }
+
+    /// Completes a transfer proposed to the caller. The caller's wallet limit is checked
+    /// now, not when the transfer was proposed.
+    pub fn accept_transfer(&mut self, caller: Principal, domain_name: &str, now: u64) -> Result<(), RegistryError> {
+        let transfer = self.pending_transfers
+            .get(domain_name)
+            .filter(|transfer| transfer.to == caller)
+            .ok_or(RegistryError::TransferNotFound)?;
+        if transfer.expires_at <= now {
+            self.pending_transfers.remove(domain_name);
+            return Err(RegistryError::TransferExpired);
+        }
+        let domain = self.domains.get(domain_name).ok_or(RegistryError::DomainNotFound)?;
+        if domain.owner != transfer.from || domain.expiration_time < now {
+            self.pending_transfers.remove(domain_name);
+            return Err(RegistryError::TransferNotFound);
+        }
+        if self.listings.get(domain_name).is_some_and(|listing| listing.is_locked(now)) {
+            return Err(RegistryError::SaleInProgress);
+        }
+        self.check_wallet_capacity(caller, None)?;
+
+        self.move_domain(domain_name, caller, now);
+        Ok(())
+    }
+
+    /// Withdraws a proposal. The proposed recipient and anyone allowed to transfer the
+    /// domain may cancel it.
+    pub fn cancel_transfer(&mut self, caller: Principal, domain_name: &str) -> Result<(), RegistryError> {
+        let transfer = self.pending_transfers.get(domain_name).ok_or(RegistryError::TransferNotFound)?;
+        if caller != transfer.to {
+            self.authorize(domain_name, caller, Permission::Transfer)?;
+        }
+        self.pending_transfers.remove(domain_name);
+        Ok(())
+    }
+
+    /// Unexpired proposals from or to `principal`.
+    pub fn transfers_involving(&self, principal: Principal, now: u64) -> Vec<PendingTransfer> {
+        let mut transfers: Vec<PendingTransfer> = self.pending_transfers
+            .values()
+            .filter(|transfer| transfer.expires_at > now && (transfer.from == principal || transfer.to == principal))
+            .cloned()
+            .collect();
+        transfers.sort_by(|a, b| a.expires_at.cmp(&b.expires_at).then_with(|| a.domain_name.cmp(&b.domain_name)));
+        transfers
+    }
+
+    pub fn expire_transfers(&mut self, now: u64) {
+        self.pending_transfers.retain(|_, transfer| transfer.expires_at > now);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::registry::tests::{registry_with, user, NOW};
+
+    #[test]
+    fn recipient_accepts_before_the_deadline() {
+        let mut registry = registry_with(&[("alpha", user(1))]);
+        registry.propose_transfer(user(1), "alpha", user(2), None, NOW).unwrap();
+        assert_eq!(registry.domains["alpha"].owner, user(1));
+        assert_eq!(registry.transfers_involving(user(1), NOW), registry.transfers_involving(user(2), NOW));
+
+        assert_eq!(registry.accept_transfer(user(3), "alpha", NOW).err(), Some(RegistryError::TransferNotFound));
+        registry.accept_transfer(user(2), "alpha", NOW + 1).unwrap();
+        assert_eq!(registry.domains["alpha"].owner, user(2));
+        assert_eq!(registry.wallet_domain(user(1)), None);
+        assert_eq!(registry.wallet_domain(user(2)), Some("alpha".to_string()));
+        assert!(registry.pending_transfers.is_empty());
+    }
+
+    #[test]
+    fn stale_expired_or_cancelled_transfers_cannot_be_accepted() {
+        let mut registry = registry_with(&[("alpha", user(1)), ("bravo", user(2))]);
+
+        registry.propose_transfer(user(1), "alpha", user(2), Some(1_000), NOW).unwrap();
+        let full = registry.accept_transfer(user(2), "alpha", NOW);
+        assert_eq!(full.err(), Some(RegistryError::WalletAlreadyOwns { domain: "bravo".to_string() }));
+        assert_eq!(registry.accept_transfer(user(2), "alpha", NOW + 1_000).err(), Some(RegistryError::TransferExpired));
+
+        registry.propose_transfer(user(1), "alpha", user(3), None, NOW).unwrap();
+        registry.cancel_transfer(user(3), "alpha").unwrap();
+        assert_eq!(registry.accept_transfer(user(3), "alpha", NOW).err(), Some(RegistryError::TransferNotFound));
+
+        // Any other change of owner voids the proposal
+        registry.propose_transfer(user(1), "alpha", user(3), None, NOW).unwrap();
+        registry.move_domain("alpha", user(4), NOW);
+        assert_eq!(registry.accept_transfer(user(3), "alpha", NOW).err(), Some(RegistryError::TransferNotFound));
+    }
+}
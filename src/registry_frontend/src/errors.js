@@ -0,0 +1,90 @@
+// Decoding of the registry's `RegistryError` variant. Candid variants arrive as
+// single-key objects, e.g. { WalletAlreadyOwns: { domain: 'foo' } }.
+
+const MESSAGES = {
+  NotAdmin: () => 'Only admins can perform this action',
+  Unauthorized: () => 'Only the domain owner or someone they authorized can do this',
+  InvalidName: () => 'Invalid domain name format',
+  Reserved: () => 'This domain name is reserved',
+  ShortNameRequiresApproval: () => 'Short domain names require approval',
+  Unavailable: () => 'This domain name is not available',
+  WalletAlreadyOwns: ({ domain }) => `This wallet already owns ${domain}`,
+  DomainNotFound: () => 'Domain not found',
+  NoSeason: () => 'No registration season is open for this name length',
+  SeasonFull: () => 'The registration season is full',
+  SeasonNotFound: () => 'Season not found',
+  SeasonNotActive: () => 'The season is no longer active',
+  ActiveSeasonExists: () => 'There is already an active season',
+  AddressNotAuthorized: ({ address }) =>
+    `Address ${address} is not authorized for the current season`,
+  PaymentInvalid: ({ reason }) => `Payment rejected: ${reason}`,
+  CanisterCreationFailed: ({ reason }) => `Canister creation failed: ${reason}`,
+  InvalidInput: ({ reason }) => reason,
+  ContentTooLarge: ({ max_bytes }) => `Content exceeds ${max_bytes} bytes`,
+  NotCanisterName: () => 'Only canister names support this operation',
+  InvalidEndpoint: ({ reason }) => reason,
+  EndpointHostDenied: ({ host }) => `Endpoint host ${host} is denied`,
+  EndpointHostNotAllowed: ({ host }) =>
+    `Endpoint host ${host} is not on the allowlist`,
+  NoPendingChange: () => 'No pending endpoint change for this domain',
+  InvalidManifest: ({ reason }) => reason,
+  LastAdmin: () => 'Cannot remove the last admin',
+  RegistrationInProgress: ({ domain }) =>
+    `A registration of ${domain} is already in progress`,
+  ReservationExpired: () => 'Registration took too long and was cancelled',
+  CommitmentNotFound: () => 'No matching commitment from this wallet',
+  CommitmentTooNew: () => 'The commitment cannot be revealed yet',
+  CommitmentExpired: () => 'The commitment has expired',
+  AuctionNotFound: () => 'Auction not found',
+  AuctionClosed: () => 'This auction is not open for bids',
+  BidTooLow: ({ minimum_e8s }) =>
+    `Bids must be at least ${Number(minimum_e8s) / 1e8} ICP`,
+  ListingNotFound: () => 'This domain is not listed for sale',
+  ListingPriceChanged: ({ price_e8s }) =>
+    `The listing price changed to ${Number(price_e8s) / 1e8} ICP`,
+  SaleInProgress: () => 'Another purchase of this domain is in progress',
+  OfferNotFound: () => 'Offer not found',
+  TransferNotFound: () => 'No pending transfer of this domain to you',
+  TransferExpired: () => 'The transfer proposal has expired',
+  WalletLimitReached: ({ limit }) =>
+    `This wallet cannot own more than ${limit} domains here`,
+  SubdomainNotFound: () => 'Subdomain not found',
+  SubdomainFeeChanged: ({ fee_e8s }) =>
+    `The subdomain fee changed to ${Number(fee_e8s) / 1e8} ICP`,
+  SubdomainNotRevocable: () => 'This subdomain cannot be revoked by its parent',
+  PermissionDenied: ({ permission }) =>
+    `The domain owner has not granted you the ${Object.keys(permission)[0]} permission`,
+  ProposalRequired: ({ threshold }) =>
+    `This action needs a proposal approved by ${threshold} admins`,
+  ProposalNotFound: () => 'Proposal not found',
+  ProposalClosed: () => 'The proposal is no longer open',
+  AlreadyVoted: () => 'You already voted on this proposal',
+  MissingRole: ({ role }) => `This action needs the ${Object.keys(role)[0]} role`,
+  LedgerUnavailable: () => 'Payments are unavailable until a ledger is configured',
+};
+
+export function errorKind(error) {
+  return Object.keys(error)[0];
+}
+
+export function describeRegistryError(error) {
+  const kind = errorKind(error);
+  const message = MESSAGES[kind];
+  return message ? message(error[kind] ?? {}) : kind;
+}
+
+export class RegistryCallError extends Error {
+  constructor(error) {
+    super(describeRegistryError(error));
+    this.kind = errorKind(error);
+    this.details = error[this.kind];
+  }
+}
+
+// Returns the `Ok` value of a registry result or throws a RegistryCallError
+export function unwrap(result) {
+  if ('Err' in result) {
+    throw new RegistryCallError(result.Err);
+  }
+  return result.Ok;
+}
@@ -0,0 +1,331 @@
+// src/search.rs - Full-text index over domain metadata and knowledge files
+use std::collections::{HashMap, HashSet};
+
+// BM25 tuning constants (standard defaults)
+const BM25_K1: f64 = 1.2;
+const BM25_B: f64 = 0.75;
+
+const SNIPPET_CONTEXT_CHARS: usize = 60;
+const SNIPPET_MAX_CHARS: usize = 160;
+
+const STOP_WORDS: &[&str] = &[
+    "a", "an", "and", "are", "as", "at", "be", "but", "by", "can", "for", "from", "has", "have",
+    "how", "i", "if", "in", "into", "is", "it", "its", "me", "my", "of", "on", "or", "our", "so",
+    "than", "that", "the", "their", "them", "then", "there", "these", "they", "this", "to", "was",
+    "we", "what", "when", "where", "which", "who", "will", "with", "you", "your",
+];
+
+/// Splits text into lowercase words, drops stop words and reduces each word to its stem.
+pub fn analyze(text: &str) -> Vec<String> {
+    words(text)
+        .filter_map(|(_, word)| analyze_word(word))
+        .collect()
+}
+
+fn words(text: &str) -> impl Iterator<Item = (usize, &str)> {
+    text.split(|c: char| !c.is_alphanumeric())
+        .filter(|word| !word.is_empty())
+        .map(move |word| (word.as_ptr() as usize - text.as_ptr() as usize, word))
+}
+
+fn analyze_word(word: &str) -> Option<String> {
+    let lowered = word.to_lowercase();
+    if lowered.chars().count() < 2 || STOP_WORDS.contains(&lowered.as_str()) {
+        return None;
+    }
+    Some(stem(&lowered))
+}
+
+/// Light suffix-stripping stemmer: folds plurals and the most common verb and adverb
+/// endings so that "swapping", "swaps" and "swapped" all index as "swap".
+pub fn stem(word: &str) -> String {
+    if !word.is_ascii() {
+        return word.to_string();
+    }
+
+    let mut stem = word.to_string();
+    if let Some(base) = stem.strip_suffix("ies") {
+        if base.len() >= 2 {
+            stem = format!("{}y", base);
+        }
+    } else if stem.ends_with("sses") {
+        stem.truncate(stem.len() - 2);
+    } else if stem.ends_with('s') && !stem.ends_with("ss") && !stem.ends_with("us") && stem.len() > 3 {
+        stem.truncate(stem.len() - 1);
+    }
+
+    for suffix in ["ingly", "edly", "ing", "ed", "ly"] {
+        if let Some(base) = stem.strip_suffix(suffix) {
+            if base.len() >= 3 && base.chars().any(is_vowel) {
+                stem = undouble(base);
+                break;
+            }
+        }
+    }
+
+    stem
+}
+
+fn is_vowel(c: char) -> bool {
+    matches!(c, 'a' | 'e' | 'i' | 'o' | 'u' | 'y')
+}
+
+// "swapp" -> "swap", but keep "ll", "ss" and "zz" ("install", "access", "buzz")
+fn undouble(base: &str) -> String {
+    let bytes = base.as_bytes();
+    let len = bytes.len();
+    if len >= 2 && bytes[len - 1] == bytes[len - 2] && !matches!(bytes[len - 1], b'l' | b's' | b'z') {
+        base[..len - 1].to_string()
+    } else {
+        base.to_string()
+    }
+}
+
+struct IndexedDocument {
+    length: u32,
+    terms: Vec<String>, // Distinct terms, so removal only touches this document's postings
+}
+
+#[derive(Default)]
+pub struct TextIndex {
+    postings: HashMap<String, HashMap<String, u32>>, // term -> document -> term frequency
+    documents: HashMap<String, IndexedDocument>,
+    total_length: u64,
+}
+
+impl TextIndex {
+    /// Replaces whatever was indexed for `document` with `text`. Empty text removes it.
+    pub fn index_document(&mut self, document: &str, text: &str) {
+        self.remove_document(document);
+
+        let terms = analyze(text);
+        if terms.is_empty() {
+            return;
+        }
+
+        let mut frequencies: HashMap<String, u32> = HashMap::new();
+        for term in &terms {
+            *frequencies.entry(term.clone()).or_default() += 1;
+        }
+        let distinct: Vec<String> = frequencies.keys().cloned().collect();
+        for (term, frequency) in frequencies {
+            self.postings.entry(term).or_default().insert(document.to_string(), frequency);
+        }
+
+        self.documents.insert(document.to_string(), IndexedDocument {
+            length: terms.len() as u32,
+            terms: distinct,
+        });
+        self.total_length += terms.len() as u64;
+    }
+
+    pub fn remove_document(&mut self, document: &str) {
+        let Some(indexed) = self.documents.remove(document) else {
+            return;
+        };
+        self.total_length -= indexed.length as u64;
+        for term in indexed.terms {
+            if let Some(documents) = self.postings.get_mut(&term) {
+                documents.remove(document);
+                if documents.is_empty() {
+                    self.postings.remove(&term);
+                }
+            }
+        }
+    }
+
+    /// Documents containing every term of the query.
+    pub fn documents_matching_all(&self, query: &str) -> HashSet<String> {
+        let terms: HashSet<String> = analyze(query).into_iter().collect();
+        let mut postings = terms.iter().map(|term| self.postings.get(term));
+
+        let mut matches: HashSet<String> = match postings.next() {
+            Some(Some(documents)) => documents.keys().cloned().collect(),
+            _ => return HashSet::new(),
+        };
+        for documents in postings {
+            match documents {
+                Some(documents) => matches.retain(|document| documents.contains_key(document)),
+                None => return HashSet::new(),
+            }
+        }
+        matches
+    }
+
+    /// BM25-ranked documents containing at least one query term, best first.
+    pub fn search(&self, query: &str) -> Vec<(String, f64)> {
+        let terms: HashSet<String> = analyze(query).into_iter().collect();
+        let document_count = self.documents.len() as f64;
+        if terms.is_empty() || document_count == 0.0 {
+            return Vec::new();
+        }
+        let average_length = self.total_length as f64 / document_count;
+
+        let mut scores: HashMap<&String, f64> = HashMap::new();
+        for term in &terms {
+            let Some(documents) = self.postings.get(term) else {
+                continue;
+            };
+            let frequency_in_corpus = documents.len() as f64;
+            let idf = (1.0 + (document_count - frequency_in_corpus + 0.5) / (frequency_in_corpus + 0.5)).ln();
+
+            for (document, frequency) in documents {
+                let frequency = *frequency as f64;
+                let length = self.documents[document].length as f64;
+                let normalization = BM25_K1 * (1.0 - BM25_B + BM25_B * length / average_length);
+                *scores.entry(document).or_default() +=
+                    idf * frequency * (BM25_K1 + 1.0) / (frequency + normalization);
+            }
+        }
+
+        let mut ranked: Vec<(String, f64)> = scores
+            .into_iter()
+            .map(|(document, score)| (document.clone(), score))
+            .collect();
+        ranked.sort_by(|(a_name, a), (b_name, b)| b.total_cmp(a).then_with(|| a_name.cmp(b_name)));
+        ranked
+    }
+}
+
+/// Excerpt of `text` around the first word matching the query, with every matching
+/// word wrapped in `**` (Markdown bold).
+pub fn snippet(text: &str, query: &str) -> Option<String> {
+    let terms: HashSet<String> = analyze(query).into_iter().collect();
+    let matches: Vec<(usize, usize)> = words(text)
+        .filter(|(_, word)| analyze_word(word).is_some_and(|term| terms.contains(&term)))
+        .map(|(offset, word)| (offset, offset + word.len()))
+        .collect();
+    let (first_start, _) = *matches.first()?;
+
+    let start = floor_char_boundary(text, first_start.saturating_sub(SNIPPET_CONTEXT_CHARS));
+    let end = floor_char_boundary(text, (start + SNIPPET_MAX_CHARS).min(text.len()));
+
+    let mut excerpt = String::new();
+    if start > 0 {
+        excerpt.push('…');
+    }
+    let mut cursor = start;
+    for (match_start, match_end) in matches {
+        if match_start < start || match_end > end {
+            continue;
+        }
+        excerpt.push_str(&text[cursor..match_start]);
+        excerpt.push_str("**");
+        excerpt.push_str(&text[match_start..match_end]);
+        excerpt.push_str("**");
+        cursor = match_end;
+    }
+    excerpt.push_str(&text[cursor..end]);
+    if end < text.len() {
+        excerpt.push('…');
+    }
+
+    Some(excerpt.split_whitespace().collect::<Vec<_>>().join(" "))
+}
+
+fn floor_char_boundary(text: &str, mut index: usize) -> usize {
+    while !text.is_char_boundary(index) {
+        index -= 1;
+    }
+    index
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn index_of(documents: &[(&str, &str)]) -> TextIndex {
+        let mut index = TextIndex::default();
+        for (name, text) in documents {
+            index.index_document(name, text);
+        }
+        index
+    }
+
+    fn ranking(index: &TextIndex, query: &str) -> Vec<String> {
+        index.search(query).into_iter().map(|(document, _)| document).collect()
+    }
+
+    #[test]
+    fn common_suffixes_are_stemmed() {
+        for word in ["swap", "swaps", "swapped", "swapping"] {
+            assert_eq!(stem(word), "swap", "{}", word);
+        }
+        assert_eq!(stem("libraries"), "library");
+        assert_eq!(stem("classes"), "class");
+        assert_eq!(stem("quickly"), "quick");
+        assert_eq!(stem("installed"), "install");
+        // Too short or not really a suffix
+        assert_eq!(stem("status"), "status");
+        assert_eq!(stem("red"), "red");
+        assert_eq!(stem("bring"), "bring");
+        assert_eq!(stem("café"), "café");
+    }
+
+    #[test]
+    fn analysis_drops_stop_words_and_single_letters() {
+        assert_eq!(analyze("Swap the Tokens on a DEX, x"), vec!["swap", "token", "dex"]);
+    }
+
+    #[test]
+    fn frequent_terms_in_short_documents_rank_first() {
+        let index = index_of(&[
+            ("long", "token swap dex with a long description of everything else it offers to traders"),
+            ("dense", "swap swap swap tokens quickly"),
+            ("short", "token swap dex"),
+            ("unrelated", "nft marketplace"),
+        ]);
+
+        assert_eq!(ranking(&index, "swapping"), vec!["dense", "short", "long"]);
+        // The rarer term counts for more than the common one
+        let index = index_of(&[("both", "swap bridge"), ("common", "swap swap"), ("other", "swap")]);
+        assert_eq!(ranking(&index, "bridge swap")[0], "both");
+        assert!(ranking(&index, "the of").is_empty());
+    }
+
+    #[test]
+    fn equal_scores_are_ordered_by_name() {
+        let index = index_of(&[("bravo", "oracle feed"), ("alpha", "oracle feed")]);
+        assert_eq!(ranking(&index, "oracle"), vec!["alpha", "bravo"]);
+    }
+
+    #[test]
+    fn reindexing_replaces_the_previous_text() {
+        let mut index = index_of(&[("alpha", "token swap"), ("bravo", "token bridge")]);
+        assert_eq!(index.documents_matching_all("token swap"), HashSet::from(["alpha".to_string()]));
+
+        index.index_document("alpha", "lending pool");
+        assert!(index.documents_matching_all("swap").is_empty());
+        assert_eq!(ranking(&index, "lend"), vec!["alpha"]);
+
+        index.index_document("alpha", "");
+        index.remove_document("bravo");
+        assert!(index.postings.is_empty() && index.documents.is_empty());
+        assert_eq!(index.total_length, 0);
+    }
+
+    #[test]
+    fn snippets_surround_the_first_match() {
+        let text = "Trade any token. Swapping is instant and cheap.";
+        assert_eq!(snippet(text, "swap").as_deref(), Some("Trade any token. **Swapping** is instant and cheap."));
+        assert_eq!(snippet(text, "lending"), None);
+
+        let filler = "filler ".repeat(20);
+        let text = format!("{}Token swaps settle at once. {}", filler, "more ".repeat(40));
+        let excerpt = snippet(&text, "token swap").unwrap();
+        assert!(excerpt.starts_with('…') && excerpt.ends_with('…'));
+        assert!(excerpt.contains("**Token** **swaps** settle"));
+        // The cut keeps SNIPPET_CONTEXT_CHARS of text before the match
+        let before = &excerpt['…'.len_utf8()..excerpt.find("**Token").unwrap()];
+        assert_eq!(before.len(), SNIPPET_CONTEXT_CHARS);
+        assert!(excerpt.replace("**", "").chars().count() <= SNIPPET_MAX_CHARS + 2);
+    }
+
+    #[test]
+    fn snippets_never_cut_inside_a_character() {
+        let text = format!("{}ab swap", "é".repeat(35));
+        let excerpt = snippet(&text, "swap").unwrap();
+        assert!(excerpt.starts_with('…'));
+        assert!(excerpt.ends_with("ab **swap**"));
+    }
+}
@@ -153,7 +153,7 @@ RESULT5=$(dfx canister call registry admin_create_domain_with_address '(record {
     recipient_address = "unauthorized123";
 })' 2>&1)
 
-if echo "$RESULT5" | grep -q "not authorized for the current season"; then
+if echo "$RESULT5" | grep -q "AddressNotAuthorized"; then
     echo -e "${GREEN}✅ Domain creation correctly rejected for unauthorized address${NC}"
     echo "Result: $RESULT5"
 else
@@ -256,7 +256,7 @@ RESULT8=$(dfx canister call registry admin_create_domain_with_address '(record {
     recipient_address = "alice123";
 })' 2>&1)
 
-if echo "$RESULT8" | grep -q "season is full"; then
+if echo "$RESULT8" | grep -q "SeasonFull"; then
     echo -e "${GREEN}✅ Domain creation correctly rejected (season full)${NC}"
     echo "Result: $RESULT8"
 else
@@ -289,7 +289,7 @@ echo "=============================================="
 echo -e "${YELLOW}Attempting to add address to completed season...${NC}"
 RESULT10=$(dfx canister call registry admin_add_address_to_season "(${SEASON_ID}, \"newaddress\")" 2>&1)
 
-if echo "$RESULT10" | grep -q "Cannot add address to completed season"; then
+if echo "$RESULT10" | grep -q "SeasonNotActive"; then
     echo -e "${GREEN}✅ Address addition correctly rejected (season completed)${NC}"
     echo "Result: $RESULT10"
 else
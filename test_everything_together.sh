@@ -22,6 +22,8 @@ BLUE='\033[0;34m'
 PURPLE='\033[0;35m'
 NC='\033[0m' # No Color
 
+source "$(dirname "$0")/registration_helpers.sh"
+
 # Test counters
 TESTS_PASSED=0
 TESTS_FAILED=0
@@ -100,7 +102,7 @@ SEASON1_ID=1
 # Test 2: Try to create second season (should fail)
 run_test "Prevent multiple active seasons" \
     "dfx canister call registry create_registration_season '(record { min_letters = 1; max_letters = opt 3; total_allowed = 10; price_icp = 100; })'" \
-    "already an active season"
+    "ActiveSeasonExists"
 
 # Test 3: Add authorized addresses
 echo -e "${BLUE}🧪 Adding authorized addresses to season${NC}"
@@ -132,13 +134,13 @@ echo "========================================================"
 # Test 6: Regular user registration (should work)
 dfx identity use test-user-1
 run_test "User1 registers first domain" \
-    "dfx canister call registry register_domain '(record { domain_name = \"user1domain\"; administrator = principal \"${USER1_PRINCIPAL}\"; operator = principal \"${USER1_PRINCIPAL}\"; payment_block = 1; })'" \
+    "register_committed user1domain \"${USER1_PRINCIPAL}\" 1" \
     "successfully"
 
 # Test 7: Same user tries second domain (should fail)
 run_test "User1 tries second domain (should fail)" \
-    "dfx canister call registry register_domain '(record { domain_name = \"user1second\"; administrator = principal \"${USER1_PRINCIPAL}\"; operator = principal \"${USER1_PRINCIPAL}\"; payment_block = 2; })'" \
-    "already owns domain"
+    "register_committed user1second \"${USER1_PRINCIPAL}\" 2" \
+    "WalletAlreadyOwns"
 
 # Test 8: Check wallet domain query
 dfx identity use default
@@ -149,7 +151,7 @@ run_test "Query User1's domain" \
 # Test 9: Different user can register
 dfx identity use test-user-2
 run_test "User2 registers domain" \
-    "dfx canister call registry register_domain '(record { domain_name = \"user2domain\"; administrator = principal \"${USER2_PRINCIPAL}\"; operator = principal \"${USER2_PRINCIPAL}\"; payment_block = 3; })'" \
+    "register_committed user2domain \"${USER2_PRINCIPAL}\" 3" \
     "successfully"
 
 dfx identity use default
@@ -166,12 +168,12 @@ run_test "Admin creates domain with authorized address" \
 # Test 11: Admin tries unauthorized address (should fail)
 run_test "Admin tries unauthorized address" \
     "dfx canister call registry admin_create_domain_with_address '(record { domain_name = \"unauthorized\"; recipient = principal \"${USER4_PRINCIPAL}\"; administrator = principal \"${USER4_PRINCIPAL}\"; operator = principal \"${USER4_PRINCIPAL}\"; recipient_address = \"notauthorized\"; })'" \
-    "not authorized for the current season"
+    "AddressNotAuthorized"
 
 # Test 12: Admin tries to create for user who already has domain (should fail)
 run_test "Admin tries to create for user with existing domain" \
     "dfx canister call registry admin_create_domain_with_address '(record { domain_name = \"duplicate\"; recipient = principal \"${USER1_PRINCIPAL}\"; administrator = principal \"${USER1_PRINCIPAL}\"; operator = principal \"${USER1_PRINCIPAL}\"; recipient_address = \"alice123\"; })'" \
-    "already owns domain"
+    "WalletAlreadyOwns"
 
 echo ""
 echo "🎁 PHASE 4: ADMIN GIFTS WITH SEASON LIMITS"
@@ -190,12 +192,12 @@ run_test "Season should be full (4/4)" \
 # Test 15: Try to create more domains when season is full
 run_test "Try to register when season is full" \
     "dfx canister call registry admin_gift_domain '(record { domain_name = \"overfull\"; recipient = principal \"${USER5_PRINCIPAL}\"; administrator = principal \"${USER5_PRINCIPAL}\"; operator = principal \"${USER5_PRINCIPAL}\"; })'" \
-    "season is full"
+    "SeasonFull"
 
 dfx identity use test-user-5
 run_test "Regular user tries when season is full" \
-    "dfx canister call registry register_domain '(record { domain_name = \"regularfull\"; administrator = principal \"${USER5_PRINCIPAL}\"; operator = principal \"${USER5_PRINCIPAL}\"; payment_block = 4; })'" \
-    "No available registration season"
+    "register_committed regularfull \"${USER5_PRINCIPAL}\" 4" \
+    "NoSeason"
 
 dfx identity use default
 
@@ -209,9 +211,9 @@ run_test "Season auto-completed when full" \
     "Completed"
 
 # Test 17: Try to add address to completed season
-run_test "Cannot add address to completed season" \
+run_test "SeasonNotActive" \
     "dfx canister call registry admin_add_address_to_season '(1, \"newaddress\")'" \
-    "Cannot add address to completed season"
+    "SeasonNotActive"
 
 # Test 18: Create new season after previous completed
 run_test "Create new season after previous completed" \
@@ -235,7 +237,7 @@ run_test "Query latest season (should be season 2)" \
 # Test 21: New registrations work in new season
 dfx identity use test-user-5
 run_test "User5 registers in new season" \
-    "dfx canister call registry register_domain '(record { domain_name = \"newseason1\"; administrator = principal \"${USER5_PRINCIPAL}\"; operator = principal \"${USER5_PRINCIPAL}\"; payment_block = 5; })'" \
+    "register_committed newseason1 \"${USER5_PRINCIPAL}\" 5" \
     "successfully"
 
 dfx identity use default
@@ -261,7 +263,7 @@ run_test "New owner has domain after transfer" \
 # Test 24: Try to transfer to user who already has domain
 run_test "Cannot transfer to user with existing domain" \
     "dfx canister call registry transfer_domain_ownership '(\"user2domain\", principal \"${USER6_PRINCIPAL}\")'" \
-    "already owns domain"
+    "WalletAlreadyOwns"
 
 echo ""
 echo "📊 PHASE 7: QUERY FUNCTIONS AND FINAL VERIFICATION"
@@ -12,6 +12,8 @@ GREEN='\033[0;32m'
 YELLOW='\033[1;33m'
 NC='\033[0m' # No Color
 
+source "$(dirname "$0")/registration_helpers.sh"
+
 # Test setup
 echo -e "${YELLOW}Setting up test environment...${NC}"
 
@@ -85,7 +87,7 @@ RESULT2=$(dfx canister call registry register_domain '(record {
     payment_block = 2;
 })' 2>&1)
 
-if echo "$RESULT2" | grep -q "already owns domain"; then
+if echo "$RESULT2" | grep -q "WalletAlreadyOwns"; then
     echo -e "${GREEN}✅ Second domain registration correctly rejected${NC}"
     echo "Result: $RESULT2"
 else
@@ -121,12 +123,7 @@ USER_PRINCIPAL=$(dfx identity get-principal)
 echo -e "${YELLOW}User principal: ${USER_PRINCIPAL}${NC}"
 
 echo -e "${YELLOW}Registering 'userdomain' with second identity...${NC}"
-RESULT4=$(dfx canister call registry register_domain '(record {
-    domain_name = "userdomain";
-    administrator = principal "'${USER_PRINCIPAL}'";
-    operator = principal "'${USER_PRINCIPAL}'";
-    payment_block = 3;
-})' 2>&1)
+RESULT4=$(register_committed userdomain "${USER_PRINCIPAL}" 3 2>&1)
 
 if echo "$RESULT4" | grep -q "successfully"; then
     echo -e "${GREEN}✅ Second identity registration succeeded${NC}"
@@ -143,9 +140,12 @@ echo "==============================="
 # Test 5: Test domain transfer (should fail - user already has domain)
 dfx identity use default
 echo -e "${YELLOW}Attempting to transfer 'testdomain' to user who already has domain...${NC}"
-RESULT5=$(dfx canister call registry transfer_domain_ownership "(\"testdomain\", principal \"${USER_PRINCIPAL}\")" 2>&1)
+dfx canister call registry propose_domain_transfer "(\"testdomain\", principal \"${USER_PRINCIPAL}\", null)" >/dev/null 2>&1
+dfx identity use test-user
+RESULT5=$(dfx canister call registry accept_domain_transfer "(\"testdomain\")" 2>&1)
+dfx identity use default
 
-if echo "$RESULT5" | grep -q "already owns domain"; then
+if echo "$RESULT5" | grep -q "WalletAlreadyOwns"; then
     echo -e "${GREEN}✅ Transfer correctly rejected (user already has domain)${NC}"
     echo "Result: $RESULT5"
 else
@@ -164,10 +164,13 @@ dfx identity use test-user-2
 USER2_PRINCIPAL=$(dfx identity get-principal)
 echo -e "${YELLOW}User2 principal: ${USER2_PRINCIPAL}${NC}"
 
-# Switch back to admin for transfer
+# Switch back to admin to propose the transfer
 dfx identity use default
 echo -e "${YELLOW}Transferring 'testdomain' to user2 (who has no domain)...${NC}"
-RESULT6=$(dfx canister call registry transfer_domain_ownership "(\"testdomain\", principal \"${USER2_PRINCIPAL}\")" 2>&1)
+dfx canister call registry propose_domain_transfer "(\"testdomain\", principal \"${USER2_PRINCIPAL}\", null)" >/dev/null 2>&1
+dfx identity use test-user-2
+RESULT6=$(dfx canister call registry accept_domain_transfer "(\"testdomain\")" 2>&1)
+dfx identity use default
 
 if echo "$RESULT6" | grep -q "Ok"; then
     echo -e "${GREEN}✅ Transfer succeeded${NC}"
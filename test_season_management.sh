@@ -12,6 +12,8 @@ GREEN='\033[0;32m'
 YELLOW='\033[1;33m'
 NC='\033[0m' # No Color
 
+source "$(dirname "$0")/registration_helpers.sh"
+
 # Check if dfx is running
 if ! dfx ping > /dev/null 2>&1; then
     echo -e "${RED}❌ dfx is not running. Please start dfx with: dfx start --background${NC}"
@@ -75,7 +77,7 @@ RESULT2=$(dfx canister call registry create_registration_season '(record {
     price_icp = 100;
 })' 2>&1)
 
-if echo "$RESULT2" | grep -q "already an active season"; then
+if echo "$RESULT2" | grep -q "ActiveSeasonExists"; then
     echo -e "${GREEN}✅ Second season creation correctly rejected${NC}"
     echo "Result: $RESULT2"
 else
@@ -174,12 +176,7 @@ fi
 echo -e "${YELLOW}Registering second domain 'domain2' with second identity...${NC}"
 dfx identity use test-user
 USER_PRINCIPAL=$(dfx identity get-principal)
-RESULT5B=$(dfx canister call registry register_domain '(record {
-    domain_name = "domain2";
-    administrator = principal "'${USER_PRINCIPAL}'";
-    operator = principal "'${USER_PRINCIPAL}'";
-    payment_block = 2;
-})' 2>&1)
+RESULT5B=$(register_committed domain2 "${USER_PRINCIPAL}" 2 2>&1)
 
 if echo "$RESULT5B" | grep -q "successfully"; then
     echo -e "${GREEN}✅ Second domain registered${NC}"
@@ -219,14 +216,9 @@ dfx identity use test-user-3
 USER3_PRINCIPAL=$(dfx identity get-principal)
 
 echo -e "${YELLOW}Attempting to register domain in completed season...${NC}"
-RESULT7=$(dfx canister call registry register_domain '(record {
-    domain_name = "domain3";
-    administrator = principal "'${USER3_PRINCIPAL}'";
-    operator = principal "'${USER3_PRINCIPAL}'";
-    payment_block = 3;
-})' 2>&1)
+RESULT7=$(register_committed domain3 "${USER3_PRINCIPAL}" 3 2>&1)
 
-if echo "$RESULT7" | grep -q "No available registration season"; then
+if echo "$RESULT7" | grep -q "NoSeason"; then
     echo -e "${GREEN}✅ Registration correctly rejected (no active season)${NC}"
     echo "Result: $RESULT7"
 else
@@ -1,200 +0,0 @@
-// Integration tests for the registry canister
-// These tests demonstrate the expected behavior of the season-based registration system
-
-#[test]
-fn test_season_registration_flow() {
-    // This test demonstrates the expected flow:
-    // 1. Admin creates registration seasons with different parameters
-    // 2. Users register domains based on available seasons
-    // 3. Season capacity is enforced
-    // 4. Pricing is determined by the applicable season
-    
-    /*
-    Expected behavior:
-    
-    1. Admin creates a season for 1-2 letter domains:
-       - min_letters: 1
-       - max_letters: 2
-       - total_allowed: 10
-       - price_icp: 100
-    
-    2. Admin creates a season for 3-5 letter domains:
-       - min_letters: 3
-       - max_letters: 5
-       - total_allowed: 100
-       - price_icp: 20
-    
-    3. Admin creates another season for 3-5 letter domains (cheaper):
-       - min_letters: 3
-       - max_letters: 5
-       - total_allowed: 50
-       - price_icp: 15
-    
-    4. User tries to register "test" (4 letters):
-       - System finds the cheaper season (15 ICP)
-       - Registration succeeds if season has capacity
-       - Season's registered_count increments
-    
-    5. Once a season reaches capacity:
-       - New registrations fall back to other applicable seasons
-       - If no seasons available, registration fails
-    
-    6. Season stats can be queried:
-       - season_number: unique ID
-       - names_available: total capacity
-       - names_taken: current registrations
-       - price_icp: price per domain
-    */
-    
-    assert!(true); // Placeholder for actual integration test
-}
-
-#[test]
-fn test_season_validation_rules() {
-    // Test validation rules for creating seasons
-    /*
-    Expected validation:
-    - min_letters must be 1-64
-    - max_letters must be >= min_letters and <= 64
-    - total_allowed must be > 0
-    - price_icp must be > 0
-    - Only admins can create seasons
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_season_deactivation() {
-    // Test season deactivation behavior
-    /*
-    Expected behavior:
-    - Admin can deactivate a season
-    - Deactivated seasons are not considered for new registrations
-    - Existing domains registered through deactivated seasons are unaffected
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_admin_free_registration() {
-    // Test that admins can register domains for free
-    /*
-    Expected behavior:
-    - Admins bypass season system
-    - No fee is charged
-    - No season capacity is consumed
-    - Domain record shows was_gifted = true
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_find_cheapest_applicable_season() {
-    // Test that system finds the cheapest applicable season
-    /*
-    Expected behavior:
-    - When multiple seasons match domain length
-    - System selects the one with lowest price_icp
-    - Only considers active seasons with available capacity
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_season_stats_query() {
-    // Test season statistics functionality
-    /*
-    Expected behavior:
-    - get_season_stats(season_id) returns:
-      - season_number: the season ID
-      - names_available: total slots in season
-      - names_taken: number of domains registered
-      - price_icp: price per domain
-      - status: Active/Completed/Deactivated
-    - get_all_season_stats() returns stats for all seasons
-    - Stats update as domains are registered
-    - get_season_by_number(0) returns latest season
-    - get_season_by_number(N) returns specific season N
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_season_management_lifecycle() {
-    // Test complete season lifecycle management
-    /*
-    Expected behavior:
-    1. Only one active season allowed at a time
-    2. Creating season when another is active fails
-    3. Season auto-completes when limit is reached
-    4. Completed seasons cannot accept new registrations
-    5. New season can be created after previous completes
-    6. Query functions work correctly:
-       - get_current_season() returns active season
-       - get_season_by_number(0) returns latest season
-       - get_season_stats_by_number() works for all season numbers
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_one_domain_per_wallet_restriction() {
-    // Test that each wallet can only register one domain
-    /*
-    Expected behavior:
-    - First domain registration succeeds
-    - Second domain registration from same wallet fails with error
-    - Error message indicates wallet already owns a domain
-    - get_wallet_domain(wallet) returns the owned domain name
-    - Admin can still register multiple domains (bypass restriction)
-    - Transfer ownership updates wallet-to-domain mappings correctly
-    - New owner can't receive transfer if they already have a domain
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_domain_transfer_functionality() {
-    // Test domain ownership transfer
-    /*
-    Expected behavior:
-    - Only domain owner or administrator can transfer
-    - Transfer updates domain record ownership
-    - Transfer updates wallet-to-domain mappings
-    - Old owner loses their mapping
-    - New owner gets the mapping
-    - New owner can't receive if they already have a domain (unless admin)
-    - Transfer authorization is properly checked
-    */
-    
-    assert!(true);
-}
-
-#[test]
-fn test_admin_address_domain_creation() {
-    // Test admin domain creation with address validation
-    /*
-    Expected behavior:
-    - Admin can add authorized addresses to active seasons
-    - Admin can create domains only for authorized addresses
-    - Unauthorized addresses are rejected with clear error
-    - Season limits are enforced for admin domain creation
-    - Admin gifts also count towards season limits
-    - Address authorization queries work correctly
-    - Completed seasons don't accept new addresses
-    - Functions available:
-      - admin_add_address_to_season(season_id, address)
-      - admin_create_domain_with_address(request)
-      - get_season_addresses(season_id)
-      - is_address_authorized_for_current_season(address)
-    */
-    
-    assert!(true);
-}
\ No newline at end of file